@@ -0,0 +1,300 @@
+// ----- Audio file input -----
+//
+// Files and uploads are probed by content, with the file extension as a
+// hint, and decoded with symphonia: WAV (8-32 bit PCM, float, A-law and
+// mu-law), FLAC, MP3 and Ogg Vorbis. Symphonia has no Opus decoder, so Ogg
+// Opus packets go to libopus. The result is mono f32 at the file's rate;
+// callers resample with `resample_to_16k`.
+
+use audiopus::coder::Decoder as OpusDecoder;
+use audiopus::packet::Packet as OpusPacket;
+use audiopus::{Channels, MutSignals, SampleRate};
+use log::warn;
+use std::fs::File;
+use std::io::{Cursor, ErrorKind};
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, Packet};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Opus always decodes at 48 kHz.
+const OPUS_RATE: u32 = 48_000;
+/// Longest Opus frame (120 ms) at 48 kHz.
+const OPUS_MAX_FRAME: usize = 5_760;
+
+/// Decode an audio file to mono f32 at its native rate, stopping after
+/// `max_samples(rate)` samples.
+pub fn read_audio_file(path: &Path, max_samples: fn(u32) -> usize) -> Result<(Vec<f32>, u32), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let extension = path.extension().and_then(|e| e.to_str());
+    decode(Box::new(file), extension, max_samples).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Like `read_audio_file`, for audio already in memory (e.g. an upload).
+pub fn read_audio_bytes(data: &[u8], max_samples: fn(u32) -> usize) -> Result<(Vec<f32>, u32), String> {
+    decode(Box::new(Cursor::new(data.to_vec())), None, max_samples)
+}
+
+/// Average interleaved frames of `channels` into `out`, as live capture does.
+pub fn downmix(interleaved: &[f32], channels: usize, out: &mut Vec<f32>) {
+    let channels = channels.max(1);
+    out.extend(
+        interleaved
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+    );
+}
+
+fn decode(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+    max_samples: fn(u32) -> usize,
+) -> Result<(Vec<f32>, u32), String> {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(source, Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| match e {
+            Error::Unsupported(_) => "not a supported audio file (WAV, FLAC, MP3, Ogg Vorbis or Ogg Opus)".to_string(),
+            e => e.to_string(),
+        })?;
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("no audio track")?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let mut codec = Codec::new(&params)?;
+    let rate = match codec {
+        Codec::Opus { .. } => OPUS_RATE,
+        Codec::Symphonia(_) => params.sample_rate.ok_or("unknown sample rate")?,
+    };
+
+    let max = max_samples(rate);
+    let mut mono = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        };
+        if packet.track_id() == track_id {
+            codec.decode(&packet, &mut mono)?;
+        }
+        if mono.len() >= max {
+            warn!("Audio longer than {} s; ignoring the rest", max as u64 / rate.max(1) as u64);
+            mono.truncate(max);
+            break;
+        }
+    }
+    Ok((mono, rate))
+}
+
+enum Codec {
+    Symphonia(Box<dyn Decoder>),
+    Opus {
+        decoder: OpusDecoder,
+        channels: usize,
+        /// Decoder delay (pre-skip) still to drop, in frames.
+        skip: usize,
+        buf: Vec<f32>,
+    },
+}
+
+impl Codec {
+    fn new(params: &CodecParameters) -> Result<Self, String> {
+        if params.codec == CODEC_TYPE_OPUS {
+            let channels = params.channels.map_or(1, |c| c.count());
+            let layout = match channels {
+                1 => Channels::Mono,
+                2 => Channels::Stereo,
+                n => return Err(format!("unsupported audio: Opus with {n} channels")),
+            };
+            let decoder = OpusDecoder::new(SampleRate::Hz48000, layout).map_err(|e| format!("Opus: {e}"))?;
+            return Ok(Codec::Opus {
+                decoder,
+                channels,
+                skip: params.delay.unwrap_or(0) as usize,
+                buf: vec![0.0; OPUS_MAX_FRAME * channels],
+            });
+        }
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(|_| "unsupported codec (not PCM, FLAC, MP3, Vorbis or Opus)".to_string())?;
+        Ok(Codec::Symphonia(decoder))
+    }
+
+    /// Decode one packet and append it, downmixed, to `mono`. Corrupt
+    /// packets are skipped, like players do.
+    fn decode(&mut self, packet: &Packet, mono: &mut Vec<f32>) -> Result<(), String> {
+        match self {
+            Codec::Symphonia(decoder) => {
+                let decoded = match decoder.decode(packet) {
+                    Ok(decoded) => decoded,
+                    Err(Error::DecodeError(_)) => return Ok(()),
+                    Err(e) => return Err(e.to_string()),
+                };
+                let spec = *decoded.spec();
+                let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                samples.copy_interleaved_ref(decoded);
+                downmix(samples.samples(), spec.channels.count(), mono);
+            }
+            Codec::Opus {
+                decoder,
+                channels,
+                skip,
+                buf,
+            } => {
+                let Ok(input) = OpusPacket::try_from(&packet.data[..]) else {
+                    return Ok(());
+                };
+                let output = MutSignals::try_from(&mut buf[..]).map_err(|e| format!("Opus: {e}"))?;
+                let Ok(frames) = decoder.decode_float(Some(input), output, false) else {
+                    return Ok(());
+                };
+                let dropped = frames.min(*skip);
+                *skip -= dropped;
+                downmix(&buf[dropped * *channels..frames * *channels], *channels, mono);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// `testdata/<name>` (see `testdata/gen.py`), decoded.
+    fn fixture(name: &str) -> (Vec<f32>, u32) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name);
+        read_audio_file(&path, unlimited).unwrap()
+    }
+
+    fn unlimited(_rate: u32) -> usize {
+        usize::MAX
+    }
+
+    /// Half-scale tone with a period of `period` samples.
+    fn tone(i: usize, period: f64) -> f32 {
+        (0.5 * (2.0 * PI * i as f64 / period).sin()) as f32
+    }
+
+    /// `name` holds the 160-sample, 8 kHz fixture tone times `scale`, give
+    /// or take `tolerance`.
+    fn assert_tone(name: &str, scale: f32, tolerance: f32) {
+        let (samples, rate) = fixture(name);
+        assert_eq!((samples.len(), rate), (160, 8_000), "{name}");
+        for (i, x) in samples.iter().enumerate() {
+            let expected = scale * tone(i, 16.0);
+            assert!((x - expected).abs() <= tolerance, "{name}[{i}]: {x}, expected {expected}");
+        }
+    }
+
+    fn energy(samples: &[f32]) -> f64 {
+        samples.iter().map(|&x| f64::from(x) * f64::from(x)).sum()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= tolerance, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn pcm_wav_decodes_to_the_tone() {
+        assert_tone("tone-u8.wav", 1.0, 1.0 / 128.0);
+        // The right channel is silent, so the mixdown is at half level.
+        assert_tone("tone-i16-stereo.wav", 0.5, 1.0 / 32_768.0);
+        assert_tone("tone-i24.wav", 1.0, 1e-6);
+        assert_tone("tone-i32.wav", 1.0, 1e-6);
+        assert_tone("tone-f32.wav", 1.0, 1e-7);
+    }
+
+    #[test]
+    fn g711_wav_decodes_to_the_tone() {
+        assert_tone("tone-alaw.wav", 1.0, 1.0 / 32.0);
+        assert_tone("tone-mulaw.wav", 1.0, 1.0 / 32.0);
+        let (alaw, _) = fixture("tone-alaw.wav");
+        assert_eq!(alaw[..5], [8.0 / 32_768.0, 0.19140625, 0.3515625, 0.4609375, 0.515625]);
+        let (mulaw, _) = fixture("tone-mulaw.wav");
+        assert_eq!(mulaw[..5], [0.0, 6_396.0 / 32_768.0, 11_388.0 / 32_768.0, 14_972.0 / 32_768.0, 16_764.0 / 32_768.0]);
+    }
+
+    #[test]
+    fn flac_decodes_to_the_tone() {
+        assert_tone("tone.flac", 1.0, 1.0 / 32_768.0);
+    }
+
+    #[test]
+    fn mp3_matches_its_golden_output() {
+        let (samples, rate) = fixture("tone.mp3");
+        // Four frames of 1152 samples; no gapless trimming.
+        assert_eq!((samples.len(), rate), (4_608, 32_000));
+        assert!((energy(&samples) - 128.4056).abs() < 0.01, "{}", energy(&samples));
+        assert_close(&samples[1152..1156], &[0.180_836_28, 0.179_093_58, 0.177_249_42, 0.175_283_67], 1e-4);
+    }
+
+    #[test]
+    fn vorbis_matches_its_golden_output() {
+        let (samples, rate) = fixture("tone.ogg");
+        // Six 256-sample blocks; the first only primes the overlap.
+        assert_eq!((samples.len(), rate), (640, 8_000));
+        assert!((energy(&samples) - 0.352_598).abs() < 1e-4, "{}", energy(&samples));
+        assert_close(&samples[..4], &[-0.014_299_161, -0.011_884_351, -0.007_182_984, -0.002_089_76], 1e-5);
+    }
+
+    #[test]
+    fn opus_drops_the_pre_skip() {
+        let (samples, rate) = fixture("tone.opus");
+        // Six 20 ms frames less the 312-sample pre-skip.
+        assert_eq!((samples.len(), rate), (6 * 960 - 312, 48_000));
+        // Lined up with the encoder input from the first sample.
+        for (i, x) in samples.iter().enumerate() {
+            assert!((x - tone(i, 96.0)).abs() < 0.05, "[{i}]: {x}");
+        }
+    }
+
+    #[test]
+    fn bytes_decode_like_files() {
+        for name in ["tone-i24.wav", "tone.flac", "tone.mp3", "tone.ogg", "tone.opus"] {
+            let data = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name)).unwrap();
+            assert_eq!(read_audio_bytes(&data, unlimited).unwrap(), fixture(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn decoding_stops_at_max_samples() {
+        for name in ["tone-i16-stereo.wav", "tone.flac", "tone.mp3", "tone.ogg", "tone.opus"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name);
+            let (full, rate) = fixture(name);
+            let (head, head_rate) = read_audio_file(&path, |rate| rate as usize / 100).unwrap();
+            assert_eq!(head_rate, rate, "{name}");
+            assert_eq!(head, full[..rate as usize / 100], "{name}");
+        }
+    }
+
+    #[test]
+    fn unsupported_input_is_named() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("adpcm.wav");
+        assert_eq!(
+            read_audio_file(&path, unlimited).unwrap_err(),
+            format!("{}: unsupported codec (not PCM, FLAC, MP3, Vorbis or Opus)", path.display())
+        );
+        assert_eq!(
+            read_audio_bytes(b"plain text, not audio", unlimited).unwrap_err(),
+            "not a supported audio file (WAV, FLAC, MP3, Ogg Vorbis or Ogg Opus)"
+        );
+    }
+}
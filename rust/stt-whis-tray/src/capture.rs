@@ -0,0 +1,436 @@
+// ----- Audio capture -----
+//
+// Input streams in any sample format cpal offers, converted to f32 with
+// cpal's exact scaling: signed integers divide by 2^(bits-1), unsigned ones
+// are centred on their midpoint first, and f64 is narrowed. 24-bit
+// interfaces arrive as I32.
+//
+// Devices are asked for 16 kHz mono when they offer it, so that little or
+// no downmixing and resampling is left to do in software.
+//
+// With `capture.privacy_mode` off, the microphone stays open between
+// dictations and recent audio is kept in a ring. The next recording starts
+// `capture.pre_roll_ms` before the hotkey went down, so words spoken just
+// before or at the press are not clipped. Nothing in the ring is stored or
+// decoded unless a recording starts.
+
+use crate::audio_file::downmix;
+use crate::push_samples;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    Device, FromSample, Host, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+};
+use log::{info, warn};
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+
+const DEFAULT_PRE_ROLL_MS: u32 = 500;
+pub const MAX_PRE_ROLL_MS: u32 = 5_000;
+/// Extra ring space for the audio between the hotkey press and the
+/// recording starting.
+const START_SLACK_MS: u32 = 1_000;
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CaptureConfig {
+    /// Open the microphone only while recording. Turn off to keep it open
+    /// and start each recording with `pre_roll_ms` of earlier audio.
+    #[serde(default = "default_true")]
+    pub privacy_mode: bool,
+    /// Audio from before the hotkey kept for each recording, in milliseconds
+    /// (at most 5000). Only used with `privacy_mode` off.
+    #[serde(default = "default_pre_roll_ms")]
+    pub pre_roll_ms: u32,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            privacy_mode: true,
+            pre_roll_ms: DEFAULT_PRE_ROLL_MS,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_pre_roll_ms() -> u32 {
+    DEFAULT_PRE_ROLL_MS
+}
+
+impl CaptureConfig {
+    /// Pre-roll in effect; 0 when the microphone must close between recordings.
+    pub fn pre_roll_ms(&self) -> u32 {
+        if self.privacy_mode {
+            0
+        } else {
+            self.pre_roll_ms.min(MAX_PRE_ROLL_MS)
+        }
+    }
+}
+
+/// An open input stream. Audio goes to the recording buffer while
+/// recording, and otherwise to the pre-roll ring.
+pub struct Mic {
+    /// Keeps the source (a cpal stream) running.
+    _source: Box<dyn Any>,
+    pub sample_rate: u32,
+    pre_roll_ms: u32,
+    state: Arc<Mutex<MicState>>,
+}
+
+struct MicState {
+    recording: bool,
+    /// Mono samples from before the recording, at most `ring_len`.
+    ring: VecDeque<f32>,
+    ring_len: usize,
+}
+
+impl Mic {
+    /// Open the default input device. Recorded audio is appended to `audio`;
+    /// `pre_roll_ms` of audio is kept while not recording.
+    pub fn open(
+        host: &Host,
+        audio: Arc<Mutex<Vec<f32>>>,
+        pre_roll_ms: u32,
+        on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+    ) -> Result<Self, String> {
+        let device = host.default_input_device().ok_or("No input device available")?;
+        let supported = input_config(&device)?;
+        let sample_rate = supported.sample_rate().0;
+        let channels = supported.channels();
+        info!(
+            "Capturing {} Hz, {} ch, {}",
+            sample_rate,
+            channels,
+            supported.sample_format()
+        );
+        Self::with_source(sample_rate, channels, audio, pre_roll_ms, |on_data| {
+            let stream = build_input_stream(
+                &device,
+                &supported.config(),
+                supported.sample_format(),
+                on_data,
+                on_error,
+            )?;
+            stream.play().map_err(|e| format!("Failed to start capture: {e}"))?;
+            Ok(stream)
+        })
+    }
+
+    /// A mic fed by whatever `start` sets up. It is handed the callback for
+    /// interleaved f32 blocks of `channels` at `sample_rate`, and returns a
+    /// value that keeps the source running until dropped.
+    pub fn with_source<S: 'static>(
+        sample_rate: u32,
+        channels: u16,
+        audio: Arc<Mutex<Vec<f32>>>,
+        pre_roll_ms: u32,
+        start: impl FnOnce(Box<dyn FnMut(&[f32]) + Send>) -> Result<S, String>,
+    ) -> Result<Self, String> {
+        let state = Arc::new(Mutex::new(MicState {
+            recording: false,
+            ring: VecDeque::new(),
+            ring_len: (sample_rate as u64 * (pre_roll_ms + START_SLACK_MS) as u64 / 1000) as usize,
+        }));
+        let cb_state = state.clone();
+        let mut mono = Vec::new();
+        let source = start(Box::new(move |data| {
+            // The state lock orders this against `start_recording`, so no
+            // block lands in the ring after it was handed over.
+            let mut state = cb_state.lock();
+            if state.recording {
+                drop(state);
+                push_samples(&audio, data, channels, sample_rate);
+                return;
+            }
+            mono.clear();
+            downmix(data, channels as usize, &mut mono);
+            state.push(&mono);
+        }))?;
+        Ok(Self {
+            _source: Box::new(source),
+            sample_rate,
+            pre_roll_ms,
+            state,
+        })
+    }
+
+    pub fn pre_roll_ms(&self) -> u32 {
+        self.pre_roll_ms
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.state.lock().recording
+    }
+
+    /// Send audio to `audio` from now on, starting with `pre_roll_ms` of
+    /// audio from before `since` (the hotkey press) and all captured after it.
+    pub fn start_recording(&self, audio: &Mutex<Vec<f32>>, since: Instant) {
+        let mut state = self.state.lock();
+        // The ring ends about now.
+        let span = since.elapsed().as_secs_f64() + self.pre_roll_ms as f64 / 1000.0;
+        let keep = (span * self.sample_rate as f64) as usize;
+        let skip = state.ring.len().saturating_sub(keep);
+        audio.lock().extend(state.ring.drain(..).skip(skip));
+        state.recording = true;
+    }
+
+    /// Go back to filling the pre-roll ring.
+    pub fn stop_recording(&self) {
+        self.state.lock().recording = false;
+    }
+}
+
+impl MicState {
+    fn push(&mut self, mono: &[f32]) {
+        self.ring.extend(mono);
+        let excess = self.ring.len().saturating_sub(self.ring_len);
+        self.ring.drain(..excess);
+    }
+}
+
+/// Whether `build_input_stream` can capture `format`.
+pub fn is_supported(format: SampleFormat) -> bool {
+    use SampleFormat::*;
+    matches!(format, I8 | I16 | I32 | I64 | U8 | U16 | U32 | U64 | F32 | F64)
+}
+
+/// Rates `resample_to_16k` reduces by plain decimation, best first.
+const PREFERRED_RATES: [u32; 3] = [16_000, 32_000, 48_000];
+
+/// The input config to capture with. A supported config at 16 kHz (or a
+/// multiple of it) wins, with the fewest channels; otherwise the device's
+/// default, or, if its sample format cannot be captured, the supported
+/// config closest to it.
+pub fn input_config(device: &Device) -> Result<SupportedStreamConfig, String> {
+    let default = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to get input config: {e}"))?;
+    let ranges: Vec<SupportedStreamConfigRange> = match device.supported_input_configs() {
+        Ok(ranges) => ranges.collect(),
+        Err(e) => {
+            warn!("Failed to list input configs: {e}");
+            Vec::new()
+        }
+    };
+    choose_config(default, &ranges)
+}
+
+/// `input_config` for a device whose default config is `default` and that
+/// supports `ranges`.
+fn choose_config(
+    default: SupportedStreamConfig,
+    ranges: &[SupportedStreamConfigRange],
+) -> Result<SupportedStreamConfig, String> {
+    let supported = || ranges.iter().copied().filter(|c| is_supported(c.sample_format()));
+    let native = supported()
+        .filter_map(|c| {
+            let rank = PREFERRED_RATES
+                .iter()
+                .position(|&r| (c.min_sample_rate().0..=c.max_sample_rate().0).contains(&r))?;
+            Some((rank, c))
+        })
+        .min_by_key(|(rank, c)| (*rank, c.channels(), c.sample_format() != default.sample_format()))
+        .map(|(rank, c)| c.with_sample_rate(SampleRate(PREFERRED_RATES[rank])));
+    if let Some(config) = native {
+        return Ok(config);
+    }
+    if is_supported(default.sample_format()) {
+        return Ok(default);
+    }
+    let rate = default.sample_rate();
+    supported()
+        // Prefer the default's channel count, then float samples.
+        .min_by_key(|c| (c.channels() != default.channels(), c.sample_format() != SampleFormat::F32))
+        .map(|c| {
+            let rate = rate.clamp(c.min_sample_rate(), c.max_sample_rate());
+            c.with_sample_rate(rate)
+        })
+        .ok_or_else(|| format!("No supported input sample format (default is {})", default.sample_format()))
+}
+
+/// Start building a stream that hands `on_data` interleaved f32 samples.
+pub fn build_input_stream(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    on_data: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<Stream, String> {
+    let stream = match format {
+        SampleFormat::I8 => build::<i8>(device, config, on_data, on_error),
+        SampleFormat::I16 => build::<i16>(device, config, on_data, on_error),
+        SampleFormat::I32 => build::<i32>(device, config, on_data, on_error),
+        SampleFormat::I64 => build::<i64>(device, config, on_data, on_error),
+        SampleFormat::U8 => build::<u8>(device, config, on_data, on_error),
+        SampleFormat::U16 => build::<u16>(device, config, on_data, on_error),
+        SampleFormat::U32 => build::<u32>(device, config, on_data, on_error),
+        SampleFormat::U64 => build::<u64>(device, config, on_data, on_error),
+        SampleFormat::F32 => build::<f32>(device, config, on_data, on_error),
+        SampleFormat::F64 => build::<f64>(device, config, on_data, on_error),
+        other => return Err(format!("Unsupported input sample format: {other}")),
+    };
+    stream.map_err(|e| format!("Failed to build input stream: {e}"))
+}
+
+fn build<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut on_data: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    // Reused across callbacks.
+    let mut converted = Vec::new();
+    device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            to_f32(data, &mut converted);
+            on_data(&converted);
+        },
+        on_error,
+        None,
+    )
+}
+
+/// Replace the contents of `out` with `data` scaled to f32; f32 input is
+/// passed through as is.
+fn to_f32<T>(data: &[T], out: &mut Vec<f32>)
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    out.clear();
+    out.extend(data.iter().map(|s| s.to_sample::<f32>()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::SupportedBufferSize;
+
+    fn range(channels: u16, min: u32, max: u32, format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(channels, SampleRate(min), SampleRate(max), SupportedBufferSize::Unknown, format)
+    }
+
+    fn config(channels: u16, rate: u32, format: SampleFormat) -> SupportedStreamConfig {
+        SupportedStreamConfig::new(channels, SampleRate(rate), SupportedBufferSize::Unknown, format)
+    }
+
+    fn converted<T>(data: &[T]) -> Vec<f32>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let mut out = vec![9.0];
+        to_f32(data, &mut out);
+        out
+    }
+
+    /// A 16 kHz mic with 500 ms of pre-roll whose ring was just filled with
+    /// two seconds of audio, each sample its index.
+    fn mic_with_full_ring(audio: Arc<Mutex<Vec<f32>>>) -> Mic {
+        Mic::with_source(16_000, 1, audio, 500, |mut on_data| {
+            let samples: Vec<f32> = (0..32_000).map(|i| i as f32).collect();
+            on_data(&samples);
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn pre_roll_reaches_back_from_the_press() {
+        let audio = Arc::new(Mutex::new(Vec::new()));
+        let mic = mic_with_full_ring(audio.clone());
+        mic.start_recording(&audio, Instant::now() - std::time::Duration::from_millis(250));
+        let kept = audio.lock();
+        // 500 ms from before the press and the 250 ms since, ending now.
+        assert!((12_000..12_800).contains(&kept.len()), "{}", kept.len());
+        assert_eq!(kept.last(), Some(&31_999.0));
+
+        // A press longer ago than the ring keeps all of it (1.5 s).
+        let audio = Arc::new(Mutex::new(Vec::new()));
+        let mic = mic_with_full_ring(audio.clone());
+        mic.start_recording(&audio, Instant::now() - std::time::Duration::from_secs(5));
+        assert_eq!(audio.lock().len(), 24_000);
+    }
+
+    #[test]
+    fn every_cpal_format_is_supported() {
+        use SampleFormat::*;
+        for format in [I8, I16, I32, I64, U8, U16, U32, U64, F32, F64] {
+            assert!(is_supported(format), "{format}");
+        }
+    }
+
+    #[test]
+    fn signed_samples_divide_by_half_their_range() {
+        assert_eq!(converted(&[i8::MIN, 0, i8::MAX]), [-1.0, 0.0, 127.0 / 128.0]);
+        assert_eq!(converted(&[i16::MIN, 0, i16::MAX]), [-1.0, 0.0, 32_767.0 / 32_768.0]);
+        assert_eq!(converted(&[i16::MIN / 2, 1]), [-0.5, 1.0 / 32_768.0]);
+        // Nearest f32 to (2^31 - 1) / 2^31 is 1.0.
+        assert_eq!(converted(&[i32::MIN, 0, i32::MAX, 1 << 30]), [-1.0, 0.0, 1.0, 0.5]);
+        assert_eq!(converted(&[i64::MIN, 0, i64::MAX, -(1 << 62)]), [-1.0, 0.0, 1.0, -0.5]);
+    }
+
+    #[test]
+    fn unsigned_samples_are_centred_on_their_midpoint() {
+        assert_eq!(converted(&[0u8, 128, 255]), [-1.0, 0.0, 127.0 / 128.0]);
+        assert_eq!(converted(&[0u16, 32_768, 49_152]), [-1.0, 0.0, 0.5]);
+        assert_eq!(converted(&[0u32, 1 << 31, 1 << 30]), [-1.0, 0.0, -0.5]);
+        assert_eq!(converted(&[0u64, 1 << 63, u64::MAX]), [-1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn floats_pass_through_or_narrow() {
+        assert_eq!(converted(&[-1.0f32, 0.25, 1.5]), [-1.0, 0.25, 1.5]);
+        assert_eq!(converted(&[0.1f64, -0.75, 1e-40]), [0.1f32, -0.75, 1e-40f64 as f32]);
+    }
+
+    #[test]
+    fn sixteen_khz_mono_wins() {
+        use SampleFormat::*;
+        let default = config(2, 48_000, F32);
+        let ranges = [range(2, 48_000, 48_000, F32), range(1, 16_000, 16_000, I16), range(2, 16_000, 16_000, F32)];
+        assert_eq!(choose_config(default, &ranges), Ok(config(1, 16_000, I16)));
+    }
+
+    #[test]
+    fn wide_ranges_are_pinned_to_16_khz_in_the_default_format() {
+        use SampleFormat::*;
+        let default = config(2, 44_100, F32);
+        let ranges = [range(2, 8_000, 96_000, I16), range(1, 8_000, 96_000, I16), range(1, 8_000, 96_000, F32)];
+        assert_eq!(choose_config(default, &ranges), Ok(config(1, 16_000, F32)));
+    }
+
+    #[test]
+    fn multiples_of_16_khz_come_next() {
+        use SampleFormat::*;
+        let default = config(1, 44_100, I16);
+        let ranges = [range(1, 44_100, 44_100, I16), range(2, 48_000, 48_000, F32), range(1, 32_000, 32_000, I32)];
+        assert_eq!(choose_config(default.clone(), &ranges), Ok(config(1, 32_000, I32)));
+        // 48 kHz beats the default's 44.1 kHz, even with more channels.
+        assert_eq!(choose_config(default, &ranges[..2]), Ok(config(2, 48_000, F32)));
+    }
+
+    #[test]
+    fn other_rates_keep_the_default() {
+        use SampleFormat::*;
+        let default = config(2, 44_100, I16);
+        let ranges = [range(2, 44_100, 44_100, I16), range(1, 22_050, 22_050, F32)];
+        assert_eq!(choose_config(default.clone(), &ranges), Ok(default.clone()));
+        // Listing the configs failed.
+        assert_eq!(choose_config(default.clone(), &[]), Ok(default));
+    }
+}
@@ -0,0 +1,435 @@
+// ----- Command line -----
+//
+// Parsing only; `main` and `commands` act on the result. Without a
+// subcommand the app runs in tray mode, same as `run`.
+
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "stt-whis-tray", version, about = "Hold-to-talk dictation with whisper.cpp")]
+pub struct Cli {
+    /// Config file to use instead of the default location.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Final-pass model for this run: a path, a file in the models
+    /// directory, or a short name like `small.en`. Not saved to the config.
+    #[arg(long, global = true, value_name = "NAME|PATH")]
+    pub model: Option<String>,
+    /// Spoken language for this run (e.g. `en`), or `auto` to detect it.
+    #[arg(long, global = true, value_name = "CODE")]
+    pub language: Option<String>,
+    /// off, error, warn, info, debug or trace.
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    #[command(flatten)]
+    pub run: RunArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the dictation app (the default).
+    Run(RunArgs),
+    /// Run the app and also serve OpenAI-style transcription over HTTP.
+    Serve(ServeArgs),
+    /// Transcribe audio files and print the text.
+    Transcribe(TranscribeArgs),
+    /// List audio input devices.
+    Devices,
+    /// List installed models.
+    Models,
+    /// Inspect the config file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Search, export or purge the dictation history.
+    #[command(subcommand)]
+    History(HistoryCommand),
+}
+
+#[derive(Debug, Default, Clone, Args)]
+pub struct RunArgs {
+    /// Don't show the recording overlay.
+    #[arg(long)]
+    pub no_overlay: bool,
+    /// Don't create the tray icon.
+    #[arg(long)]
+    pub no_tray: bool,
+    /// No GUI at all: hotkey, speech runtime and outputs only.
+    #[arg(long)]
+    pub headless: bool,
+    /// Start recording (forwarded to the running instance if there is one).
+    #[arg(long)]
+    pub start: bool,
+    /// Stop recording and transcribe.
+    #[arg(long)]
+    pub stop: bool,
+    /// Start if idle, stop if recording.
+    #[arg(long)]
+    pub toggle: bool,
+    /// Stop recording and discard the audio.
+    #[arg(long)]
+    pub cancel: bool,
+    /// Type the last transcript again.
+    #[arg(long)]
+    pub reinject: bool,
+    /// Decode the last recording with the re-transcribe model and replace its text.
+    #[arg(long)]
+    pub retranscribe: bool,
+    /// Re-read the config file.
+    #[arg(long)]
+    pub reload: bool,
+}
+
+impl RunArgs {
+    /// The action flags as control API methods, in a fixed order.
+    pub fn actions(&self) -> Vec<&'static str> {
+        [
+            (self.start, "start"),
+            (self.stop, "stop"),
+            (self.toggle, "toggle"),
+            (self.cancel, "cancel"),
+            (self.reinject, "reinject"),
+            (self.retranscribe, "retranscribe"),
+            (self.reload, "reload_config"),
+        ]
+        .into_iter()
+        .filter_map(|(set, method)| set.then_some(method))
+        .collect()
+    }
+
+    pub fn show_overlay(&self) -> bool {
+        !(self.headless || self.no_overlay)
+    }
+
+    pub fn show_tray(&self) -> bool {
+        !(self.headless || self.no_tray)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Address for the HTTP API.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8787")]
+    pub bind: SocketAddr,
+    /// Also serve Wyoming (Home Assistant speech-to-text) on this address,
+    /// e.g. 0.0.0.0:10300.
+    #[arg(long, value_name = "ADDR")]
+    pub wyoming: Option<SocketAddr>,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TranscribeArgs {
+    /// Audio files to transcribe.
+    #[arg(required = true, value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The transcript only, one file per line.
+    Text,
+    /// One JSON object per file with language, confidence and timings.
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Report every problem in the config file with line and column.
+    Check {
+        /// File to check; defaults to the active config.
+        path: Option<PathBuf>,
+    },
+    /// Print where config, data, cache and logs live.
+    Path,
+    /// Print the effective config (defaults filled in, command-line overrides applied).
+    Print,
+    /// Print the JSON Schema for the config file.
+    Schema,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HistoryCommand {
+    /// Show entries, newest first.
+    Search {
+        /// Only entries containing this text (case-insensitive); all when omitted.
+        query: Option<String>,
+        /// Maximum number of entries; 0 for no limit.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// One JSON object per line instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write entries, oldest first, to a file or stdout.
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+        /// Output file; stdout when omitted.
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Only entries from this day on.
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        since: Option<NaiveDate>,
+    },
+    /// Delete entries.
+    Purge {
+        /// Only entries older than this many days.
+        #[arg(long, value_name = "DAYS", required_unless_present = "all")]
+        older_than: Option<u32>,
+        /// Delete everything.
+        #[arg(long, conflicts_with = "older_than")]
+        all: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// The stored records as-is.
+    Jsonl,
+    Csv,
+    /// A bullet per entry, like the `file` output.
+    Markdown,
+}
+
+impl Cli {
+    /// Run arguments given before the subcommand and after `run` or `serve`, combined.
+    pub fn run_args(&self) -> RunArgs {
+        let mut args = self.run.clone();
+        if let Some(Command::Run(sub) | Command::Serve(ServeArgs { run: sub, .. })) = &self.command {
+            args.no_overlay |= sub.no_overlay;
+            args.no_tray |= sub.no_tray;
+            args.headless |= sub.headless;
+            args.start |= sub.start;
+            args.stop |= sub.stop;
+            args.toggle |= sub.toggle;
+            args.cancel |= sub.cancel;
+            args.reinject |= sub.reinject;
+            args.retranscribe |= sub.retranscribe;
+            args.reload |= sub.reload;
+        }
+        args
+    }
+
+    /// Rejects run flags given with a subcommand that doesn't run the app,
+    /// such as `--stop devices`; they would otherwise be dropped silently.
+    pub fn validate(self) -> Result<Self, clap::Error> {
+        let name = match &self.command {
+            None | Some(Command::Run(_) | Command::Serve(_)) => return Ok(self),
+            Some(Command::Transcribe(_)) => "transcribe",
+            Some(Command::Devices) => "devices",
+            Some(Command::Models) => "models",
+            Some(Command::Config(_)) => "config",
+            Some(Command::History(_)) => "history",
+        };
+        let run = &self.run;
+        if run.actions().is_empty() && !(run.no_overlay || run.no_tray || run.headless) {
+            return Ok(self);
+        }
+        Err(<Self as clap::CommandFactory>::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            format!("`{name}` doesn't run the app; give run flags like --stop or --headless on their own or with `run`"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+    use std::path::Path;
+
+    fn try_parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("stt-whis-tray").chain(args.iter().copied())).and_then(Cli::validate)
+    }
+
+    fn parse(args: &[&str]) -> Cli {
+        try_parse(args).unwrap()
+    }
+
+    fn parse_err(args: &[&str]) -> clap::Error {
+        try_parse(args).unwrap_err()
+    }
+
+    #[test]
+    fn definition_is_consistent() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn no_subcommand_runs_the_app() {
+        let cli = parse(&[]);
+        assert!(cli.command.is_none());
+        assert!(cli.run_args().actions().is_empty());
+        assert!(cli.run_args().show_overlay());
+        assert!(cli.run_args().show_tray());
+
+        let cli = parse(&["--headless", "--toggle"]);
+        assert!(cli.command.is_none());
+        let run = cli.run_args();
+        assert!(!run.show_overlay() && !run.show_tray());
+        assert_eq!(run.actions(), ["toggle"]);
+    }
+
+    #[test]
+    fn global_options_work_on_either_side_of_the_subcommand() {
+        let cli = parse(&["--model", "small.en", "run", "--language", "de", "--log-level", "debug"]);
+        assert!(matches!(cli.command, Some(Command::Run(_))));
+        assert_eq!(cli.model.as_deref(), Some("small.en"));
+        assert_eq!(cli.language.as_deref(), Some("de"));
+        assert_eq!(cli.log_level, Some(LevelFilter::Debug));
+
+        let cli = parse(&["models", "--config", "/tmp/c.json"]);
+        assert!(matches!(cli.command, Some(Command::Models)));
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/c.json")));
+    }
+
+    #[test]
+    fn run_args_merge_flags_before_and_after_the_subcommand() {
+        let run = parse(&["--no-tray", "--start", "run", "--no-overlay", "--reload"]).run_args();
+        assert!(run.no_tray && run.no_overlay && !run.headless);
+        assert_eq!(run.actions(), ["start", "reload_config"]);
+
+        let run = parse(&["--cancel", "serve", "--bind", "127.0.0.1:9000", "--headless"]).run_args();
+        assert!(run.headless);
+        assert_eq!(run.actions(), ["cancel"]);
+
+        // Other subcommands exit before anything is forwarded, so the flags are refused.
+        assert_eq!(parse_err(&["--stop", "devices"]).kind(), ErrorKind::ArgumentConflict);
+        assert_eq!(parse_err(&["--headless", "history", "purge", "--all"]).kind(), ErrorKind::ArgumentConflict);
+        assert!(parse(&["--log-level", "debug", "devices"]).run_args().actions().is_empty());
+    }
+
+    #[test]
+    fn actions_follow_a_fixed_order() {
+        let run = parse(&[
+            "--reload",
+            "--retranscribe",
+            "--reinject",
+            "--cancel",
+            "--toggle",
+            "--stop",
+            "--start",
+        ])
+        .run_args();
+        assert_eq!(
+            run.actions(),
+            ["start", "stop", "toggle", "cancel", "reinject", "retranscribe", "reload_config"]
+        );
+    }
+
+    #[test]
+    fn serve() {
+        let Some(Command::Serve(serve)) = parse(&["serve"]).command else {
+            panic!("expected serve");
+        };
+        assert_eq!(serve.bind, "127.0.0.1:8787".parse().unwrap());
+        assert!(serve.wyoming.is_none());
+
+        let Some(Command::Serve(serve)) = parse(&["serve", "--bind", "0.0.0.0:80", "--wyoming", "0.0.0.0:10300"]).command
+        else {
+            panic!("expected serve");
+        };
+        assert_eq!(serve.bind, "0.0.0.0:80".parse().unwrap());
+        assert_eq!(serve.wyoming, Some("0.0.0.0:10300".parse().unwrap()));
+    }
+
+    #[test]
+    fn transcribe() {
+        let Some(Command::Transcribe(args)) = parse(&["transcribe", "a.wav", "b.mp3"]).command else {
+            panic!("expected transcribe");
+        };
+        assert_eq!(args.files, [PathBuf::from("a.wav"), PathBuf::from("b.mp3")]);
+        assert_eq!(args.format, OutputFormat::Text);
+
+        let Some(Command::Transcribe(args)) = parse(&["transcribe", "--format", "json", "a.wav"]).command else {
+            panic!("expected transcribe");
+        };
+        assert_eq!(args.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn devices_and_models() {
+        assert!(matches!(parse(&["devices"]).command, Some(Command::Devices)));
+        assert!(matches!(parse(&["models"]).command, Some(Command::Models)));
+    }
+
+    #[test]
+    fn config() {
+        let config = |args: &[&str]| match parse(args).command {
+            Some(Command::Config(c)) => c,
+            other => panic!("expected config, got {other:?}"),
+        };
+        assert!(matches!(config(&["config", "check"]), ConfigCommand::Check { path: None }));
+        assert!(
+            matches!(config(&["config", "check", "x.json"]), ConfigCommand::Check { path: Some(p) } if p == Path::new("x.json"))
+        );
+        assert!(matches!(config(&["config", "path"]), ConfigCommand::Path));
+        assert!(matches!(config(&["config", "print"]), ConfigCommand::Print));
+        assert!(matches!(config(&["config", "schema"]), ConfigCommand::Schema));
+    }
+
+    #[test]
+    fn history() {
+        let history = |args: &[&str]| match parse(args).command {
+            Some(Command::History(h)) => h,
+            other => panic!("expected history, got {other:?}"),
+        };
+        assert!(matches!(
+            history(&["history", "search"]),
+            HistoryCommand::Search { query: None, limit: 20, json: false }
+        ));
+        assert!(matches!(
+            history(&["history", "search", "meeting", "--limit", "0", "--json"]),
+            HistoryCommand::Search { query: Some(q), limit: 0, json: true } if q == "meeting"
+        ));
+        assert!(matches!(
+            history(&["history", "export"]),
+            HistoryCommand::Export { format: ExportFormat::Jsonl, output: None, since: None }
+        ));
+        assert!(matches!(
+            history(&["history", "export", "--format", "csv", "-o", "out.csv", "--since", "2024-05-06"]),
+            HistoryCommand::Export { format: ExportFormat::Csv, output: Some(_), since: Some(d) }
+                if d == NaiveDate::from_ymd_opt(2024, 5, 6).unwrap()
+        ));
+        assert!(matches!(
+            history(&["history", "purge", "--older-than", "30"]),
+            HistoryCommand::Purge { older_than: Some(30), all: false }
+        ));
+        assert!(matches!(
+            history(&["history", "purge", "--all"]),
+            HistoryCommand::Purge { older_than: None, all: true }
+        ));
+    }
+
+    #[test]
+    fn usage_errors_exit_with_2() {
+        for args in [
+            &["--bogus"][..],
+            &["frobnicate"],
+            &["transcribe"],
+            &["transcribe", "--format", "yaml", "a.wav"],
+            &["serve", "--bind", "not-an-address"],
+            &["--log-level", "loud"],
+            &["history", "purge"],
+            &["history", "purge", "--all", "--older-than", "3"],
+            &["history", "export", "--since", "yesterday"],
+            &["config"],
+        ] {
+            let err = parse_err(args);
+            assert_eq!(err.exit_code(), 2, "{args:?}: {err}");
+        }
+        assert_eq!(parse_err(&["history", "purge"]).kind(), ErrorKind::MissingRequiredArgument);
+        assert_eq!(parse_err(&["--bogus"]).kind(), ErrorKind::UnknownArgument);
+    }
+}
@@ -0,0 +1,298 @@
+// ----- Clipboard paste with snapshot/restore -----
+//
+// The user's clipboard is captured in every format arboard understands, our
+// text is placed and pasted, and the snapshot is put back on a background
+// thread once the target app has had time to read it. A newer paste cancels a
+// pending restore and inherits its snapshot, so back-to-back dictations never
+// "restore" our own transient text. If anything else writes the clipboard in
+// the meantime the restore is skipped.
+
+use arboard::{Clipboard, ImageData};
+use log::{info, warn};
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+const ADAPTIVE_BASE_MS: u64 = 150;
+const ADAPTIVE_MAX_MS: u64 = 1500;
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ClipboardConfig {
+    /// Put the previous clipboard contents back after pasting.
+    #[serde(default = "default_true")]
+    pub restore: bool,
+    /// Fixed wait before restoring; `None` scales with the pasted text length.
+    #[serde(default)]
+    pub restore_delay_ms: Option<u64>,
+    /// Ask the OS / clipboard managers not to record our transient text.
+    #[serde(default = "default_true")]
+    pub exclude_from_history: bool,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            restore: true,
+            restore_delay_ms: None,
+            exclude_from_history: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Everything we could read off the clipboard before overwriting it.
+#[derive(Default)]
+struct ClipboardSnapshot {
+    text: Option<String>,
+    html: Option<String>,
+    image: Option<ImageData<'static>>,
+    files: Vec<PathBuf>,
+}
+
+impl ClipboardSnapshot {
+    fn capture(clipboard: &mut Clipboard) -> Self {
+        Self {
+            text: clipboard.get().text().ok(),
+            html: clipboard.get().html().ok(),
+            image: clipboard.get().image().ok(),
+            files: clipboard.get().file_list().unwrap_or_default(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_none() && self.html.is_none() && self.image.is_none() && self.files.is_empty()
+    }
+
+    /// arboard replaces the clipboard on every set, so only the richest
+    /// representation is restored (HTML carries its plain-text alternative).
+    fn restore(self, clipboard: &mut Clipboard) -> Result<(), arboard::Error> {
+        if !self.files.is_empty() {
+            clipboard.set().file_list(&self.files)
+        } else if let Some(image) = self.image {
+            clipboard.set().image(image)
+        } else if let Some(html) = self.html {
+            clipboard.set().html(html.as_str(), self.text.as_deref())
+        } else if let Some(text) = self.text {
+            clipboard.set().text(text)
+        } else {
+            clipboard.clear()
+        }
+    }
+}
+
+struct PendingRestore {
+    generation: u64,
+    snapshot: ClipboardSnapshot,
+    /// Text we placed; used to detect foreign writes where no sequence number exists.
+    placed_text: String,
+    placed_seq: Option<u32>,
+}
+
+struct RestoreState {
+    generation: u64,
+    pending: Option<PendingRestore>,
+}
+
+impl RestoreState {
+    /// Makes `pending` the restore to run, superseding any earlier one, and
+    /// returns the generation its thread has to present to `take`.
+    fn schedule(&mut self, snapshot: ClipboardSnapshot, placed_text: &str, placed_seq: Option<u32>) -> u64 {
+        self.generation += 1;
+        self.pending = Some(PendingRestore {
+            generation: self.generation,
+            snapshot,
+            placed_text: placed_text.to_string(),
+            placed_seq,
+        });
+        self.generation
+    }
+
+    /// The pending restore if it is still `generation`'s; `None` once a newer
+    /// paste took it over or a copy cancelled it.
+    fn take(&mut self, generation: u64) -> Option<PendingRestore> {
+        match self.pending.take() {
+            Some(p) if p.generation == generation => Some(p),
+            other => {
+                self.pending = other;
+                None
+            }
+        }
+    }
+
+    /// Drops the pending restore, which would otherwise overwrite a copy.
+    fn cancel(&mut self) {
+        self.pending = None;
+    }
+}
+
+static RESTORE: Mutex<RestoreState> = Mutex::new(RestoreState {
+    generation: 0,
+    pending: None,
+});
+
+/// Place `text` on the clipboard, run `paste` (sends the paste chord), then
+/// schedule the restore of whatever was there before.
+pub fn paste_text(text: &str, cfg: &ClipboardConfig, paste: impl FnOnce()) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("clipboard: {e}"))?;
+
+    let mut state = RESTORE.lock();
+    // A restore still pending means the clipboard holds our previous text;
+    // the user's data is the snapshot it was going to put back.
+    let snapshot = match state.pending.take() {
+        Some(p) if !changed_since(&mut clipboard, &p) => p.snapshot,
+        _ if cfg.restore => ClipboardSnapshot::capture(&mut clipboard),
+        _ => ClipboardSnapshot::default(),
+    };
+    set_transient_text(&mut clipboard, text, cfg.exclude_from_history)
+        .map_err(|e| format!("set clipboard: {e}"))?;
+    let placed_seq = clipboard_sequence();
+    paste();
+
+    if !cfg.restore || snapshot.is_empty() {
+        return Ok(());
+    }
+    let generation = state.schedule(snapshot, text, placed_seq);
+    drop(state);
+
+    let delay = restore_delay(text, cfg);
+    thread::spawn(move || {
+        // Keep the handle alive until the restore: on X11/Wayland the owning
+        // process has to keep serving the pasted text until the target reads it.
+        let mut clipboard = clipboard;
+        thread::sleep(delay);
+        let mut state = RESTORE.lock();
+        // Superseded by a newer paste, which now owns the snapshot, or by a copy.
+        let Some(pending) = state.take(generation) else {
+            return;
+        };
+        if changed_since(&mut clipboard, &pending) {
+            info!("clipboard changed by another app since paste; not restoring");
+            return;
+        }
+        if let Err(e) = pending.snapshot.restore(&mut clipboard) {
+            warn!("clipboard restore failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+/// Put `text` on the clipboard for the user to paste themselves. Unlike
+/// dictated text it is not restored away or hidden from clipboard history.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn copy_text(text: &str) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| format!("clipboard: {e}"))?;
+    RESTORE.lock().cancel();
+    clipboard
+        .set_text(text)
+        .map_err(|e| format!("set clipboard: {e}"))
+}
+
+fn set_transient_text(
+    clipboard: &mut Clipboard,
+    text: &str,
+    exclude_from_history: bool,
+) -> Result<(), arboard::Error> {
+    let set = clipboard.set();
+    #[cfg(windows)]
+    let set = if exclude_from_history {
+        use arboard::SetExtWindows;
+        set.exclude_from_history().exclude_from_cloud()
+    } else {
+        set
+    };
+    #[cfg(target_os = "linux")]
+    let set = if exclude_from_history {
+        use arboard::SetExtLinux;
+        set.exclude_from_history()
+    } else {
+        set
+    };
+    #[cfg(target_os = "macos")]
+    let set = if exclude_from_history {
+        use arboard::SetExtApple;
+        set.exclude_from_history()
+    } else {
+        set
+    };
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    let _ = exclude_from_history;
+    set.text(text)
+}
+
+fn changed_since(clipboard: &mut Clipboard, pending: &PendingRestore) -> bool {
+    match (pending.placed_seq, clipboard_sequence()) {
+        (Some(before), Some(now)) => before != now,
+        _ => clipboard.get_text().ok().as_deref() != Some(pending.placed_text.as_str()),
+    }
+}
+
+fn restore_delay(text: &str, cfg: &ClipboardConfig) -> Duration {
+    let ms = cfg.restore_delay_ms.unwrap_or_else(|| {
+        // Large pastes take longer for the target to pull; ~1 ms per 100 chars.
+        (ADAPTIVE_BASE_MS + text.len() as u64 / 100).min(ADAPTIVE_MAX_MS)
+    });
+    Duration::from_millis(ms)
+}
+
+#[cfg(windows)]
+fn clipboard_sequence() -> Option<u32> {
+    let seq = unsafe { windows_sys::Win32::System::DataExchange::GetClipboardSequenceNumber() };
+    (seq != 0).then_some(seq)
+}
+
+#[cfg(not(windows))]
+fn clipboard_sequence() -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(text: &str) -> ClipboardSnapshot {
+        ClipboardSnapshot {
+            text: Some(text.into()),
+            ..ClipboardSnapshot::default()
+        }
+    }
+
+    fn state() -> RestoreState {
+        RestoreState {
+            generation: 0,
+            pending: None,
+        }
+    }
+
+    #[test]
+    fn a_newer_paste_takes_over_the_restore() {
+        let mut state = state();
+        let first = state.schedule(snapshot("user's"), "one", None);
+        // The second paste inherits the snapshot, as `paste_text` does.
+        let inherited = state.pending.take().unwrap().snapshot;
+        let second = state.schedule(inherited, "two", None);
+
+        assert!(state.take(first).is_none());
+        let pending = state.take(second).expect("the newer restore still runs");
+        assert_eq!(pending.snapshot.text.as_deref(), Some("user's"));
+        assert_eq!(pending.placed_text, "two");
+        assert!(state.take(second).is_none());
+    }
+
+    #[test]
+    fn a_copy_cancels_the_pending_restore() {
+        let mut state = state();
+        let generation = state.schedule(snapshot("user's"), "dictated", None);
+        state.cancel();
+        assert!(state.take(generation).is_none());
+
+        // Later pastes schedule as usual.
+        let generation = state.schedule(snapshot("copied"), "next", None);
+        assert!(state.take(generation).is_some());
+    }
+}
@@ -0,0 +1,358 @@
+// ----- One-shot subcommands -----
+//
+// `transcribe`, `devices`, `models`, `config ...` and `history ...`. They
+// print to stdout, log problems to stderr and return the process exit code.
+// None of them create, migrate or otherwise write files, except
+// `history export --output` and `history purge`.
+
+use crate::cli::{Cli, ConfigCommand, ExportFormat, HistoryCommand, OutputFormat, TranscribeArgs};
+use crate::history::{self, HistoryEntry};
+use crate::outputs::TranscriptRecord;
+use crate::{config_file, logging, paths, spawn_speech_runtime, Config, ConfigOverrides, SpeechCommand};
+use chrono::{Duration as ChronoDuration, Local, TimeZone};
+use cpal::traits::{DeviceTrait, HostTrait};
+use log::warn;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+const EXIT_OK: i32 = 0;
+const EXIT_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+/// The config as the app would see it, with command-line overrides applied.
+fn load_config(cli: &Cli) -> Result<Config, String> {
+    let path = &paths::get().config_file;
+    let config = match fs::read_to_string(path) {
+        Ok(data) => {
+            let report = config_file::parse(&data);
+            for issue in &report.issues {
+                warn!("{}:{issue}", path.display());
+            }
+            report.config
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    let overrides = ConfigOverrides::from_cli(cli, &config)?;
+    Ok(overrides.apply(&config))
+}
+
+pub fn transcribe(cli: &Cli, args: &TranscribeArgs) -> i32 {
+    let config = match load_config(cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return EXIT_USAGE;
+        }
+    };
+    // File jobs report through their reply channel; runtime events are unused.
+    let (evt_tx, _evt_rx) = mpsc::channel();
+    let speech_tx = match spawn_speech_runtime(evt_tx) {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("speech runtime: {e}");
+            return EXIT_FAILED;
+        }
+    };
+    let mut code = EXIT_OK;
+    for file in &args.files {
+        let (reply, reply_rx) = mpsc::channel();
+        let cmd = SpeechCommand::TranscribeFile {
+            path: file.clone(),
+            model_path: config.final_model_file(),
+            language: config.language.clone(),
+            engine: config.engine.clone(),
+            reply,
+        };
+        let result = match speech_tx.send(cmd).ok().and_then(|_| reply_rx.recv().ok()) {
+            Some(result) => result,
+            None => Err("speech runtime stopped".to_string()),
+        };
+        match result {
+            Ok((text, meta)) => match args.format {
+                OutputFormat::Text => println!("{}", text.trim()),
+                OutputFormat::Json => {
+                    let mut json = TranscriptRecord::new(text, meta).to_json();
+                    json["file"] = file.display().to_string().into();
+                    println!("{json}");
+                }
+            },
+            Err(e) => {
+                eprintln!("{}: {e}", file.display());
+                code = EXIT_FAILED;
+            }
+        }
+    }
+    code
+}
+
+pub fn devices() -> i32 {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+    let devices = match host.input_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("input devices: {e}");
+            return EXIT_FAILED;
+        }
+    };
+    for device in devices {
+        let name = device.name().unwrap_or_else(|_| "<unnamed>".to_string());
+        let marker = if default_name.as_ref() == Some(&name) { "*" } else { " " };
+        println!("{marker} {name}");
+        match device.supported_input_configs() {
+            Ok(configs) => {
+                for c in configs {
+                    println!(
+                        "    {} ch, {}-{} Hz, {}",
+                        c.channels(),
+                        c.min_sample_rate().0,
+                        c.max_sample_rate().0,
+                        c.sample_format()
+                    );
+                }
+            }
+            Err(e) => println!("    (formats unavailable: {e})"),
+        }
+    }
+    EXIT_OK
+}
+
+/// Model files in the models directory and next to the configured model;
+/// `*` marks the one in use.
+pub fn models(cli: &Cli) -> i32 {
+    let config = match load_config(cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return EXIT_USAGE;
+        }
+    };
+    let current = PathBuf::from(config.final_model_file());
+    let mut dirs = vec![paths::get().models_dir()];
+    if let Some(dir) = current.parent() {
+        dirs.push(dir.to_path_buf());
+    }
+    let mut found = BTreeSet::new();
+    for dir in &dirs {
+        let Ok(entries) = fs::read_dir(dir) else { continue };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_model = path
+                .extension()
+                .is_some_and(|ext| ext == "bin" || ext == "gguf");
+            if is_model && path.is_file() {
+                found.insert(path);
+            }
+        }
+    }
+    if found.is_empty() {
+        println!("No models in {}", paths::get().models_dir().display());
+    }
+    for path in &found {
+        let marker = if same_file(path, &current) { "*" } else { " " };
+        let size_mb = fs::metadata(path).map(|m| m.len() / (1024 * 1024)).unwrap_or(0);
+        println!("{marker} {} ({size_mb} MB)", path.display());
+    }
+    if !config.engine.model_exists(&config.final_model_file()) {
+        println!("! configured model missing: {}", current.display());
+    }
+    EXIT_OK
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+pub fn config(cli: &Cli, cmd: &ConfigCommand) -> i32 {
+    match cmd {
+        ConfigCommand::Check { path } => {
+            let path = path.clone().unwrap_or_else(|| paths::get().config_file.clone());
+            let data = match fs::read_to_string(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("{}: {e}", path.display());
+                    return EXIT_USAGE;
+                }
+            };
+            let report = config_file::parse(&data);
+            for issue in &report.issues {
+                println!("{}:{issue}", path.display());
+            }
+            if let Some(from) = report.migrated_from {
+                println!(
+                    "{}: version {from} is migrated to {} on next start",
+                    path.display(),
+                    config_file::CONFIG_VERSION
+                );
+            }
+            if report.has_errors() {
+                EXIT_FAILED
+            } else {
+                println!("{}: OK", path.display());
+                EXIT_OK
+            }
+        }
+        ConfigCommand::Path => {
+            let p = paths::get();
+            println!("config   {}", p.config_file.display());
+            println!("data     {}", p.data_dir.display());
+            println!("models   {}", p.models_dir().display());
+            println!("cache    {}", p.cache_dir.display());
+            println!("log      {}", logging::log_file().display());
+            println!("portable {}", p.portable);
+            EXIT_OK
+        }
+        ConfigCommand::Print => match load_config(cli) {
+            Ok(config) => match serde_json::to_string_pretty(&config) {
+                Ok(json) => {
+                    println!("{json}");
+                    EXIT_OK
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    EXIT_FAILED
+                }
+            },
+            Err(e) => {
+                eprintln!("{e}");
+                EXIT_USAGE
+            }
+        },
+        ConfigCommand::Schema => {
+            println!("{}", config_file::schema_json());
+            EXIT_OK
+        }
+    }
+}
+
+pub fn history(cmd: &HistoryCommand) -> i32 {
+    let path = paths::get().history_file();
+    let entries = match history::load(&path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            return EXIT_FAILED;
+        }
+    };
+    match cmd {
+        HistoryCommand::Search { query, limit, json } => {
+            let query = query.as_deref().map(str::to_lowercase);
+            let found = entries
+                .iter()
+                .rev()
+                .filter(|e| {
+                    query
+                        .as_deref()
+                        .is_none_or(|q| e.text.to_lowercase().contains(q))
+                })
+                .take(if *limit == 0 { usize::MAX } else { *limit });
+            for entry in found {
+                if *json {
+                    println!("{}", serde_json::to_string(entry).unwrap_or_default());
+                } else {
+                    println!(
+                        "#{:<5} {}  {:<16} {}",
+                        entry.id,
+                        entry.timestamp.format("%Y-%m-%d %H:%M"),
+                        entry.app.as_deref().unwrap_or("-"),
+                        entry.text
+                    );
+                }
+            }
+            EXIT_OK
+        }
+        HistoryCommand::Export {
+            format,
+            output,
+            since,
+        } => {
+            let since = since.and_then(|d| d.and_hms_opt(0, 0, 0)).and_then(|t| {
+                Local.from_local_datetime(&t).earliest()
+            });
+            let selected: Vec<&HistoryEntry> = entries
+                .iter()
+                .filter(|e| since.is_none_or(|s| e.timestamp >= s))
+                .collect();
+            let text = export(&selected, *format);
+            let result = match output {
+                Some(file) => fs::write(file, text),
+                None => io::stdout().write_all(text.as_bytes()),
+            };
+            match result {
+                Ok(()) => EXIT_OK,
+                Err(e) => {
+                    eprintln!("export: {e}");
+                    EXIT_FAILED
+                }
+            }
+        }
+        HistoryCommand::Purge { older_than, .. } => {
+            let cutoff = older_than.map(|days| Local::now() - ChronoDuration::days(i64::from(days)));
+            match history::purge(&path, cutoff) {
+                Ok(n) => {
+                    println!("Removed {n} entries");
+                    EXIT_OK
+                }
+                Err(e) => {
+                    eprintln!("{}: {e}", path.display());
+                    EXIT_FAILED
+                }
+            }
+        }
+    }
+}
+
+fn export(entries: &[&HistoryEntry], format: ExportFormat) -> String {
+    let mut out = String::new();
+    match format {
+        ExportFormat::Jsonl => {
+            for entry in entries {
+                out.push_str(&serde_json::to_string(entry).unwrap_or_default());
+                out.push('\n');
+            }
+        }
+        ExportFormat::Csv => {
+            out.push_str("id,timestamp,duration_ms,model,language,app,delivered,text\n");
+            for e in entries {
+                let row = [
+                    e.id.to_string(),
+                    e.timestamp.to_rfc3339(),
+                    e.duration_ms.to_string(),
+                    e.model.clone(),
+                    e.language.clone().unwrap_or_default(),
+                    e.app.clone().unwrap_or_default(),
+                    e.delivered.to_string(),
+                    e.text.clone(),
+                ];
+                let row: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+                out.push_str(&row.join(","));
+                out.push('\n');
+            }
+        }
+        ExportFormat::Markdown => {
+            for e in entries {
+                out.push_str(&format!(
+                    "- **{}** {}\n",
+                    e.timestamp.format("%Y-%m-%d %H:%M"),
+                    e.text
+                ));
+            }
+        }
+    }
+    out
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,549 @@
+// ----- Config file: parsing, validation, migration, schema -----
+//
+// config.json is read leniently: every top-level field is deserialized on its
+// own, so one bad value only costs that field (it falls back to its default
+// in memory) instead of the whole file. Each problem is reported with the
+// line and column it came from. The file is never rewritten to "fix" a
+// problem; the only writes are creating a fresh file, a migration of a clean
+// file to the current version (after a backup), setting a single field on
+// request (`set_field`), and the schema file beside it.
+
+use crate::capture::MAX_PRE_ROLL_MS;
+use crate::engine::EngineConfig;
+use crate::mock_engine::MockConfig;
+use crate::{Config, HotkeyCombo};
+use schemars::schema_for;
+use serde_json::value::RawValue;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Current `config_version`. Bump it together with a new entry in `MIGRATIONS`.
+pub const CONFIG_VERSION: u32 = 2;
+/// `$schema` value written into new configs; resolved relative to config.json.
+pub const SCHEMA_REF: &str = "./config.schema.json";
+const SCHEMA_FILE: &str = "config.schema.json";
+
+/// `MIGRATIONS[n]` upgrades a version `n` file to version `n + 1`.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// v0 files predate versioning; they only gain the editor schema pointer.
+fn migrate_v0_to_v1(cfg: &mut Map<String, Value>) {
+    cfg.entry("$schema")
+        .or_insert_with(|| Value::String(SCHEMA_REF.to_string()));
+}
+
+/// v2 split decoding into a partial and a final pass; the single model of
+/// v1 becomes the final one.
+fn migrate_v1_to_v2(cfg: &mut Map<String, Value>) {
+    if let Some(model) = cfg.remove("model_path") {
+        cfg.entry("final_model_path").or_insert(model);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The field was replaced by its default.
+    Error,
+    /// The value was kept but is probably not what the user wants.
+    Warning,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub field: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}:{}: {level}: ", self.line, self.column)?;
+        if !self.field.is_empty() {
+            write!(f, "{}: ", self.field)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Outcome of reading config.json: the usable config plus everything wrong with the file.
+pub struct ConfigReport {
+    pub config: Config,
+    pub issues: Vec<ConfigIssue>,
+    /// Version of the file on disk when it is older than `CONFIG_VERSION`.
+    pub migrated_from: Option<u32>,
+    /// The migrated file contents, unknown fields included, for `write_migrated`.
+    migrated: Option<Value>,
+}
+
+impl ConfigReport {
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|i| i.severity == Severity::Error)
+    }
+
+    /// All errors as one line, for `SpeechEvent::Error` and log output.
+    pub fn error_summary(&self) -> String {
+        self.issues
+            .iter()
+            .filter(|i| i.severity == Severity::Error)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Parse config text. Never fails: unusable input yields defaults plus issues.
+pub fn parse(data: &str) -> ConfigReport {
+    let mut issues = Vec::new();
+    let fields: BTreeMap<String, &RawValue> = match serde_json::from_str(data) {
+        Ok(fields) => fields,
+        Err(e) => {
+            issues.push(issue_from_json(Severity::Error, "", &e, (1, 1), 0));
+            return ConfigReport {
+                config: Config::default(),
+                issues,
+                migrated_from: None,
+                migrated: None,
+            };
+        }
+    };
+    let position = |key: &str| {
+        fields
+            .get(key)
+            .map(|raw| line_col(data, raw.get().as_ptr() as usize - data.as_ptr() as usize))
+            .unwrap_or((1, 1))
+    };
+
+    let mut map = Map::new();
+    for (key, raw) in &fields {
+        // Already valid JSON, so this cannot fail.
+        map.insert(key.clone(), serde_json::from_str(raw.get()).unwrap_or(Value::Null));
+    }
+    let version = match map.get("config_version") {
+        None => 0,
+        Some(v) => match v.as_u64().and_then(|v| u32::try_from(v).ok()) {
+            Some(version) => version,
+            None => {
+                let (line, column) = position("config_version");
+                issues.push(ConfigIssue {
+                    severity: Severity::Error,
+                    field: "config_version".into(),
+                    line,
+                    column,
+                    message: format!("{v} is not a version number; reading the file as version 0"),
+                });
+                0
+            }
+        },
+    };
+    if version > CONFIG_VERSION {
+        let (line, column) = position("config_version");
+        issues.push(ConfigIssue {
+            severity: Severity::Warning,
+            field: "config_version".into(),
+            line,
+            column,
+            message: format!(
+                "written by a newer release (version {version}, this build knows {CONFIG_VERSION}); unknown settings are ignored"
+            ),
+        });
+    }
+    let migrated_from = (version < CONFIG_VERSION).then_some(version);
+    for migrate in MIGRATIONS.iter().skip(version as usize) {
+        migrate(&mut map);
+    }
+    map.insert("config_version".into(), CONFIG_VERSION.into());
+    let migrated = migrated_from.map(|_| Value::Object(map.clone()));
+
+    let known = known_fields();
+    let mut accepted = Map::new();
+    for (key, value) in map {
+        let (line, column) = position(&key);
+        if !known.contains(&key) {
+            issues.push(ConfigIssue {
+                severity: Severity::Warning,
+                field: key,
+                line,
+                column,
+                message: "unknown field, ignored".into(),
+            });
+            continue;
+        }
+        // Re-parse the original text so nested errors keep their exact position.
+        let result = match fields.get(&key) {
+            Some(raw) if serde_json::from_str::<Value>(raw.get()).ok().as_ref() == Some(&value) => {
+                let key_json = Value::String(key.clone()).to_string();
+                let prefix = key_json.len() + 2;
+                serde_json::from_str::<Config>(&format!("{{{key_json}:{}}}", raw.get()))
+                    .map_err(|e| issue_from_json(Severity::Error, &key, &e, (line, column), prefix))
+            }
+            _ => {
+                let single = Value::Object(Map::from_iter([(key.clone(), value.clone())]));
+                serde_json::from_value::<Config>(single).map_err(|e| ConfigIssue {
+                    severity: Severity::Error,
+                    field: key.clone(),
+                    line,
+                    column,
+                    message: e.to_string(),
+                })
+            }
+        };
+        match result {
+            Ok(_) => {
+                accepted.insert(key, value);
+            }
+            Err(mut issue) => {
+                issue.message.push_str("; using the default");
+                issues.push(issue);
+            }
+        }
+    }
+    let mut config = serde_json::from_value::<Config>(Value::Object(accepted)).unwrap_or_default();
+    check_values(&mut config, &position, &mut issues);
+    issues.sort_by_key(|i| (i.line, i.column));
+    ConfigReport {
+        config,
+        issues,
+        migrated_from,
+        migrated,
+    }
+}
+
+/// Checks serde cannot express. Unusable values are reset to their defaults.
+fn check_values(
+    cfg: &mut Config,
+    position: &dyn Fn(&str) -> (usize, usize),
+    issues: &mut Vec<ConfigIssue>,
+) {
+    let defaults = Config::default();
+    let mut report = |severity, field: &str, message: String| {
+        let top = field.split('.').next().unwrap_or(field);
+        let (line, column) = position(top);
+        issues.push(ConfigIssue {
+            severity,
+            field: field.to_string(),
+            line,
+            column,
+            message,
+        });
+    };
+    if let Err(e) = HotkeyCombo::parse(&cfg.hotkey.combo) {
+        report(Severity::Error, "hotkey.combo", format!("{e}; using {}", defaults.hotkey.combo));
+        cfg.hotkey.combo = defaults.hotkey.combo.clone();
+    }
+    for (field, combo) in [
+        ("hotkey.reinject", &mut cfg.hotkey.reinject),
+        ("hotkey.retranscribe", &mut cfg.hotkey.retranscribe),
+    ] {
+        if let Some(Err(e)) = combo.as_deref().map(HotkeyCombo::parse) {
+            report(Severity::Error, field, format!("{e}; hotkey disabled"));
+            *combo = None;
+        }
+    }
+    if !(1..=16).contains(&cfg.retranscribe.beam_size) {
+        report(
+            Severity::Error,
+            "retranscribe.beam_size",
+            format!(
+                "{} is outside 1..=16; using {}",
+                cfg.retranscribe.beam_size, defaults.retranscribe.beam_size
+            ),
+        );
+        cfg.retranscribe.beam_size = defaults.retranscribe.beam_size;
+    }
+    if !(1..=1000).contains(&cfg.poll_hz) {
+        report(
+            Severity::Error,
+            "poll_hz",
+            format!("{} is outside 1..=1000; using {}", cfg.poll_hz, defaults.poll_hz),
+        );
+        cfg.poll_hz = defaults.poll_hz;
+    }
+    if cfg.capture.pre_roll_ms > MAX_PRE_ROLL_MS {
+        report(
+            Severity::Error,
+            "capture.pre_roll_ms",
+            format!(
+                "{} is outside 0..={MAX_PRE_ROLL_MS}; using {}",
+                cfg.capture.pre_roll_ms, defaults.capture.pre_roll_ms
+            ),
+        );
+        cfg.capture.pre_roll_ms = defaults.capture.pre_roll_ms;
+    }
+    if !(0.0..=1.0).contains(&cfg.min_confidence) {
+        report(
+            Severity::Error,
+            "min_confidence",
+            format!("{} is outside 0.0..=1.0; using {}", cfg.min_confidence, defaults.min_confidence),
+        );
+        cfg.min_confidence = defaults.min_confidence;
+    }
+    if let EngineConfig::Mock(mock) = &mut cfg.engine {
+        if mock.replies.is_empty() {
+            report(Severity::Error, "engine.replies", "is empty; using the default reply".into());
+            mock.replies = MockConfig::default().replies;
+        }
+        if !(0.0..=1.0).contains(&mock.confidence) {
+            report(
+                Severity::Error,
+                "engine.confidence",
+                format!("{} is outside 0.0..=1.0; using the default", mock.confidence),
+            );
+            mock.confidence = MockConfig::default().confidence;
+        }
+    }
+    // Kept as-is: the model may live on a drive that is only temporarily missing.
+    if !cfg.engine.model_exists(&cfg.final_model_file()) {
+        report(
+            Severity::Warning,
+            "final_model_path",
+            format!(
+                "{} does not exist (yet); transcription fails until it does",
+                cfg.final_model_path
+            ),
+        );
+    }
+    if let Some(path) = &cfg.partial_model_path {
+        if !cfg.engine.model_exists(&cfg.partial_model_file()) {
+            report(
+                Severity::Warning,
+                "partial_model_path",
+                format!("{path} does not exist (yet); partials use the final model until it does"),
+            );
+        }
+    }
+    if let Some(path) = &cfg.retranscribe.model_path {
+        if !cfg.engine.model_exists(&cfg.retranscribe_model_file()) {
+            report(
+                Severity::Warning,
+                "retranscribe.model_path",
+                format!("{path} does not exist (yet); re-transcribe fails until it does"),
+            );
+        }
+    }
+}
+
+/// Top-level keys `Config` understands.
+fn known_fields() -> Vec<String> {
+    let mut known: Vec<String> = match serde_json::to_value(Config::default()) {
+        Ok(Value::Object(map)) => map.into_iter().map(|(k, _)| k).collect(),
+        _ => Vec::new(),
+    };
+    // Optional fields that are skipped when unset.
+    for key in ["$schema", "language"] {
+        if !known.iter().any(|k| k == key) {
+            known.push(key.to_string());
+        }
+    }
+    known
+}
+
+/// Turn a serde_json error from a `{"key":<raw>}` wrapper back into a position
+/// in the original file; `prefix` is the wrapper's length before `<raw>`.
+fn issue_from_json(
+    severity: Severity,
+    field: &str,
+    err: &serde_json::Error,
+    (line, column): (usize, usize),
+    prefix: usize,
+) -> ConfigIssue {
+    let (line, column) = match err.line() {
+        0 => (line, column),
+        1 => (line, column + err.column().saturating_sub(prefix + 1)),
+        n => (line + n - 1, err.column()),
+    };
+    let message = err.to_string();
+    let suffix = format!(" at line {} column {}", err.line(), err.column());
+    ConfigIssue {
+        severity,
+        field: field.to_string(),
+        line,
+        column,
+        message: message.strip_suffix(&suffix).unwrap_or(&message).to_string(),
+    }
+}
+
+/// 1-based line and column (in characters) of a byte offset.
+fn line_col(data: &str, offset: usize) -> (usize, usize) {
+    let before = &data[..offset.min(data.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// JSON Schema for config.json (editor autocompletion and validation).
+pub fn schema_json() -> String {
+    serde_json::to_string_pretty(&schema_for!(Config)).unwrap_or_default()
+}
+
+pub fn schema_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(SCHEMA_FILE)
+}
+
+/// Keep the schema next to config.json current with this build.
+pub fn write_schema(config_path: &Path) -> std::io::Result<()> {
+    let path = schema_path(config_path);
+    let schema = schema_json();
+    if fs::read_to_string(&path).ok().as_deref() == Some(schema.as_str()) {
+        return Ok(());
+    }
+    fs::write(path, schema)
+}
+
+/// Persist a migrated config, keeping the old file as `config.json.v<N>.bak`.
+pub fn write_migrated(path: &Path, report: &ConfigReport) -> std::io::Result<()> {
+    let (Some(from), Some(migrated)) = (report.migrated_from, &report.migrated) else {
+        return Ok(());
+    };
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{from}.bak"));
+    fs::copy(path, PathBuf::from(backup))?;
+    fs::write(path, serde_json::to_string_pretty(migrated)?)
+}
+
+/// Set one top-level `key` in the config file and return the parsed result.
+/// The rest of the file (`$schema`, unknown keys, key order, even invalid
+/// values) is kept as written. Nothing is written if the result has errors;
+/// otherwise the file is replaced atomically.
+pub fn set_field(path: &Path, key: &str, value: Value) -> Result<ConfigReport, String> {
+    let err = |e: &dyn fmt::Display| format!("{}: {e}", path.display());
+    let data = fs::read_to_string(path).map_err(|e| err(&e))?;
+    let mut fields: Map<String, Value> = serde_json::from_str(&data).map_err(|e| err(&e))?;
+    fields.insert(key.to_string(), value);
+    let data = serde_json::to_string_pretty(&fields).map_err(|e| err(&e))?;
+    let report = parse(&data);
+    if report.has_errors() {
+        return Err(report.error_summary());
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, data + "\n")
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| err(&e))?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_field_keeps_the_rest_of_the_file() {
+        let dir = std::env::temp_dir().join(format!("whistxt-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        fs::write(
+            &path,
+            r#"{"$schema": "./config.schema.json", "config_version": 2, "zzz_note": "mine", "final_model_path": "a.bin", "min_confidence": 0.5}"#,
+        )
+        .unwrap();
+
+        let report = set_field(&path, "final_model_path", Value::from("b.bin")).unwrap();
+        assert_eq!(report.config.final_model_path, "b.bin");
+        let written: Map<String, Value> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let keys: Vec<&str> = written.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["$schema", "config_version", "zzz_note", "final_model_path", "min_confidence"]);
+        assert_eq!(written["zzz_note"], "mine");
+        assert_eq!(written["final_model_path"], "b.bin");
+
+        // A value that does not validate leaves the file alone.
+        assert!(set_field(&path, "min_confidence", Value::from("high")).is_err());
+        let after: Map<String, Value> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(after, written);
+        assert!(!dir.join("config.json.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn issue(report: &ConfigReport, field: &str) -> String {
+        let found: Vec<_> = report.issues.iter().filter(|i| i.field == field).collect();
+        assert_eq!(found.len(), 1, "{field}: {:?}", report.issues);
+        found[0].to_string()
+    }
+
+    #[test]
+    fn a_bad_field_only_costs_itself() {
+        let report = parse(
+            "{\n  \"config_version\": 2,\n  \"min_confidence\": \"high\",\n  \"final_model_path\": \"a.bin\",\n  \"poll_hz\": 5000\n}",
+        );
+        let defaults = Config::default();
+        assert_eq!(report.config.final_model_path, "a.bin");
+        assert_eq!(report.config.min_confidence, defaults.min_confidence);
+        assert_eq!(report.config.poll_hz, defaults.poll_hz);
+        assert!(issue(&report, "min_confidence").starts_with("3:26: error: min_confidence: "), "{:?}", report.issues);
+        assert!(issue(&report, "min_confidence").ends_with("; using the default"));
+        assert_eq!(
+            issue(&report, "poll_hz"),
+            format!("5:14: error: poll_hz: 5000 is outside 1..=1000; using {}", defaults.poll_hz)
+        );
+        assert_eq!(report.migrated_from, None);
+    }
+
+    #[test]
+    fn nested_errors_point_into_the_value() {
+        let report = parse("{\"config_version\": 2,\n\"capture\": {\n  \"pre_roll_ms\": \"lots\"\n}}");
+        assert!(issue(&report, "capture").starts_with("3:23: error: capture: "), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn unreadable_json_gives_defaults() {
+        let report = parse("{\n  \"poll_hz\": 30,\n}");
+        assert!(report.has_errors());
+        assert_eq!(report.issues[0].line, 3);
+        assert_eq!(report.config.poll_hz, Config::default().poll_hz);
+    }
+
+    #[test]
+    fn old_versions_migrate_step_by_step() {
+        // Version 0 had no `config_version`, no `$schema` and a single `model_path`.
+        let report = parse(r#"{"model_path": "old.bin", "zzz_note": "mine"}"#);
+        assert_eq!(report.migrated_from, Some(0));
+        assert_eq!(report.config.final_model_path, "old.bin");
+        let migrated = report.migrated.as_ref().unwrap();
+        assert_eq!(migrated["$schema"], SCHEMA_REF);
+        assert_eq!(migrated["config_version"], CONFIG_VERSION);
+        assert_eq!(migrated["final_model_path"], "old.bin");
+        assert_eq!(migrated["zzz_note"], "mine");
+        assert!(migrated.get("model_path").is_none());
+
+        // Version 1 already has a schema of its own.
+        let report = parse(r#"{"$schema": "mine.json", "config_version": 1, "model_path": "old.bin", "final_model_path": "new.bin"}"#);
+        assert_eq!(report.migrated_from, Some(1));
+        let migrated = report.migrated.as_ref().unwrap();
+        assert_eq!(migrated["$schema"], "mine.json");
+        assert_eq!(migrated["final_model_path"], "new.bin");
+        assert!(migrated.get("model_path").is_none());
+    }
+
+    #[test]
+    fn a_non_numeric_version_is_read_as_version_0() {
+        let report = parse("{\n  \"config_version\": \"two\",\n  \"model_path\": \"old.bin\"\n}");
+        assert_eq!(
+            issue(&report, "config_version"),
+            "2:21: error: config_version: \"two\" is not a version number; reading the file as version 0"
+        );
+        assert_eq!(report.migrated_from, Some(0));
+        assert_eq!(report.config.final_model_path, "old.bin");
+    }
+
+    #[test]
+    fn newer_versions_warn_and_keep_known_fields() {
+        let report = parse(r#"{"config_version": 99, "poll_hz": 60, "from_the_future": true}"#);
+        assert_eq!(report.migrated_from, None);
+        assert!(!report.has_errors());
+        assert_eq!(report.config.poll_hz, 60);
+        assert_eq!(
+            issue(&report, "config_version"),
+            format!("1:20: warning: config_version: written by a newer release (version 99, this build knows {CONFIG_VERSION}); unknown settings are ignored")
+        );
+        assert_eq!(issue(&report, "from_the_future"), "1:58: warning: from_the_future: unknown field, ignored");
+    }
+}
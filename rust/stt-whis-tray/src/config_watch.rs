@@ -0,0 +1,73 @@
+// ----- Live config reload -----
+//
+// Watches the directory holding config.json (editors often save by writing a
+// temp file and renaming it over the original, which a watch on the file
+// itself would lose) and reloads through `SharedConfig::reload`, which
+// validates before swapping. A bad edit is reported as `SpeechEvent::Error`
+// and the previous config stays active. Readers pick the new values up on
+// their next `SharedConfig::get`.
+
+use crate::{SharedConfig, SpeechEvent};
+use log::info;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Editors emit several events per save; wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+pub fn start(config: SharedConfig, evt_tx: Sender<SpeechEvent>) -> Result<(), String> {
+    let path = config.path().to_path_buf();
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."))
+        .to_path_buf();
+    let file_name = path.file_name().map(|n| n.to_os_string());
+
+    let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| format!("config watcher: {e}"))?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("watch {}: {e}", dir.display()))?;
+
+    thread::spawn(move || {
+        // Owned here so the watch lives as long as the thread.
+        let _watcher = watcher;
+        let touches_config = |ev: &notify::Event| {
+            !matches!(ev.kind, EventKind::Access(_))
+                && ev.paths.iter().any(|p| p.file_name() == file_name.as_deref())
+        };
+        let mut last_error: Option<String> = None;
+        while let Ok(res) = rx.recv() {
+            let Ok(ev) = res else { continue };
+            if !touches_config(&ev) {
+                continue;
+            }
+            while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+            let before = config.get();
+            match config.reload() {
+                Ok(after) => {
+                    last_error = None;
+                    // Our own writes (`SharedConfig::set`) come back here unchanged.
+                    let same = serde_json::to_value(&*before).ok() == serde_json::to_value(&*after).ok();
+                    if !same {
+                        info!("Config reloaded from {}", path.display());
+                        let _ = evt_tx.send(SpeechEvent::Info("Config reloaded".into()));
+                    }
+                }
+                Err(e) => {
+                    let msg = format!("{}: {e}; keeping previous config", path.display());
+                    // Don't repeat the same complaint for every save of a broken file.
+                    if last_error.as_deref() != Some(msg.as_str()) {
+                        let _ = evt_tx.send(SpeechEvent::Error(msg.clone()));
+                        last_error = Some(msg);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,191 @@
+// ----- Speech engines -----
+//
+// `spawn_speech_runtime` owns capture, buffering and the dictation state
+// machine; decoding goes through `SpeechEngine`. `whisper` is the real
+// backend. `vosk` (cargo feature) is a lighter recognizer for slow machines
+// that decodes while recording. `mock` needs no model: it returns scripted
+// text after scripted delays, so the runtime, overlay and outputs can be
+// exercised anywhere.
+// Engines take 16 kHz mono samples; models are identified by path.
+
+use crate::mock_engine::{MockConfig, MockEngine};
+#[cfg(feature = "vosk")]
+use crate::vosk_engine::VoskEngine;
+use crate::whisper_engine::WhisperEngine;
+use crate::TranscriptMeta;
+use log::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineConfig {
+    /// whisper.cpp with `final_model_path` / `partial_model_path`.
+    #[default]
+    Whisper,
+    /// Scripted transcripts; model paths are ignored.
+    Mock(MockConfig),
+    /// Vosk (Kaldi) with native streaming partials; `final_model_path` is
+    /// an unpacked model directory and `partial_model_path` is unused.
+    #[cfg(feature = "vosk")]
+    Vosk,
+}
+
+impl EngineConfig {
+    /// Whether `path` looks like a model this engine can load.
+    pub fn model_exists(&self, path: &str) -> bool {
+        match self {
+            EngineConfig::Whisper => Path::new(path).is_file(),
+            EngineConfig::Mock(_) => true,
+            #[cfg(feature = "vosk")]
+            EngineConfig::Vosk => Path::new(path).is_dir(),
+        }
+    }
+
+    /// Language codes `model` understands; empty when the engine cannot tell.
+    pub fn languages(&self, model: &str) -> Vec<String> {
+        match self {
+            EngineConfig::Whisper => crate::whisper_engine::languages(model),
+            EngineConfig::Mock(_) => vec!["en".to_string()],
+            #[cfg(feature = "vosk")]
+            EngineConfig::Vosk => Vec::new(),
+        }
+    }
+
+    pub fn create(&self) -> Arc<dyn SpeechEngine> {
+        match self {
+            EngineConfig::Whisper => Arc::new(WhisperEngine::default()),
+            EngineConfig::Mock(cfg) => Arc::new(MockEngine::new(cfg.clone())),
+            #[cfg(feature = "vosk")]
+            EngineConfig::Vosk => Arc::new(VoskEngine::default()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    /// Spoken language; detected when unset.
+    pub language: Option<String>,
+    /// Beam search width; greedy decoding at 1 (or 0).
+    pub beam_size: u32,
+    /// A live overlay pass: one segment, no language or no-speech probes.
+    pub partial: bool,
+    /// Also measure how likely the detected language is
+    /// (`TranscriptMeta::language_prob`). Whisper needs a second encoder
+    /// pass for it, so it is only asked for when `min_confidence` uses it.
+    pub language_prob: bool,
+    /// Text that precedes the audio, to steer spelling and style. Engines
+    /// that cannot use it ignore it.
+    pub prompt: Option<String>,
+    /// Cuts this decode short; it then returns an error. Other decodes on
+    /// the same engine are unaffected.
+    pub cancel: CancelToken,
+}
+
+/// Shared flag that asks the decodes holding it to stop.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub trait SpeechEngine: Send + Sync {
+    /// Shown in logs and transcript metadata, e.g. `whisper.cpp/cpu`.
+    fn backend(&self) -> String;
+
+    /// Whether `model` exists; checked before capture starts.
+    fn has_model(&self, model: &str) -> bool;
+
+    /// Make `model` ready for decoding. Engines may unload other models
+    /// when memory is short.
+    fn load(&self, model: &str) -> Result<(), String>;
+
+    /// Load `model` only if it fits next to `keep`; `Ok(false)` when it does not.
+    fn load_alongside(&self, model: &str, _keep: &str) -> Result<bool, String> {
+        self.load(model).map(|_| true)
+    }
+
+    /// Decode `audio`, loading `model` if needed.
+    fn transcribe(
+        &self,
+        model: &str,
+        audio: &[f32],
+        opts: &DecodeOptions,
+    ) -> Result<(String, TranscriptMeta), String> {
+        self.transcribe_streaming(model, audio, opts, &mut |_| {})
+    }
+
+    /// Like `transcribe`, calling `on_segment` with each segment's text as
+    /// soon as it is decoded.
+    fn transcribe_streaming(
+        &self,
+        model: &str,
+        audio: &[f32],
+        opts: &DecodeOptions,
+        on_segment: &mut dyn FnMut(&str),
+    ) -> Result<(String, TranscriptMeta), String>;
+
+    /// A decoder that follows the recording as it is captured, for engines
+    /// that stream natively. `None` when the engine only decodes whole clips;
+    /// partials then come from re-decoding the recent audio.
+    fn live(&self, _model: &str, _opts: &DecodeOptions) -> Option<Result<Box<dyn LiveDecoder>, String>> {
+        None
+    }
+}
+
+/// Incremental decoding of one recording.
+pub trait LiveDecoder: Send {
+    /// Add newly captured 16 kHz audio; the text so far when it changed.
+    fn accept(&mut self, audio: &[f32]) -> Option<String>;
+
+    /// Transcript of everything accepted.
+    fn finish(self: Box<Self>) -> Result<(String, TranscriptMeta), String>;
+}
+
+/// The engine the speech runtime decodes with, rebuilt when its config changes.
+pub struct ActiveEngine {
+    cfg: EngineConfig,
+    engine: Arc<dyn SpeechEngine>,
+}
+
+impl ActiveEngine {
+    pub fn new(cfg: &EngineConfig) -> Self {
+        Self {
+            cfg: cfg.clone(),
+            engine: cfg.create(),
+        }
+    }
+
+    /// Switch engines if `cfg` differs from the running one. Decodes still
+    /// holding the old engine finish on it.
+    pub fn sync(&mut self, cfg: &EngineConfig) {
+        if self.cfg != *cfg {
+            *self = Self::new(cfg);
+            info!("Speech engine changed to {}", self.engine.backend());
+        }
+    }
+
+    /// A handle for decoding on another thread.
+    pub fn shared(&self) -> Arc<dyn SpeechEngine> {
+        self.engine.clone()
+    }
+}
+
+impl Deref for ActiveEngine {
+    type Target = dyn SpeechEngine;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.engine
+    }
+}
@@ -0,0 +1,798 @@
+// ----- Windows GUI -----
+//
+// Overlay HUD, tray icon and the "Start with Windows" link. Compiled on
+// Windows only; other platforms always run headless.
+
+use crate::history::HistoryEntry;
+use crate::{AppError, OverlayMsg};
+use log::error;
+use parking_lot::Mutex;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use windows_sys::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, RECT, WPARAM};
+use windows_sys::Win32::Graphics::Gdi::{
+    BeginPaint, CreateSolidBrush, DeleteObject, Ellipse, EndPaint, FillRect, InvalidateRect,
+    SelectObject, SetBkMode, SetTextColor, TextOutW, HBRUSH, HDC, PAINTSTRUCT, TRANSPARENT,
+};
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::UI::Shell::{
+    ShellExecuteW, Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE,
+    NIM_MODIFY, NOTIFYICONDATAW,
+};
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    CreateIcon, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DispatchMessageW, GetClassNameW,
+    GetCursorPos, GetForegroundWindow, GetMessageW, GetWindowLongPtrW, IsWindow, KillTimer,
+    LoadIconW, LoadImageW, MoveWindow, PeekMessageW, PostMessageW, PostQuitMessage, RegisterClassW,
+    RemoveMenu, SetForegroundWindow, SetTimer, SetWindowLongPtrW, SetWindowPos, ShowWindow,
+    TrackPopupMenu, TranslateMessage, CW_USEDEFAULT, GWLP_USERDATA, HICON, HMENU, HWND_TOPMOST,
+    IDI_APPLICATION, IMAGE_ICON, LR_SHARED, MF_BYPOSITION, MF_GRAYED, MF_POPUP, MSG, PM_REMOVE,
+    SW_HIDE, SW_SHOWNOACTIVATE, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SWP_SHOWWINDOW,
+    SW_SHOWNORMAL, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_RETURNCMD, TPM_RIGHTBUTTON, WNDCLASSW,
+    WM_APP, WM_COMMAND, WM_DESTROY, WM_NULL, WM_PAINT, WM_QUIT, WM_RBUTTONUP, WM_TIMER,
+    WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_OVERLAPPEDWINDOW, WS_POPUP,
+};
+
+const STARTUP_LINK_NAME: &str = "WhisTxtR.lnk";
+const WM_TRAYICON: u32 = 0x0400 + 1;
+const ID_TRAY_QUIT: u16 = 1;
+const ID_TRAY_STARTUP: u16 = 2;
+const ID_TRAY_OPEN_LOG: u16 = 3;
+/// Recent entry `i` uses `ID_TRAY_RECENT + 2 * i` (paste) and the next id (copy).
+const ID_TRAY_RECENT: u16 = 100;
+const RECENT_LABEL_CHARS: usize = 48;
+const TRAY_TIP: &str = "WhisTxtR (right-click for menu)";
+const TRAY_TIP_MIC: &str = "WhisTxtR: microphone open";
+const TRAY_ICON_SIZE: i32 = 16;
+const OVERLAY_WIDTH: i32 = 520;
+const OVERLAY_HEIGHT: i32 = 120;
+const WM_OVERLAY_UPDATE: u32 = WM_APP + 1;
+const OVERLAY_TIMER_ID: usize = 1;
+const OVERLAY_BLINK_MS: u32 = 500;
+const OVERLAY_HIDE_DELAY_MS: u128 = 2500;
+
+// ----- Start with Windows (shortcut in the Startup folder) -----
+
+fn startup_link_path() -> PathBuf {
+    let startup = env::var("APPDATA").unwrap_or_default();
+    PathBuf::from(startup)
+        .join("Microsoft")
+        .join("Windows")
+        .join("Start Menu")
+        .join("Programs")
+        .join("Startup")
+        .join(STARTUP_LINK_NAME)
+}
+
+pub fn enable_startup() -> Result<(), String> {
+    let exe = env::current_exe().map_err(|e| format!("exe path: {e}"))?;
+    let link_path = startup_link_path();
+    let sl = mslnk::ShellLink::new(exe).map_err(|e| format!("ShellLink: {e}"))?;
+    sl.create_lnk(&link_path)
+        .map_err(|e| format!("create startup link: {e}"))
+}
+
+pub fn disable_startup() -> Result<(), String> {
+    let link_path = startup_link_path();
+    if link_path.exists() {
+        fs::remove_file(&link_path).map_err(|e| format!("remove link: {e}"))?;
+    }
+    Ok(())
+}
+
+pub fn is_startup_enabled() -> bool {
+    startup_link_path().exists()
+}
+
+// ----- Overlay (recording HUD) -----
+
+struct OverlayState {
+    recording: bool,
+    processing: bool,
+    text: String,
+    visible: bool,
+    last_update: Instant,
+    blink_on: bool,
+}
+
+struct OverlayShared {
+    state: Mutex<OverlayState>,
+    queue: Mutex<Vec<OverlayMsg>>,
+}
+
+pub struct OverlayHandle {
+    tx: Sender<OverlayMsg>,
+}
+
+impl OverlayHandle {
+    pub fn send(&self, msg: OverlayMsg) {
+        let _ = self.tx.send(msg);
+    }
+}
+
+pub fn start_overlay() -> OverlayHandle {
+    let (tx, rx) = mpsc::channel::<OverlayMsg>();
+    thread::spawn(move || {
+        if let Err(e) = run_overlay(rx) {
+            error!("overlay error: {e}");
+        }
+    });
+    OverlayHandle { tx }
+}
+
+fn run_overlay(rx: Receiver<OverlayMsg>) -> Result<(), String> {
+    unsafe {
+        let hinstance = GetModuleHandleW(std::ptr::null());
+        let class_name = wide("WhisOverlay");
+        let wc = WNDCLASSW {
+            style: 0,
+            lpfnWndProc: Some(overlay_wnd_proc),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: hinstance,
+            hIcon: LoadIconW(0, IDI_APPLICATION as *const u16),
+            hCursor: 0,
+            hbrBackground: 0 as HBRUSH,
+            lpszMenuName: std::ptr::null(),
+            lpszClassName: class_name.as_ptr(),
+        };
+        if RegisterClassW(&wc) == 0 {
+            return Err("RegisterClassW failed".into());
+        }
+
+        let hwnd = CreateWindowExW(
+            WS_EX_TOPMOST | WS_EX_TOOLWINDOW,
+            class_name.as_ptr(),
+            class_name.as_ptr(),
+            WS_POPUP,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            OVERLAY_WIDTH,
+            OVERLAY_HEIGHT,
+            0,
+            0,
+            hinstance,
+            std::ptr::null(),
+        );
+        if hwnd == 0 {
+            return Err("CreateWindowExW failed".into());
+        }
+
+        let shared = Arc::new(OverlayShared {
+            state: Mutex::new(OverlayState {
+                recording: false,
+                processing: false,
+                text: String::from("Ready"),
+                visible: true,
+                last_update: std::time::Instant::now(),
+                blink_on: true,
+            }),
+            queue: Mutex::new(Vec::new()),
+        });
+        let shared_for_wnd = Arc::clone(&shared);
+        SetWindowLongPtrW(hwnd, GWLP_USERDATA, Arc::into_raw(shared_for_wnd) as isize);
+
+        // Position at bottom center
+        let sx = GetSystemMetrics(0);
+        let sy = GetSystemMetrics(1);
+        let x = (sx - OVERLAY_WIDTH) / 2;
+        let y = sy - OVERLAY_HEIGHT - 40;
+        MoveWindow(hwnd, x, y, OVERLAY_WIDTH, OVERLAY_HEIGHT, 1);
+        SetWindowPos(
+            hwnd,
+            HWND_TOPMOST,
+            x,
+            y,
+            OVERLAY_WIDTH,
+            OVERLAY_HEIGHT,
+            SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE | SWP_SHOWWINDOW,
+        );
+        ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+        InvalidateRect(hwnd, std::ptr::null(), 1);
+
+        // Bridge the channel onto the window thread so GetMessageW can stay blocking.
+        let hwnd_for_thread = hwnd;
+        let shared_for_queue = Arc::clone(&shared);
+        thread::spawn(move || {
+            while let Ok(msg) = rx.recv() {
+                {
+                    let mut q = shared_for_queue.queue.lock();
+                    q.push(msg);
+                }
+                PostMessageW(hwnd_for_thread, WM_OVERLAY_UPDATE, 0, 0);
+            }
+            PostMessageW(hwnd_for_thread, WM_QUIT, 0, 0);
+        });
+
+        SetTimer(hwnd, OVERLAY_TIMER_ID, OVERLAY_BLINK_MS, None);
+
+        let mut msg: MSG = std::mem::zeroed();
+        while GetMessageW(&mut msg, 0, 0, 0) != 0 {
+            if msg.message == WM_TIMER && msg.wParam == OVERLAY_TIMER_ID {
+                let mut hide_now = false;
+                {
+                    let mut st = shared.state.lock();
+                    if st.recording || st.processing {
+                        st.blink_on = !st.blink_on;
+                    }
+                    if st.visible
+                        && !st.recording
+                        && !st.processing
+                        && st.last_update.elapsed().as_millis() > OVERLAY_HIDE_DELAY_MS
+                    {
+                        st.visible = false;
+                        hide_now = true;
+                    }
+                }
+                if hide_now {
+                    ShowWindow(hwnd, SW_HIDE);
+                }
+                InvalidateRect(hwnd, std::ptr::null(), 0);
+                continue;
+            }
+            if msg.message == WM_OVERLAY_UPDATE {
+                apply_overlay_queue(hwnd, &shared);
+                continue;
+            }
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+
+        KillTimer(hwnd, OVERLAY_TIMER_ID);
+        let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const OverlayShared;
+        if !ptr.is_null() {
+            let _ = Arc::from_raw(ptr);
+        }
+        Ok(())
+    }
+}
+
+#[allow(non_snake_case)]
+extern "system" fn overlay_wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_PAINT => {
+                let mut ps: PAINTSTRUCT = std::mem::zeroed();
+                let hdc = BeginPaint(hwnd, &mut ps);
+                if hdc != 0 {
+                    let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const OverlayShared;
+                    if !ptr.is_null() {
+                        let shared = &*ptr;
+                        let st = shared.state.lock();
+                        paint_overlay(hdc, &st);
+                    }
+                    EndPaint(hwnd, &mut ps);
+                }
+                0
+            }
+            WM_DESTROY => {
+                PostQuitMessage(0);
+                0
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+fn apply_overlay_queue(hwnd: HWND, shared: &Arc<OverlayShared>) {
+    let mut do_show = false;
+    let mut do_hide = false;
+    {
+        let mut queue = shared.queue.lock();
+        if queue.is_empty() {
+            return;
+        }
+        let mut st = shared.state.lock();
+        for msg in queue.drain(..) {
+            match msg {
+                OverlayMsg::RecordingStart => {
+                    st.recording = true;
+                    st.processing = false;
+                    st.text.clear();
+                    st.visible = true;
+                    st.last_update = Instant::now();
+                    st.blink_on = true;
+                }
+                OverlayMsg::Processing => {
+                    st.processing = true;
+                    st.recording = false;
+                    st.visible = true;
+                    st.last_update = Instant::now();
+                    st.blink_on = true;
+                }
+                OverlayMsg::Transcript(t) => {
+                    st.text = t;
+                    st.processing = false;
+                    st.recording = false;
+                    st.visible = true;
+                    st.last_update = Instant::now();
+                    st.blink_on = true;
+                }
+                OverlayMsg::Hide => {
+                    st.recording = false;
+                    st.processing = false;
+                    st.visible = false;
+                }
+            }
+        }
+        do_show = st.visible;
+        do_hide = !st.visible;
+    }
+    if do_show {
+        unsafe {
+            SetWindowPos(
+                hwnd,
+                HWND_TOPMOST,
+                0,
+                0,
+                0,
+                0,
+                SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE | SWP_SHOWWINDOW,
+            );
+            ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+            InvalidateRect(hwnd, std::ptr::null(), 1);
+        }
+    } else if do_hide {
+        unsafe {
+            ShowWindow(hwnd, SW_HIDE);
+        }
+    }
+}
+
+fn paint_overlay(hdc: HDC, state: &OverlayState) {
+    unsafe {
+        let rect = RECT {
+            left: 0,
+            top: 0,
+            right: OVERLAY_WIDTH,
+            bottom: OVERLAY_HEIGHT,
+        };
+        // black background
+        let back_br = CreateSolidBrush(colorref(0, 0, 0));
+        FillRect(hdc, &rect, back_br);
+        DeleteObject(back_br as _);
+
+        // Text color white
+        SetTextColor(hdc, colorref(255, 255, 255));
+        SetBkMode(hdc, TRANSPARENT as i32);
+
+        // Blinking circle
+        let dot_br = CreateSolidBrush(if state.blink_on {
+            colorref(200, 40, 40)
+        } else {
+            colorref(120, 120, 120)
+        });
+        let circle_size = 18;
+        let old = SelectObject(hdc, dot_br as _);
+        Ellipse(
+            hdc,
+            12,
+            (OVERLAY_HEIGHT - circle_size) / 2,
+            12 + circle_size,
+            (OVERLAY_HEIGHT - circle_size) / 2 + circle_size,
+        );
+        if old != 0 {
+            SelectObject(hdc, old);
+        }
+        DeleteObject(dot_br as _);
+
+        // Status line
+        let status = if state.recording {
+            "Recording..."
+        } else if state.processing {
+            "Processing..."
+        } else {
+            "Transcript"
+        };
+        let status_w = wide(&format!("WhisTxtR - {}", status));
+        TextOutW(hdc, 40, 14, status_w.as_ptr(), (status_w.len() - 1) as i32);
+
+        // Body text
+        let body = if state.recording {
+            if state.text.is_empty() {
+                "Listening..."
+            } else {
+                state.text.as_str()
+            }
+        } else if state.processing {
+            "Processing..."
+        } else if state.text.is_empty() {
+            "No transcript"
+        } else {
+            state.text.as_str()
+        };
+        let body_w = wide(body);
+        TextOutW(hdc, 44, 50, body_w.as_ptr(), (body_w.len() - 1) as i32);
+    }
+}
+
+fn GetSystemMetrics(index: i32) -> i32 {
+    unsafe { windows_sys::Win32::UI::WindowsAndMessaging::GetSystemMetrics(index) }
+}
+
+fn colorref(r: u8, g: u8, b: u8) -> u32 {
+    (r as u32) | ((g as u32) << 8) | ((b as u32) << 16)
+}
+
+// ----- Win32 tray (NotifyIcon) -----
+
+pub enum TrayCmd {
+    Quit,
+    ToggleStartup,
+    OpenLog,
+    /// History entry id to paste into the last active app.
+    PasteRecent(u64),
+    CopyRecent(u64),
+}
+
+pub struct Tray {
+    hwnd: HWND,
+    hmenu: HMENU,
+    cmd_rx: Receiver<TrayCmd>,
+    /// Last foreground window that was not ours or the taskbar.
+    last_target: HWND,
+    app_icon: HICON,
+    /// Shown while the microphone is open.
+    mic_icon: HICON,
+}
+
+/// Recent menu contents: history id and label. `None` hides the submenu.
+static RECENT: Mutex<Option<Vec<(u64, String)>>> = Mutex::new(None);
+
+static mut TRAY_CMD_TX: Option<Sender<TrayCmd>> = None;
+fn send_tray_cmd(cmd: TrayCmd) {
+    unsafe {
+        if let Some(tx) = TRAY_CMD_TX.as_ref() {
+            let _ = tx.send(cmd);
+        }
+    }
+}
+
+impl Tray {
+    pub fn start(startup_checked: bool) -> Result<Self, AppError> {
+        unsafe {
+            let hinstance = GetModuleHandleW(std::ptr::null());
+            let class_name = wide("WhisTrayHidden");
+            let wc = WNDCLASSW {
+                style: 0,
+                lpfnWndProc: Some(tray_wnd_proc),
+                cbClsExtra: 0,
+                cbWndExtra: 0,
+                hInstance: hinstance,
+                hIcon: LoadIconW(0, IDI_APPLICATION as *const u16),
+                hCursor: 0,
+                hbrBackground: 0 as HBRUSH,
+                lpszMenuName: std::ptr::null(),
+                lpszClassName: class_name.as_ptr(),
+            };
+            if RegisterClassW(&wc) == 0 {
+                return Err(AppError::Msg("RegisterClassW failed".into()));
+            }
+
+            let hwnd = CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                class_name.as_ptr(),
+                WS_OVERLAPPEDWINDOW,
+                CW_USEDEFAULT,
+                CW_USEDEFAULT,
+                CW_USEDEFAULT,
+                CW_USEDEFAULT,
+                0,
+                0,
+                hinstance,
+                std::ptr::null(),
+            );
+            if hwnd == 0 {
+                return Err(AppError::Msg("CreateWindowExW failed".into()));
+            }
+
+            let hmenu = CreatePopupMenu();
+            fill_menu(hmenu, startup_checked);
+
+            let (tx, rx) = mpsc::channel::<TrayCmd>();
+            TRAY_CMD_TX = Some(tx);
+
+            let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
+            nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+            nid.hWnd = hwnd;
+            nid.uID = 1;
+            nid.uFlags = NIF_MESSAGE | NIF_TIP | NIF_ICON;
+            nid.uCallbackMessage = WM_TRAYICON;
+            let app_icon = LoadImageW(
+                0,
+                IDI_APPLICATION as *const u16,
+                IMAGE_ICON,
+                TRAY_ICON_SIZE,
+                TRAY_ICON_SIZE,
+                LR_SHARED,
+            ) as isize;
+            nid.hIcon = app_icon;
+            let tip = wide(TRAY_TIP);
+            nid.szTip[..tip.len()].copy_from_slice(&tip);
+            Shell_NotifyIconW(NIM_ADD, &mut nid);
+
+            Ok(Tray {
+                hwnd,
+                hmenu,
+                cmd_rx: rx,
+                last_target: 0,
+                app_icon,
+                mic_icon: red_dot_icon(),
+            })
+        }
+    }
+
+    pub fn pump(&mut self) -> bool {
+        unsafe {
+            let mut msg: MSG = std::mem::zeroed();
+            while PeekMessageW(&mut msg, 0, 0, 0, PM_REMOVE) != 0 {
+                if msg.message == WM_QUIT as u32 {
+                    send_tray_cmd(TrayCmd::Quit);
+                    return false;
+                }
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+            let fg = GetForegroundWindow();
+            if fg != 0 && fg != self.hwnd && !is_shell_window(fg) {
+                self.last_target = fg;
+            }
+        }
+        true
+    }
+
+    /// Bring back the window the user was in before opening the tray menu.
+    pub fn focus_last_target(&self) {
+        unsafe {
+            if self.last_target != 0 && IsWindow(self.last_target) != 0 {
+                SetForegroundWindow(self.last_target);
+            }
+        }
+    }
+
+    /// Newest first. With history off the Recent submenu is left out.
+    pub fn set_recent(&self, entries: &[HistoryEntry]) {
+        let items = entries
+            .iter()
+            .map(|e| (e.id, e.label(RECENT_LABEL_CHARS)))
+            .collect();
+        *RECENT.lock() = Some(items);
+    }
+
+    /// Swap the tray icon for a red dot while the microphone is open.
+    pub fn set_mic_open(&self, open: bool) {
+        let (icon, tip) = if open {
+            (self.mic_icon, TRAY_TIP_MIC)
+        } else {
+            (self.app_icon, TRAY_TIP)
+        };
+        unsafe {
+            let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
+            nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+            nid.hWnd = self.hwnd;
+            nid.uID = 1;
+            nid.uFlags = NIF_TIP | NIF_ICON;
+            nid.hIcon = if icon != 0 { icon } else { self.app_icon };
+            let tip = wide(tip);
+            nid.szTip[..tip.len()].copy_from_slice(&tip);
+            Shell_NotifyIconW(NIM_MODIFY, &nid);
+        }
+    }
+
+    pub fn try_recv_cmd(&self) -> Option<TrayCmd> {
+        self.cmd_rx.try_recv().ok()
+    }
+
+    pub fn set_startup_checked(&self, on: bool) {
+        unsafe {
+            // Rebuild the menu label to reflect current state.
+            while RemoveMenu(self.hmenu, 0, MF_BYPOSITION) != 0 {}
+            fill_menu(self.hmenu, on);
+        }
+    }
+}
+
+/// A red dot, the colour of the overlay's recording light, on transparent.
+fn red_dot_icon() -> HICON {
+    const N: usize = TRAY_ICON_SIZE as usize;
+    // AND mask bit set = transparent; XOR data is BGRA.
+    let mut mask = [0xFFu8; N * N / 8];
+    let mut color = [0u8; N * N * 4];
+    let center = (N as f32 - 1.0) / 2.0;
+    let radius = N as f32 * 0.4;
+    for y in 0..N {
+        for x in 0..N {
+            let (dx, dy) = (x as f32 - center, y as f32 - center);
+            if dx * dx + dy * dy <= radius * radius {
+                let i = y * N + x;
+                mask[i / 8] &= !(0x80 >> (i % 8));
+                color[i * 4..i * 4 + 4].copy_from_slice(&[40, 40, 200, 255]);
+            }
+        }
+    }
+    unsafe {
+        CreateIcon(
+            GetModuleHandleW(std::ptr::null()),
+            TRAY_ICON_SIZE,
+            TRAY_ICON_SIZE,
+            1,
+            32,
+            mask.as_ptr(),
+            color.as_ptr(),
+        )
+    }
+}
+
+/// Menu items, top to bottom.
+fn fill_menu(hmenu: HMENU, startup_enabled: bool) {
+    if let Some(recent) = RECENT.lock().as_ref() {
+        unsafe {
+            let sub = CreatePopupMenu();
+            if recent.is_empty() {
+                append_menu(sub, MF_GRAYED, 0, "(empty)");
+            }
+            for (i, (_, label)) in recent.iter().enumerate() {
+                let entry = CreatePopupMenu();
+                let id = ID_TRAY_RECENT + 2 * i as u16;
+                append_menu_str(entry, id as u32, "Paste");
+                append_menu_str(entry, id as u32 + 1, "Copy");
+                append_menu(sub, MF_POPUP, entry as usize, label);
+            }
+            append_menu(hmenu, MF_POPUP, sub as usize, "Recent");
+        }
+    }
+    append_menu_str(hmenu, ID_TRAY_STARTUP as u32, startup_label(startup_enabled));
+    append_menu_str(hmenu, ID_TRAY_OPEN_LOG as u32, "Open log");
+    append_menu_str(hmenu, ID_TRAY_QUIT as u32, "Quit");
+}
+
+/// Turn a menu selection into a command for the app loop.
+fn menu_command(id: u16) {
+    match id {
+        ID_TRAY_QUIT => {
+            send_tray_cmd(TrayCmd::Quit);
+            unsafe { PostQuitMessage(0) };
+        }
+        ID_TRAY_STARTUP => send_tray_cmd(TrayCmd::ToggleStartup),
+        ID_TRAY_OPEN_LOG => send_tray_cmd(TrayCmd::OpenLog),
+        id if id >= ID_TRAY_RECENT => {
+            let offset = (id - ID_TRAY_RECENT) as usize;
+            let entry = RECENT
+                .lock()
+                .as_ref()
+                .and_then(|recent| recent.get(offset / 2).map(|(id, _)| *id));
+            match (entry, offset % 2) {
+                (Some(entry), 0) => send_tray_cmd(TrayCmd::PasteRecent(entry)),
+                (Some(entry), _) => send_tray_cmd(TrayCmd::CopyRecent(entry)),
+                (None, _) => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Taskbar and notification-area windows, which take focus when the tray is clicked.
+fn is_shell_window(hwnd: HWND) -> bool {
+    let mut buf = [0u16; 64];
+    let len = unsafe { GetClassNameW(hwnd, buf.as_mut_ptr(), buf.len() as i32) };
+    let class = String::from_utf16_lossy(&buf[..len.max(0) as usize]);
+    matches!(
+        class.as_str(),
+        "Shell_TrayWnd" | "Shell_SecondaryTrayWnd" | "NotifyIconOverflowWindow"
+            | "TopLevelWindowForOverflowXamlIsland"
+    )
+}
+
+fn startup_label(enabled: bool) -> &'static str {
+    if enabled {
+        "Disable Start with Windows"
+    } else {
+        "Enable Start with Windows"
+    }
+}
+
+extern "system" fn tray_wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_TRAYICON => {
+                if lparam as u32 == WM_RBUTTONUP {
+                    SetForegroundWindow(hwnd);
+                    // Build a fresh menu near the cursor.
+                    let hmenu = CreatePopupMenu();
+                    fill_menu(hmenu, is_startup_enabled());
+                    let mut pt = POINT { x: 0, y: 0 };
+                    GetCursorPos(&mut pt);
+                    let sel = TrackPopupMenu(
+                        hmenu,
+                        TPM_LEFTALIGN | TPM_BOTTOMALIGN | TPM_RIGHTBUTTON | TPM_RETURNCMD,
+                        pt.x,
+                        pt.y,
+                        0,
+                        hwnd,
+                        std::ptr::null(),
+                    );
+                    // Destroys the submenus too.
+                    DestroyMenu(hmenu);
+                    if sel != 0 {
+                        menu_command(sel as u16);
+                    }
+                    // Per docs, post a null message after TrackPopupMenu.
+                    PostMessageW(hwnd, WM_NULL, 0, 0);
+                }
+                0
+            }
+            WM_COMMAND => {
+                menu_command((wparam & 0xFFFF) as u16);
+                0
+            }
+            WM_DESTROY => {
+                Shell_NotifyIconW(
+                    NIM_DELETE,
+                    &mut NOTIFYICONDATAW {
+                        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+                        hWnd: hwnd,
+                        uID: 1,
+                        ..unsafe { std::mem::zeroed() }
+                    },
+                );
+                PostQuitMessage(0);
+                0
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+fn append_menu_str(hmenu: HMENU, id: u32, label: &str) {
+    append_menu(hmenu, 0, id as usize, label);
+}
+
+/// `item` is a command id, or the submenu handle with `MF_POPUP`.
+fn append_menu(hmenu: HMENU, flags: u32, item: usize, label: &str) {
+    let w = wide(label);
+    unsafe {
+        windows_sys::Win32::UI::WindowsAndMessaging::AppendMenuW(hmenu, flags, item, w.as_ptr());
+    }
+}
+
+/// Open a file with its associated application (e.g. the log in Notepad).
+pub fn open_path(path: &Path) -> Result<(), String> {
+    let file = wide(&path.to_string_lossy());
+    let verb = wide("open");
+    let result = unsafe {
+        ShellExecuteW(
+            0,
+            verb.as_ptr(),
+            file.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+    // Values up to 32 are error codes.
+    if result > 32 {
+        Ok(())
+    } else {
+        Err(format!("cannot open {} (error {result})", path.display()))
+    }
+}
+
+pub fn wide(s: &str) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    std::ffi::OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+}
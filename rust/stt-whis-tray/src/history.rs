@@ -0,0 +1,337 @@
+// ----- Dictation history -----
+//
+// Every final transcript is appended to `history.jsonl` in the data dir, one
+// JSON object per line, so text that missed its window can be pasted again
+// from the tray or found with `stt-whis-tray history`. The file is opened for
+// each append rather than held open, which lets `history purge` rewrite it
+// while the app runs; appends and purges hold a lock on `history.jsonl.lock`
+// so a purge never drops an entry written meanwhile. `history.enabled: false` stops all writes; entries older
+// than `history.retention_days` are purged on start and then hourly.
+
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Entries offered in the tray's Recent menu.
+pub const RECENT_LEN: usize = 10;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryConfig {
+    /// Keep a local history of transcripts. When off, nothing is written.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Delete entries older than this many days; 0 keeps them forever.
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+    /// Store the name of the app each transcript was typed into.
+    #[serde(default = "default_true")]
+    pub record_app: bool,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: DEFAULT_RETENTION_DAYS,
+            record_app: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_retention_days() -> u32 {
+    DEFAULT_RETENTION_DAYS
+}
+
+impl HistoryConfig {
+    /// Entries older than this are dropped; `None` keeps everything.
+    fn cutoff(&self) -> Option<DateTime<Local>> {
+        (self.retention_days > 0)
+            .then(|| Local::now() - ChronoDuration::days(i64::from(self.retention_days)))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub timestamp: DateTime<Local>,
+    pub text: String,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Process (or window class) of the target window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// False when the confidence gate kept the text from the outputs.
+    #[serde(default = "default_true")]
+    pub delivered: bool,
+}
+
+impl HistoryEntry {
+    /// One-line menu label: time and the start of the text.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn label(&self, max_chars: usize) -> String {
+        let mut text: String = self.text.chars().take(max_chars).collect();
+        if self.text.chars().count() > max_chars {
+            text.push('…');
+        }
+        format!("{}  {text}", self.timestamp.format("%H:%M"))
+    }
+}
+
+/// Append side used by the running app; keeps the newest entries in memory.
+pub struct History {
+    path: PathBuf,
+    next_id: u64,
+    recent: VecDeque<HistoryEntry>,
+    /// File size after our last write; a difference means it was rewritten.
+    file_len: u64,
+    last_purge: Option<Instant>,
+}
+
+impl History {
+    pub fn open(path: PathBuf) -> Self {
+        let mut history = Self {
+            path,
+            next_id: 1,
+            recent: VecDeque::new(),
+            file_len: 0,
+            last_purge: None,
+        };
+        history.reload();
+        history
+    }
+
+    fn reload(&mut self) {
+        let entries = load(&self.path).unwrap_or_default();
+        self.next_id = self
+            .next_id
+            .max(entries.iter().map(|e| e.id).max().unwrap_or(0) + 1);
+        let skip = entries.len().saturating_sub(RECENT_LEN);
+        self.recent = entries.into_iter().skip(skip).collect();
+        self.file_len = file_len(&self.path);
+    }
+
+    /// Store a transcript. Also runs the retention purge when it is due.
+    pub fn append(&mut self, cfg: &HistoryConfig, mut entry: HistoryEntry) -> io::Result<()> {
+        if !cfg.enabled {
+            return Ok(());
+        }
+        let _lock = lock(&self.path)?;
+        self.purge_due(cfg)?;
+        entry.id = self.next_id;
+        if !cfg.record_app {
+            entry.app = None;
+        }
+        let mut file = private_file().append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        self.file_len = file_len(&self.path);
+        self.next_id += 1;
+        self.recent.push_back(entry);
+        while self.recent.len() > RECENT_LEN {
+            self.recent.pop_front();
+        }
+        Ok(())
+    }
+
+    pub fn purge_if_due(&mut self, cfg: &HistoryConfig) -> io::Result<()> {
+        if self.last_purge.is_some_and(|t| t.elapsed() < PURGE_INTERVAL) || cfg.cutoff().is_none() {
+            return Ok(());
+        }
+        let _lock = lock(&self.path)?;
+        self.purge_due(cfg)
+    }
+
+    /// `purge_if_due` for callers that already hold the lock.
+    fn purge_due(&mut self, cfg: &HistoryConfig) -> io::Result<()> {
+        if self.last_purge.is_some_and(|t| t.elapsed() < PURGE_INTERVAL) {
+            return Ok(());
+        }
+        self.last_purge = Some(Instant::now());
+        if let Some(cutoff) = cfg.cutoff() {
+            if purge_locked(&self.path, Some(cutoff))? > 0 {
+                self.reload();
+            }
+        }
+        Ok(())
+    }
+
+    /// Newest first; empty when history is off. Picks up a `history purge`
+    /// run from the command line.
+    pub fn recent(&mut self, cfg: &HistoryConfig) -> Vec<HistoryEntry> {
+        if !cfg.enabled {
+            return Vec::new();
+        }
+        if file_len(&self.path) != self.file_len {
+            self.reload();
+        }
+        self.recent.iter().rev().cloned().collect()
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.recent.iter().find(|e| e.id == id)
+    }
+}
+
+fn file_len(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Every entry in the file, oldest first. Lines that do not parse are skipped.
+pub fn load(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str::<HistoryEntry>(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Options that create files readable by the current user only; transcripts
+/// can hold anything that was dictated.
+fn private_file() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+/// Takes the lock that serializes appends and purges across processes. It is
+/// a separate file because a purge replaces the history file itself, and it
+/// is released when the returned handle is dropped.
+fn lock(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = private_file().write(true).truncate(false).open(PathBuf::from(lock_path))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Drop entries older than `cutoff` (all of them for `None`). Returns how many
+/// were removed. The file is replaced atomically.
+pub fn purge(path: &Path, cutoff: Option<DateTime<Local>>) -> io::Result<usize> {
+    let _lock = lock(path)?;
+    purge_locked(path, cutoff)
+}
+
+fn purge_locked(path: &Path, cutoff: Option<DateTime<Local>>) -> io::Result<usize> {
+    let entries = load(path)?;
+    let keep: Vec<&HistoryEntry> = match cutoff {
+        Some(cutoff) => entries.iter().filter(|e| e.timestamp >= cutoff).collect(),
+        None => Vec::new(),
+    };
+    let removed = entries.len() - keep.len();
+    if removed == 0 {
+        return Ok(0);
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // A leftover from an interrupted purge would keep its old permissions.
+    let _ = fs::remove_file(&tmp);
+    {
+        let mut file = private_file().write(true).truncate(true).open(&tmp)?;
+        for entry in keep {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(removed)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn entry(days_ago: i64) -> HistoryEntry {
+        HistoryEntry {
+            id: 0,
+            timestamp: Local::now() - ChronoDuration::days(days_ago),
+            text: "secret".into(),
+            duration_ms: 1000,
+            model: String::new(),
+            language: None,
+            app: None,
+            delivered: true,
+        }
+    }
+
+    fn keep_forever() -> HistoryConfig {
+        HistoryConfig {
+            retention_days: 0,
+            ..HistoryConfig::default()
+        }
+    }
+
+    #[test]
+    fn files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("whistxt-history-{}", std::process::id()));
+        let path = dir.join("history.jsonl");
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        let cfg = keep_forever();
+
+        let mut history = History::open(path.clone());
+        history.append(&cfg, entry(10)).unwrap();
+        history.append(&cfg, entry(0)).unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        assert_eq!(purge(&path, Some(Local::now() - ChronoDuration::days(1))).unwrap(), 1);
+        assert_eq!(load(&path).unwrap().len(), 1);
+        assert_eq!(mode(&path), 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn purges_never_drop_concurrent_appends() {
+        let dir = std::env::temp_dir().join(format!("whistxt-history-race-{}", std::process::id()));
+        let path = dir.join("history.jsonl");
+        let cfg = keep_forever();
+        const ROUNDS: usize = 100;
+
+        // The app appends while `history purge` runs over and over, each time
+        // with an old entry to remove so the file really is rewritten.
+        let app = std::thread::spawn({
+            let (path, cfg) = (path.clone(), cfg.clone());
+            move || {
+                let mut history = History::open(path);
+                for _ in 0..ROUNDS {
+                    history.append(&cfg, entry(0)).unwrap();
+                }
+            }
+        });
+        let mut other = History::open(path.clone());
+        for _ in 0..ROUNDS {
+            other.append(&cfg, entry(10)).unwrap();
+            assert_eq!(purge(&path, Some(Local::now() - ChronoDuration::days(1))).unwrap(), 1);
+        }
+        app.join().unwrap();
+
+        assert_eq!(load(&path).unwrap().len(), ROUNDS);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
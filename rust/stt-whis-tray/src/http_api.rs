@@ -0,0 +1,617 @@
+// ----- OpenAI-compatible HTTP API (`serve --bind`) -----
+//
+// `POST /v1/audio/transcriptions` with a multipart form, like OpenAI's
+// endpoint: `file` (any format `audio_file` reads), plus optional `model`,
+// `language`, `prompt` and `response_format` (json, text, srt, vtt,
+// verbose_json). Requests decode on their own threads with the speech
+// runtime's engine, so they share its loaded models without waiting for a
+// dictation. Plain HTTP only; bind to loopback unless the network is trusted.
+// `/v1/audio/stream` is a WebSocket for live transcription (see `ws_api`).
+//
+// The API's own listener takes every connection. WebSocket sessions need
+// their socket to themselves, so upgrades to the stream are served here;
+// all other connections are relayed to tiny_http on a loopback port.
+
+use crate::audio_file::read_audio_bytes;
+use crate::engine::DecodeOptions;
+use crate::ws_api;
+use crate::{max_samples, network_model_path, resample_to_16k, shared_engine, Segment, SharedConfig, SpeechCommand, TranscriptMeta};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+const TRANSCRIPTIONS: &str = "/v1/audio/transcriptions";
+/// Same upload limit as OpenAI.
+const MAX_UPLOAD: usize = 25 * 1024 * 1024;
+/// Longest a WebSocket upgrade request may be.
+const MAX_HEAD: usize = 8 * 1024;
+/// How long a new connection has to send its request line, and an upgrade
+/// its headers.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve on `bind`; the address actually bound (for port 0).
+pub fn start(bind: SocketAddr, speech_tx: Sender<SpeechCommand>, config: SharedConfig) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(bind).map_err(|e| format!("{bind}: {e}"))?;
+    let bind = listener.local_addr().unwrap_or(bind);
+    let server = Server::http("127.0.0.1:0").map_err(|e| format!("HTTP server: {e}"))?;
+    let inner = server.server_addr().to_ip().ok_or("HTTP server: no TCP address")?;
+    info!("HTTP API listening on http://{bind}{TRANSCRIPTIONS}");
+    let (request_tx, request_config) = (speech_tx.clone(), config.clone());
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let speech_tx = request_tx.clone();
+            let config = request_config.clone();
+            thread::spawn(move || handle(request, &speech_tx, &config));
+        }
+    });
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let speech_tx = speech_tx.clone();
+            let config = config.clone();
+            thread::spawn(move || {
+                if let Err(e) = route(stream, inner, &speech_tx, &config) {
+                    info!("HTTP client went away: {e}");
+                }
+            });
+        }
+    });
+    Ok(bind)
+}
+
+/// Serve a `GET` of `ws_api::STREAM` here and relay anything else to
+/// tiny_http at `inner`.
+fn route(stream: TcpStream, inner: SocketAddr, speech_tx: &Sender<SpeechCommand>, config: &SharedConfig) -> io::Result<()> {
+    stream.set_read_timeout(Some(HEAD_TIMEOUT))?;
+    if is_stream_request(&stream)? {
+        return upgrade(stream, speech_tx, config);
+    }
+    stream.set_read_timeout(None)?;
+    relay(stream, inner)
+}
+
+/// Whether the request line starts with `GET <STREAM>`. Only peeks, so a
+/// relayed connection still sends the whole request.
+fn is_stream_request(stream: &TcpStream) -> io::Result<bool> {
+    let prefix = format!("GET {}", ws_api::STREAM).into_bytes();
+    let mut buf = [0; 64];
+    let n = peek_until(stream, &mut buf, |seen| {
+        seen.len() > prefix.len() || !prefix.starts_with(seen) || seen.contains(&b'\n')
+    })?;
+    let seen = &buf[..n];
+    Ok(seen.starts_with(&prefix) && matches!(seen.get(prefix.len()), Some(b' ' | b'?')))
+}
+
+/// Peek into `buf` until `done` says enough arrived, the client stops
+/// sending, `buf` is full or HEAD_TIMEOUT passes; how much is there.
+fn peek_until(stream: &TcpStream, buf: &mut [u8], done: impl Fn(&[u8]) -> bool) -> io::Result<usize> {
+    let deadline = Instant::now() + HEAD_TIMEOUT;
+    loop {
+        let n = stream.peek(buf)?;
+        if n == 0 || n == buf.len() || done(&buf[..n]) || Instant::now() > deadline {
+            return Ok(n);
+        }
+        // Peeking returns at once while the rest is on its way.
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Read an upgrade request's head and start the live session, or refuse.
+fn upgrade(mut stream: TcpStream, speech_tx: &Sender<SpeechCommand>, config: &SharedConfig) -> io::Result<()> {
+    // Take the head and nothing past it; the client sends no frames before
+    // the answer anyway.
+    let mut head = vec![0; MAX_HEAD];
+    let seen = peek_until(&stream, &mut head, |seen| find(seen, b"\r\n\r\n", 0).is_some())?;
+    let Some(end) = find(&head[..seen], b"\r\n\r\n", 0) else {
+        return Reply::error(431, "request head too large").write_to(&mut stream, ws_api::STREAM);
+    };
+    head.truncate(end + 4);
+    stream.read_exact(&mut head)?;
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let url = lines.next().and_then(|l| l.split(' ').nth(1)).unwrap_or_default();
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    match ws_api::accept(url, &headers, speech_tx, config) {
+        Ok(session) => session.run(stream),
+        Err((status, message)) => Reply::error(status, message).write_to(&mut stream, ws_api::STREAM),
+    }
+}
+
+/// Copy bytes both ways between `client` and tiny_http until both sides
+/// are done.
+fn relay(client: TcpStream, inner: SocketAddr) -> io::Result<()> {
+    let server = TcpStream::connect(inner)?;
+    let (mut from_client, mut to_server) = (client.try_clone()?, server.try_clone()?);
+    let upload = thread::spawn(move || {
+        let _ = io::copy(&mut from_client, &mut to_server);
+        let _ = to_server.shutdown(Shutdown::Write);
+    });
+    let (mut from_server, mut to_client) = (server, client);
+    let result = io::copy(&mut from_server, &mut to_client);
+    let _ = to_client.shutdown(Shutdown::Both);
+    let _ = upload.join();
+    result.map(drop)
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    /// An error in OpenAI's shape.
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        let kind = if status < 500 { "invalid_request_error" } else { "server_error" };
+        let body = json!({
+            "error": { "message": message.into(), "type": kind, "param": null, "code": null }
+        });
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    /// Send the reply outside tiny_http, closing the connection.
+    fn write_to(&self, stream: &mut TcpStream, path: &str) -> io::Result<()> {
+        if self.status >= 400 {
+            warn!("HTTP {} {path}: {}", self.status, self.body);
+        }
+        let reason = StatusCode(self.status).default_reason_phrase();
+        write!(
+            stream,
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+fn handle(mut request: Request, speech_tx: &Sender<SpeechCommand>, config: &SharedConfig) {
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    let reply = match (request.method(), path.as_str()) {
+        // `route` serves GETs.
+        (_, ws_api::STREAM) => Reply::error(405, "use GET with a WebSocket upgrade"),
+        (Method::Post, TRANSCRIPTIONS) => transcribe(&mut request, speech_tx, config).unwrap_or_else(|e| e),
+        (_, TRANSCRIPTIONS) => Reply::error(405, "use POST"),
+        _ => Reply::error(404, format!("no route for {path}")),
+    };
+    if reply.status >= 400 {
+        warn!("HTTP {} {path}: {}", reply.status, reply.body);
+    }
+    let content_type = Header::from_bytes("Content-Type", reply.content_type).expect("static header");
+    let response = Response::from_string(reply.body)
+        .with_status_code(reply.status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        info!("HTTP client went away: {e}");
+    }
+}
+
+fn transcribe(
+    request: &mut Request,
+    speech_tx: &Sender<SpeechCommand>,
+    config: &SharedConfig,
+) -> Result<Reply, Reply> {
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_string())
+        .unwrap_or_default();
+    let boundary = boundary(&content_type).ok_or_else(|| Reply::error(400, "expected multipart/form-data"))?;
+    if request.body_length().is_some_and(|n| n > MAX_UPLOAD) {
+        return Err(Reply::error(413, "upload is larger than 25 MB"));
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_UPLOAD as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| Reply::error(400, format!("reading the body: {e}")))?;
+    if body.len() > MAX_UPLOAD {
+        return Err(Reply::error(413, "upload is larger than 25 MB"));
+    }
+    let form = parse_multipart(&body, &boundary).map_err(|e| Reply::error(400, e))?;
+    let field = |name: &str| {
+        form.iter()
+            .find(|p| p.name == name)
+            .map(|p| String::from_utf8_lossy(&p.data).trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let file = form
+        .iter()
+        .find(|p| p.name == "file")
+        .ok_or_else(|| Reply::error(400, "missing form field `file`"))?;
+    let format = match field("response_format").as_deref().unwrap_or("json") {
+        "json" => Format::Json,
+        "text" => Format::Text,
+        "srt" => Format::Srt,
+        "vtt" => Format::Vtt,
+        "verbose_json" => Format::VerboseJson,
+        other => return Err(Reply::error(400, format!("unknown response_format `{other}`"))),
+    };
+
+    let cfg = config.get();
+    // Names that are not local models (e.g. `whisper-1`) get the configured one.
+    let model_path = network_model_path(field("model").as_deref(), &cfg);
+    let language = match field("language").as_deref() {
+        None => cfg.language.clone(),
+        Some("auto") => None,
+        Some(code) => Some(code.to_string()),
+    };
+    let (samples, sample_rate) =
+        read_audio_bytes(&file.data, max_samples).map_err(|e| Reply::error(400, format!("unreadable audio: {e}")))?;
+
+    let opts = DecodeOptions {
+        language,
+        prompt: field("prompt"),
+        ..DecodeOptions::default()
+    };
+    let engine = shared_engine(speech_tx, &cfg.engine).ok_or_else(|| Reply::error(503, "speech runtime stopped"))?;
+    let (text, meta) = engine
+        .transcribe(&model_path, &resample_to_16k(&samples, sample_rate), &opts)
+        .map_err(|e| Reply::error(500, e))?;
+    Ok(format.render(&text, &meta))
+}
+
+enum Format {
+    Json,
+    Text,
+    Srt,
+    Vtt,
+    VerboseJson,
+}
+
+impl Format {
+    fn render(&self, text: &str, meta: &TranscriptMeta) -> Reply {
+        match self {
+            Format::Json => Reply::ok("application/json", json!({ "text": text }).to_string()),
+            Format::Text => Reply::ok("text/plain; charset=utf-8", format!("{text}\n")),
+            Format::Srt => {
+                let mut out = String::new();
+                for (i, s) in segments(text, meta).iter().enumerate() {
+                    out.push_str(&format!(
+                        "{}\n{} --> {}\n{}\n\n",
+                        i + 1,
+                        timestamp(s.start, ','),
+                        timestamp(s.end, ','),
+                        s.text
+                    ));
+                }
+                Reply::ok("text/plain; charset=utf-8", out)
+            }
+            Format::Vtt => {
+                let mut out = String::from("WEBVTT\n\n");
+                for s in segments(text, meta) {
+                    out.push_str(&format!(
+                        "{} --> {}\n{}\n\n",
+                        timestamp(s.start, '.'),
+                        timestamp(s.end, '.'),
+                        s.text
+                    ));
+                }
+                Reply::ok("text/vtt; charset=utf-8", out)
+            }
+            Format::VerboseJson => {
+                let segments: Vec<Value> = segments(text, meta)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| {
+                        json!({
+                            "id": i,
+                            "start": s.start.as_secs_f64(),
+                            "end": s.end.as_secs_f64(),
+                            "text": s.text,
+                        })
+                    })
+                    .collect();
+                let body = json!({
+                    "task": "transcribe",
+                    "language": meta.language,
+                    "duration": meta.audio_duration.as_secs_f64(),
+                    "text": text,
+                    "segments": segments,
+                });
+                Reply::ok("application/json", body.to_string())
+            }
+        }
+    }
+}
+
+/// The engine's segments, or the whole text as one when it gave none.
+fn segments(text: &str, meta: &TranscriptMeta) -> Vec<Segment> {
+    if !meta.segments.is_empty() || text.is_empty() {
+        return meta.segments.clone();
+    }
+    vec![Segment {
+        start: Duration::ZERO,
+        end: meta.audio_duration,
+        text: text.to_string(),
+    }]
+}
+
+/// `HH:MM:SS,mmm` (SRT) or `HH:MM:SS.mmm` (WebVTT).
+fn timestamp(t: Duration, separator: char) -> String {
+    let ms = t.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{separator}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';').map(str::trim);
+    if !params.next()?.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .filter(|b| !b.is_empty())
+}
+
+struct Part {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// Named parts of a multipart/form-data body (RFC 7578).
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{boundary}").into_bytes();
+    let next_delimiter = [b"\r\n".as_slice(), &delimiter].concat();
+    let mut pos = find(body, &delimiter, 0).ok_or("no multipart boundary in the body")? + delimiter.len();
+    let mut parts = Vec::new();
+    // A boundary followed by `--` closes the body.
+    while !body[pos..].starts_with(b"--") {
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err("malformed multipart body".into());
+        }
+        let headers_start = pos + 2;
+        let headers_end = find(body, b"\r\n\r\n", headers_start).ok_or("malformed part headers")?;
+        let data_start = headers_end + 4;
+        let data_end = find(body, &next_delimiter, data_start).ok_or("unterminated multipart body")?;
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        let name = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("content-disposition"))
+            .and_then(|(_, value)| {
+                value
+                    .split(';')
+                    .filter_map(|p| p.trim().split_once('='))
+                    .find(|(key, _)| *key == "name")
+                    .map(|(_, v)| v.trim_matches('"').to_string())
+            });
+        if let Some(name) = name {
+            parts.push(Part {
+                name,
+                data: body[data_start..data_end].to_vec(),
+            });
+        }
+        pos = data_end + next_delimiter.len();
+    }
+    Ok(parts)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mock_service;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    const BOUNDARY: &str = "XyZboundary";
+
+    fn server() -> SocketAddr {
+        let (speech_tx, config) = mock_service(&["One. Two."]);
+        start("127.0.0.1:0".parse().unwrap(), speech_tx, config).unwrap()
+    }
+
+    /// One second of a 16 kHz mono 16-bit WAV tone.
+    fn wav() -> Vec<u8> {
+        long_wav(1)
+    }
+
+    fn long_wav(seconds: usize) -> Vec<u8> {
+        let samples: Vec<i16> = (0..16_000 * seconds).map(|i| ((i as f32 * 0.05).sin() * 8_000.0) as i16).collect();
+        let data_len = samples.len() as u32 * 2;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&16_000u32.to_le_bytes());
+        out.extend_from_slice(&32_000u32.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for s in samples {
+            out.extend_from_slice(&s.to_le_bytes());
+        }
+        out
+    }
+
+    fn form(fields: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, data) in fields {
+            let filename = if *name == "file" { "; filename=\"a.wav\"" } else { "" };
+            write!(body, "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"{filename}\r\n\r\n").unwrap();
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        write!(body, "--{BOUNDARY}--\r\n").unwrap();
+        body
+    }
+
+    /// Send a raw request; the status and body of the response.
+    fn request(addr: SocketAddr, head: &str, body: &[u8]) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        write!(stream, "{head}Connection: close\r\n\r\n").unwrap();
+        // The server may answer before reading everything.
+        let _ = stream.write_all(body);
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response).into_owned();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").map(|(_, b)| b.to_string()).unwrap_or_default();
+        (status, body)
+    }
+
+    fn post(addr: SocketAddr, content_type: &str, body: &[u8]) -> (u16, String) {
+        let head = format!(
+            "POST {TRANSCRIPTIONS} HTTP/1.1\r\nHost: test\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        request(addr, &head, body)
+    }
+
+    fn post_form(addr: SocketAddr, fields: &[(&str, &[u8])]) -> (u16, String) {
+        post(addr, &format!("multipart/form-data; boundary={BOUNDARY}"), &form(fields))
+    }
+
+    fn error_message(body: &str) -> String {
+        let json: Value = serde_json::from_str(body).unwrap();
+        json["error"]["message"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn json_is_the_default() {
+        let addr = server();
+        let (status, body) = post_form(addr, &[("file", &wav()), ("model", b"whisper-1")]);
+        assert_eq!(status, 200, "{body}");
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "text": "One. Two." }));
+    }
+
+    #[test]
+    fn text() {
+        let (status, body) = post_form(server(), &[("file", &wav()), ("response_format", b"text")]);
+        assert_eq!((status, body.as_str()), (200, "One. Two.\n"));
+    }
+
+    #[test]
+    fn srt() {
+        let (status, body) = post_form(server(), &[("file", &wav()), ("response_format", b"srt")]);
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            "1\n00:00:00,000 --> 00:00:00,500\nOne.\n\n2\n00:00:00,500 --> 00:00:01,000\nTwo.\n\n"
+        );
+    }
+
+    #[test]
+    fn vtt() {
+        let (status, body) = post_form(server(), &[("file", &wav()), ("response_format", b"vtt")]);
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            "WEBVTT\n\n00:00:00.000 --> 00:00:00.500\nOne.\n\n00:00:00.500 --> 00:00:01.000\nTwo.\n\n"
+        );
+    }
+
+    #[test]
+    fn verbose_json() {
+        let (status, body) = post_form(
+            server(),
+            &[("file", &wav()), ("response_format", b"verbose_json"), ("language", b"de")],
+        );
+        assert_eq!(status, 200);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["task"], "transcribe");
+        assert_eq!(json["language"], "de");
+        assert_eq!(json["duration"], 1.0);
+        assert_eq!(json["text"], "One. Two.");
+        assert_eq!(
+            json["segments"],
+            json!([
+                { "id": 0, "start": 0.0, "end": 0.5, "text": "One." },
+                { "id": 1, "start": 0.5, "end": 1.0, "text": "Two." },
+            ])
+        );
+    }
+
+    #[test]
+    fn long_audio_is_cut_at_two_minutes() {
+        let (status, body) = post_form(server(), &[("file", &long_wav(150)), ("response_format", b"verbose_json")]);
+        assert_eq!(status, 200, "{body}");
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["duration"], 120.0);
+    }
+
+    #[test]
+    fn oversized_upload_is_refused() {
+        let head = format!(
+            "POST {TRANSCRIPTIONS} HTTP/1.1\r\nHost: test\r\nContent-Type: multipart/form-data; boundary={BOUNDARY}\r\nContent-Length: {}\r\n",
+            MAX_UPLOAD + 1
+        );
+        let (status, body) = request(server(), &head, b"");
+        assert_eq!(status, 413);
+        assert_eq!(error_message(&body), "upload is larger than 25 MB");
+    }
+
+    #[test]
+    fn bad_requests() {
+        let addr = server();
+        let (status, body) = post(addr, "application/json", b"{}");
+        assert_eq!((status, error_message(&body).as_str()), (400, "expected multipart/form-data"));
+
+        let (status, body) = post_form(addr, &[("model", b"whisper-1")]);
+        assert_eq!((status, error_message(&body).as_str()), (400, "missing form field `file`"));
+
+        let (status, body) = post_form(addr, &[("file", &wav()), ("response_format", b"yaml")]);
+        assert_eq!((status, error_message(&body).as_str()), (400, "unknown response_format `yaml`"));
+
+        let (status, body) = post_form(addr, &[("file", b"not audio")]);
+        assert_eq!(status, 400);
+        assert!(error_message(&body).starts_with("unreadable audio: "), "{body}");
+
+        let (status, body) = post(addr, &format!("multipart/form-data; boundary={BOUNDARY}"), b"garbage");
+        assert_eq!((status, error_message(&body).as_str()), (400, "no multipart boundary in the body"));
+    }
+
+    #[test]
+    fn other_routes() {
+        let addr = server();
+        let (status, _) = request(addr, &format!("GET {TRANSCRIPTIONS} HTTP/1.1\r\nHost: test\r\n"), b"");
+        assert_eq!(status, 405);
+        let (status, _) = request(addr, "GET /nope HTTP/1.1\r\nHost: test\r\n", b"");
+        assert_eq!(status, 404);
+        let (status, body) = request(addr, &format!("GET {} HTTP/1.1\r\nHost: test\r\n", ws_api::STREAM), b"");
+        assert_eq!((status, error_message(&body).as_str()), (400, "expected a WebSocket upgrade"));
+        let (status, _) = request(addr, &format!("POST {} HTTP/1.1\r\nHost: test\r\n", ws_api::STREAM), b"");
+        assert_eq!(status, 405);
+    }
+}
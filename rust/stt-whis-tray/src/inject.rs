@@ -0,0 +1,748 @@
+// ----- Text injection (clipboard paste / synthetic keystrokes) -----
+//
+// The strategy is resolved per injection from the foreground window: the
+// global defaults in `InjectionConfig` are overridden field-by-field by the
+// first rule whose window class / process name matches.
+
+use crate::clipboard::{paste_text, ClipboardConfig};
+use enigo::{Enigo, Key, KeyboardControllable};
+use log::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_PRE_DELAY_MS: u64 = 30;
+const DEFAULT_PASTE_CHORD: &str = "Ctrl+V";
+const DEFAULT_CHUNK_DELAY_MS: u64 = 15;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectMode {
+    Clipboard,
+    Keystroke,
+    /// Never inject into matching windows (e.g. password managers).
+    Disabled,
+}
+
+impl Default for InjectMode {
+    fn default() -> Self {
+        InjectMode::Clipboard
+    }
+}
+
+/// How previously injected text is removed before a corrected version goes in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplaceMode {
+    /// One Backspace per character.
+    #[default]
+    Backspace,
+    /// Shift+Left over the old text; the new text overwrites the selection.
+    Select,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct InjectionConfig {
+    /// Wait before injecting so focus can return after the hotkey release.
+    #[serde(default = "default_pre_delay_ms")]
+    pub pre_delay_ms: u64,
+    /// Key chord sent after filling the clipboard, e.g. "Ctrl+V" or "Ctrl+Shift+V".
+    #[serde(default = "default_paste_chord")]
+    pub paste_chord: String,
+    /// Delay after every typed character in keystroke mode (0 = none).
+    #[serde(default)]
+    pub char_delay_ms: u64,
+    /// Characters typed per burst in keystroke mode (0 = whole text at once).
+    #[serde(default)]
+    pub chunk_size: usize,
+    /// Pause between keystroke bursts when `chunk_size` is set.
+    #[serde(default = "default_chunk_delay_ms")]
+    pub chunk_delay_ms: u64,
+    #[serde(default)]
+    pub clipboard: ClipboardConfig,
+    /// Per-application overrides; the first matching rule wins.
+    #[serde(default)]
+    pub rules: Vec<InjectRule>,
+}
+
+impl Default for InjectionConfig {
+    fn default() -> Self {
+        Self {
+            pre_delay_ms: DEFAULT_PRE_DELAY_MS,
+            paste_chord: DEFAULT_PASTE_CHORD.to_string(),
+            char_delay_ms: 0,
+            chunk_size: 0,
+            chunk_delay_ms: DEFAULT_CHUNK_DELAY_MS,
+            clipboard: ClipboardConfig::default(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+fn default_pre_delay_ms() -> u64 {
+    DEFAULT_PRE_DELAY_MS
+}
+
+fn default_paste_chord() -> String {
+    DEFAULT_PASTE_CHORD.to_string()
+}
+
+fn default_chunk_delay_ms() -> u64 {
+    DEFAULT_CHUNK_DELAY_MS
+}
+
+/// Matches on the foreground window; patterns are case-insensitive and may use `*`.
+/// A rule with neither `window_class` nor `process_name` never matches.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct InjectRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_class: Option<String>,
+    /// Executable name (`WindowsTerminal.exe`) on Windows, process comm on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<InjectMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paste_chord: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub char_delay_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_delay_ms: Option<u64>,
+}
+
+impl InjectRule {
+    fn matches(&self, win: &ForegroundWindow) -> bool {
+        if self.window_class.is_none() && self.process_name.is_none() {
+            return false;
+        }
+        let class_ok = self
+            .window_class
+            .as_deref()
+            .is_none_or(|p| glob_match(p, &win.class));
+        let process_ok = self
+            .process_name
+            .as_deref()
+            .is_none_or(|p| glob_match(p, &win.process));
+        class_ok && process_ok
+    }
+}
+
+/// Fully resolved strategy for one injection.
+#[derive(Clone, Debug)]
+pub struct InjectPlan {
+    pub mode: InjectMode,
+    pub paste_chord: String,
+    pub char_delay: Duration,
+    pub chunk_size: usize,
+    pub chunk_delay: Duration,
+    pub pre_delay: Duration,
+}
+
+impl InjectPlan {
+    pub fn resolve(mode: &InjectMode, cfg: &InjectionConfig, win: &ForegroundWindow) -> Self {
+        let rule = cfg.rules.iter().find(|r| r.matches(win));
+        let rule = rule.cloned().unwrap_or_default();
+        Self {
+            mode: rule.mode.unwrap_or_else(|| mode.clone()),
+            paste_chord: rule.paste_chord.unwrap_or_else(|| cfg.paste_chord.clone()),
+            char_delay: Duration::from_millis(rule.char_delay_ms.unwrap_or(cfg.char_delay_ms)),
+            chunk_size: rule.chunk_size.unwrap_or(cfg.chunk_size),
+            chunk_delay: Duration::from_millis(cfg.chunk_delay_ms),
+            pre_delay: Duration::from_millis(rule.pre_delay_ms.unwrap_or(cfg.pre_delay_ms)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForegroundWindow {
+    pub class: String,
+    pub process: String,
+}
+
+pub fn inject_text(text: &str, mode: &InjectMode, cfg: &InjectionConfig) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    let win = foreground_window();
+    let plan = InjectPlan::resolve(mode, cfg, &win);
+    info!(
+        "inject: class={:?} process={:?} mode={:?} chord={}",
+        win.class, win.process, plan.mode, plan.paste_chord
+    );
+    thread::sleep(plan.pre_delay);
+    inject_with_plan(text, &plan, cfg)
+}
+
+/// Remove the `old_chars` characters just before the caret, then inject
+/// `text` in their place. Nothing is erased in windows where injection is
+/// disabled.
+pub fn replace_text(
+    old_chars: usize,
+    text: &str,
+    how: ReplaceMode,
+    mode: &InjectMode,
+    cfg: &InjectionConfig,
+) -> Result<(), String> {
+    let win = foreground_window();
+    let plan = InjectPlan::resolve(mode, cfg, &win);
+    info!(
+        "replace: class={:?} process={:?} mode={:?} erase={old_chars} via {how:?}",
+        win.class, win.process, plan.mode
+    );
+    if plan.mode == InjectMode::Disabled {
+        return Ok(());
+    }
+    thread::sleep(plan.pre_delay);
+    let mut enigo = Enigo::new();
+    match how {
+        ReplaceMode::Backspace => {
+            for _ in 0..old_chars {
+                enigo.key_click(Key::Backspace);
+            }
+        }
+        ReplaceMode::Select => {
+            enigo.key_down(Key::Shift);
+            for _ in 0..old_chars {
+                enigo.key_click(Key::LeftArrow);
+            }
+            enigo.key_up(Key::Shift);
+            // A selection with nothing to overwrite it still has to go.
+            if text.trim().is_empty() && old_chars > 0 {
+                enigo.key_click(Key::Backspace);
+            }
+        }
+    }
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    inject_with_plan(text, &plan, cfg)
+}
+
+fn inject_with_plan(text: &str, plan: &InjectPlan, cfg: &InjectionConfig) -> Result<(), String> {
+    match plan.mode {
+        InjectMode::Clipboard => inject_via_clipboard(text, plan, &cfg.clipboard),
+        InjectMode::Keystroke => inject_via_keystrokes(text, plan),
+        InjectMode::Disabled => Ok(()),
+    }
+}
+
+fn inject_via_clipboard(text: &str, plan: &InjectPlan, cfg: &ClipboardConfig) -> Result<(), String> {
+    let chord = parse_chord(&plan.paste_chord)?;
+    paste_text(text, cfg, || {
+        let mut enigo = Enigo::new();
+        send_chord(&mut enigo, &chord);
+    })
+}
+
+fn inject_via_keystrokes(text: &str, plan: &InjectPlan) -> Result<(), String> {
+    let mut enigo = Enigo::new();
+    let chars: Vec<char> = text.chars().collect();
+    let chunk = if plan.chunk_size == 0 {
+        chars.len().max(1)
+    } else {
+        plan.chunk_size
+    };
+    for (i, burst) in chars.chunks(chunk).enumerate() {
+        if i > 0 {
+            thread::sleep(plan.chunk_delay);
+        }
+        if plan.char_delay.is_zero() {
+            enigo.key_sequence(&burst.iter().collect::<String>());
+        } else {
+            for c in burst {
+                let mut buf = [0u8; 4];
+                enigo.key_sequence(c.encode_utf8(&mut buf));
+                thread::sleep(plan.char_delay);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Modifiers held while `key` is clicked.
+struct Chord {
+    modifiers: Vec<Key>,
+    key: Key,
+}
+
+fn parse_chord(spec: &str) -> Result<Chord, String> {
+    let mut modifiers = Vec::new();
+    let mut key = None;
+    for part in spec.split('+').map(str::trim).filter(|p| !p.is_empty()) {
+        let k = match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => {
+                modifiers.push(Key::Control);
+                continue;
+            }
+            "shift" => {
+                modifiers.push(Key::Shift);
+                continue;
+            }
+            "alt" => {
+                modifiers.push(Key::Alt);
+                continue;
+            }
+            "win" | "meta" | "super" | "cmd" => {
+                modifiers.push(Key::Meta);
+                continue;
+            }
+            "enter" | "return" => Key::Return,
+            "tab" => Key::Tab,
+            "space" => Key::Space,
+            other if other.chars().count() == 1 => Key::Layout(other.chars().next().unwrap()),
+            other => return Err(format!("unsupported key '{other}' in chord '{spec}'")),
+        };
+        if key.replace(k).is_some() {
+            return Err(format!("chord '{spec}' has more than one non-modifier key"));
+        }
+    }
+    let key = key.ok_or_else(|| format!("chord '{spec}' has no key"))?;
+    Ok(Chord { modifiers, key })
+}
+
+fn send_chord(enigo: &mut Enigo, chord: &Chord) {
+    for m in &chord.modifiers {
+        enigo.key_down(*m);
+    }
+    enigo.key_click(chord.key);
+    for m in chord.modifiers.iter().rev() {
+        enigo.key_up(*m);
+    }
+}
+
+/// Case-insensitive match where `*` stands for any run of characters.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let value = value.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == value;
+    }
+    let mut rest = value.as_str();
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            continue;
+        }
+        if i == 0 {
+            match rest.strip_prefix(part) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+        } else if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+#[cfg(windows)]
+pub fn foreground_window() -> ForegroundWindow {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+        PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GetClassNameW, GetForegroundWindow, GetWindowThreadProcessId,
+    };
+
+    let mut out = ForegroundWindow::default();
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd == 0 {
+            return out;
+        }
+        let mut class_buf = [0u16; 256];
+        let n = GetClassNameW(hwnd, class_buf.as_mut_ptr(), class_buf.len() as i32);
+        if n > 0 {
+            out.class = String::from_utf16_lossy(&class_buf[..n as usize]);
+        }
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, &mut pid);
+        if pid == 0 {
+            return out;
+        }
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process == 0 {
+            return out;
+        }
+        let mut path_buf = [0u16; 1024];
+        let mut len = path_buf.len() as u32;
+        if QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, path_buf.as_mut_ptr(), &mut len)
+            != 0
+        {
+            let path = String::from_utf16_lossy(&path_buf[..len as usize]);
+            out.process = path.rsplit('\\').next().unwrap_or_default().to_string();
+        }
+        CloseHandle(process);
+    }
+    out
+}
+
+/// X11 lookup via `_NET_ACTIVE_WINDOW`; class is the `WM_CLASS` class part and
+/// process the `/proc/<pid>/comm` of `_NET_WM_PID`.
+#[cfg(target_os = "linux")]
+pub fn foreground_window() -> ForegroundWindow {
+    use std::ffi::CStr;
+    use std::os::raw::{c_int, c_uchar, c_ulong};
+    use x11::xlib;
+
+    unsafe fn window_property(
+        display: *mut xlib::Display,
+        window: xlib::Window,
+        name: &CStr,
+        kind: xlib::Atom,
+    ) -> Option<c_ulong> {
+        let atom = xlib::XInternAtom(display, name.as_ptr(), xlib::True);
+        if atom == 0 {
+            return None;
+        }
+        let mut actual_type: xlib::Atom = 0;
+        let mut actual_format: c_int = 0;
+        let mut n_items: c_ulong = 0;
+        let mut bytes_after: c_ulong = 0;
+        let mut prop: *mut c_uchar = std::ptr::null_mut();
+        let status = xlib::XGetWindowProperty(
+            display,
+            window,
+            atom,
+            0,
+            1,
+            xlib::False,
+            kind,
+            &mut actual_type,
+            &mut actual_format,
+            &mut n_items,
+            &mut bytes_after,
+            &mut prop,
+        );
+        if status != xlib::Success as c_int || prop.is_null() {
+            return None;
+        }
+        // 32-bit format properties are returned as an array of C longs.
+        let value = if n_items > 0 && actual_format == 32 {
+            Some(*(prop as *const c_ulong))
+        } else {
+            None
+        };
+        xlib::XFree(prop as *mut _);
+        value
+    }
+
+    let mut out = ForegroundWindow::default();
+    unsafe {
+        let display = xlib::XOpenDisplay(std::ptr::null());
+        if display.is_null() {
+            return out;
+        }
+        let root = xlib::XDefaultRootWindow(display);
+        if let Some(active) =
+            window_property(display, root, c"_NET_ACTIVE_WINDOW", xlib::XA_WINDOW)
+        {
+            let mut hint = xlib::XClassHint {
+                res_name: std::ptr::null_mut(),
+                res_class: std::ptr::null_mut(),
+            };
+            if xlib::XGetClassHint(display, active, &mut hint) != 0 {
+                if !hint.res_class.is_null() {
+                    out.class = CStr::from_ptr(hint.res_class).to_string_lossy().into_owned();
+                    xlib::XFree(hint.res_class as *mut _);
+                }
+                if !hint.res_name.is_null() {
+                    xlib::XFree(hint.res_name as *mut _);
+                }
+            }
+            if let Some(pid) = window_property(display, active, c"_NET_WM_PID", xlib::XA_CARDINAL)
+            {
+                if let Ok(comm) = std::fs::read_to_string(format!("/proc/{pid}/comm")) {
+                    out.process = comm.trim().to_string();
+                }
+            }
+        }
+        xlib::XCloseDisplay(display);
+    }
+    out
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn foreground_window() -> ForegroundWindow {
+    ForegroundWindow::default()
+}
+
+// Sending keys needs a desktop session. On Linux, the ignored test
+// `rules_pick_the_mode_for_the_focused_x_window` types into a window of its
+// own on an Xvfb display: `xvfb-run cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(class: &str, process: &str) -> ForegroundWindow {
+        ForegroundWindow {
+            class: class.to_string(),
+            process: process.to_string(),
+        }
+    }
+
+    fn rule(class: Option<&str>, process: Option<&str>, mode: InjectMode) -> InjectRule {
+        InjectRule {
+            window_class: class.map(str::to_string),
+            process_name: process.map(str::to_string),
+            mode: Some(mode),
+            ..InjectRule::default()
+        }
+    }
+
+    #[test]
+    fn resolve_without_rules_uses_defaults() {
+        let cfg = InjectionConfig::default();
+        let plan = InjectPlan::resolve(&InjectMode::Keystroke, &cfg, &window("Notepad", "notepad.exe"));
+        assert_eq!(plan.mode, InjectMode::Keystroke);
+        assert_eq!(plan.paste_chord, DEFAULT_PASTE_CHORD);
+        assert_eq!(plan.pre_delay, Duration::from_millis(DEFAULT_PRE_DELAY_MS));
+        assert_eq!(plan.chunk_delay, Duration::from_millis(DEFAULT_CHUNK_DELAY_MS));
+        assert_eq!(plan.chunk_size, 0);
+        assert!(plan.char_delay.is_zero());
+    }
+
+    #[test]
+    fn resolve_first_matching_rule_wins() {
+        let cfg = InjectionConfig {
+            rules: vec![
+                rule(None, Some("keepass*"), InjectMode::Disabled),
+                rule(Some("Chrome_WidgetWin_1"), Some("slack.exe"), InjectMode::Keystroke),
+                rule(Some("Chrome_*"), None, InjectMode::Disabled),
+            ],
+            ..InjectionConfig::default()
+        };
+        let resolve = |class, process| InjectPlan::resolve(&InjectMode::Clipboard, &cfg, &window(class, process)).mode;
+        assert_eq!(resolve("Chrome_WidgetWin_1", "Slack.exe"), InjectMode::Keystroke);
+        assert_eq!(resolve("Chrome_WidgetWin_1", "chrome.exe"), InjectMode::Disabled);
+        assert_eq!(resolve("Chrome_WidgetWin_1", "KeePassXC.exe"), InjectMode::Disabled);
+        assert_eq!(resolve("Notepad", "notepad.exe"), InjectMode::Clipboard);
+    }
+
+    #[test]
+    fn resolve_rule_overrides_field_by_field() {
+        let cfg = InjectionConfig {
+            pre_delay_ms: 40,
+            char_delay_ms: 5,
+            chunk_size: 16,
+            chunk_delay_ms: 20,
+            rules: vec![InjectRule {
+                process_name: Some("WindowsTerminal.exe".into()),
+                paste_chord: Some("Ctrl+Shift+V".into()),
+                chunk_size: Some(4),
+                ..InjectRule::default()
+            }],
+            ..InjectionConfig::default()
+        };
+        let plan = InjectPlan::resolve(&InjectMode::Clipboard, &cfg, &window("CASCADIA", "WindowsTerminal.exe"));
+        assert_eq!(plan.mode, InjectMode::Clipboard);
+        assert_eq!(plan.paste_chord, "Ctrl+Shift+V");
+        assert_eq!(plan.chunk_size, 4);
+        assert_eq!(plan.char_delay, Duration::from_millis(5));
+        assert_eq!(plan.chunk_delay, Duration::from_millis(20));
+        assert_eq!(plan.pre_delay, Duration::from_millis(40));
+    }
+
+    #[test]
+    fn resolve_ignores_rules_without_patterns() {
+        let cfg = InjectionConfig {
+            rules: vec![rule(None, None, InjectMode::Disabled)],
+            ..InjectionConfig::default()
+        };
+        let plan = InjectPlan::resolve(&InjectMode::Clipboard, &cfg, &window("", ""));
+        assert_eq!(plan.mode, InjectMode::Clipboard);
+    }
+
+    #[test]
+    fn glob_star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("chrome*", "chrome.exe"));
+        assert!(glob_match("*.exe", "slack.exe"));
+        assert!(glob_match("*Widget*", "Chrome_WidgetWin_1"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("a**c", "ac"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(!glob_match("*.exe", "exe"));
+        assert!(!glob_match("chrome*", "xchrome"));
+        assert!(!glob_match("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn glob_question_mark_is_literal() {
+        assert!(glob_match("a?c", "a?c"));
+        assert!(!glob_match("a?c", "abc"));
+    }
+
+    #[test]
+    fn glob_folds_case() {
+        assert!(glob_match("SLACK.EXE", "slack.exe"));
+        assert!(glob_match("keepass*", "KeePassXC"));
+        assert!(glob_match("*ÄPFEL", "grüne äpfel"));
+    }
+
+    #[test]
+    fn glob_empty() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "x"));
+        assert!(!glob_match("x", ""));
+    }
+
+    #[test]
+    fn parse_chord_accepts_modifiers_and_keys() {
+        let chord = parse_chord("Ctrl+Shift+V").unwrap();
+        assert_eq!(chord.modifiers, [Key::Control, Key::Shift]);
+        assert_eq!(chord.key, Key::Layout('v'));
+        let chord = parse_chord(" win + enter ").unwrap();
+        assert_eq!(chord.modifiers, [Key::Meta]);
+        assert_eq!(chord.key, Key::Return);
+        let chord = parse_chord("Tab").unwrap();
+        assert!(chord.modifiers.is_empty());
+        assert_eq!(chord.key, Key::Tab);
+    }
+
+    #[test]
+    fn parse_chord_errors() {
+        let err = |spec| parse_chord(spec).err().unwrap();
+        assert_eq!(err(""), "chord '' has no key");
+        assert_eq!(err("Ctrl+Shift"), "chord 'Ctrl+Shift' has no key");
+        assert_eq!(err("Ctrl+V+C"), "chord 'Ctrl+V+C' has more than one non-modifier key");
+        assert_eq!(err("Ctrl+F5"), "unsupported key 'f5' in chord 'Ctrl+F5'");
+    }
+
+    /// A focused X window, marked active as a window manager would, that
+    /// records what is typed into it.
+    #[cfg(target_os = "linux")]
+    struct TestWindow {
+        display: *mut x11::xlib::Display,
+        window: x11::xlib::Window,
+    }
+
+    #[cfg(target_os = "linux")]
+    impl TestWindow {
+        fn open(class: &std::ffi::CStr) -> Option<Self> {
+            use std::os::raw::{c_uchar, c_ulong};
+            use x11::xlib;
+
+            unsafe {
+                let display = xlib::XOpenDisplay(std::ptr::null());
+                if display.is_null() {
+                    return None;
+                }
+                let root = xlib::XDefaultRootWindow(display);
+                let window = xlib::XCreateSimpleWindow(display, root, 0, 0, 200, 100, 0, 0, 0);
+                let mut hint = xlib::XClassHint {
+                    res_name: c"whistxt-test".as_ptr().cast_mut(),
+                    res_class: class.as_ptr().cast_mut(),
+                };
+                xlib::XSetClassHint(display, window, &mut hint);
+                let atom = |name: &std::ffi::CStr| xlib::XInternAtom(display, name.as_ptr(), xlib::False);
+                let pid = std::process::id() as c_ulong;
+                let set = |target: xlib::Window, name: &std::ffi::CStr, kind: xlib::Atom, value: &c_ulong| {
+                    let data: *const c_ulong = value;
+                    xlib::XChangeProperty(display, target, atom(name), kind, 32, xlib::PropModeReplace, data.cast::<c_uchar>(), 1);
+                };
+                set(window, c"_NET_WM_PID", xlib::XA_CARDINAL, &pid);
+                xlib::XSelectInput(display, window, xlib::KeyPressMask | xlib::StructureNotifyMask);
+                xlib::XMapWindow(display, window);
+                // Focus can only go to a window that is already mapped.
+                let mut event: xlib::XEvent = std::mem::zeroed();
+                while event.get_type() != xlib::MapNotify {
+                    xlib::XNextEvent(display, &mut event);
+                }
+                set(root, c"_NET_ACTIVE_WINDOW", xlib::XA_WINDOW, &window);
+                xlib::XSetInputFocus(display, window, xlib::RevertToParent, xlib::CurrentTime);
+                xlib::XSync(display, xlib::False);
+                Some(Self { display, window })
+            }
+        }
+
+        /// What was typed since the last call, once keys stop arriving.
+        fn typed(&self) -> String {
+            use std::os::raw::{c_char, c_int};
+            use std::time::Instant;
+            use x11::xlib;
+
+            let mut text = String::new();
+            let mut last_key = Instant::now();
+            while last_key.elapsed() < Duration::from_millis(500) {
+                unsafe {
+                    if xlib::XPending(self.display) == 0 {
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                    let mut event: xlib::XEvent = std::mem::zeroed();
+                    xlib::XNextEvent(self.display, &mut event);
+                    if event.get_type() == xlib::KeyPress {
+                        let mut buf = [0 as c_char; 8];
+                        let n = xlib::XLookupString(
+                            &mut event.key,
+                            buf.as_mut_ptr(),
+                            buf.len() as c_int,
+                            std::ptr::null_mut(),
+                            std::ptr::null_mut(),
+                        );
+                        text.extend(buf[..n.max(0) as usize].iter().map(|&b| b as u8 as char));
+                        last_key = Instant::now();
+                    }
+                }
+            }
+            text
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Drop for TestWindow {
+        fn drop(&mut self) {
+            unsafe {
+                x11::xlib::XDestroyWindow(self.display, self.window);
+                x11::xlib::XCloseDisplay(self.display);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "types into the X display; run under Xvfb"]
+    fn rules_pick_the_mode_for_the_focused_x_window() {
+        if std::env::var_os("DISPLAY").is_none() {
+            eprintln!("DISPLAY is not set; skipping");
+            return;
+        }
+        let win = TestWindow::open(c"WhistxtTest").expect("X display");
+        let comm = std::fs::read_to_string("/proc/self/comm").unwrap().trim().to_string();
+        assert_eq!(foreground_window(), window("WhistxtTest", &comm));
+
+        // The class rule overrides the disabled default.
+        let cfg = InjectionConfig {
+            pre_delay_ms: 0,
+            rules: vec![rule(Some("whistxt*"), None, InjectMode::Keystroke)],
+            ..InjectionConfig::default()
+        };
+        inject_text("hi there", &InjectMode::Disabled, &cfg).unwrap();
+        assert_eq!(win.typed(), "hi there");
+
+        // The first matching rule, on the process, turns injection off.
+        let cfg = InjectionConfig {
+            pre_delay_ms: 0,
+            rules: vec![
+                rule(None, Some(&comm), InjectMode::Disabled),
+                rule(Some("whistxt*"), None, InjectMode::Keystroke),
+            ],
+            ..InjectionConfig::default()
+        };
+        inject_text("not typed", &InjectMode::Keystroke, &cfg).unwrap();
+        replace_text(3, "nor this", ReplaceMode::Backspace, &InjectMode::Keystroke, &cfg).unwrap();
+        assert_eq!(win.typed(), "");
+    }
+}
@@ -0,0 +1,104 @@
+// ----- Single-instance guard and launch forwarding -----
+//
+// Only one tray may run per user: two copies would both see the hotkey and
+// inject every transcript twice. The guard is a named mutex on Windows and an
+// `flock`ed file next to the control socket on Unix; both are released by the
+// OS if the process dies, so a crash never leaves a stale lock. A second
+// launch forwards its action flags to the running copy over the control API
+// and exits.
+
+use crate::ipc;
+use serde_json::Value;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a second launch waits for the first copy's control API to come up.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+const FORWARD_RETRY: Duration = Duration::from_millis(100);
+
+/// Held for the lifetime of the primary instance.
+pub struct InstanceLock {
+    #[cfg(windows)]
+    handle: windows_sys::Win32::Foundation::HANDLE,
+    #[cfg(unix)]
+    _file: std::fs::File,
+}
+
+impl InstanceLock {
+    /// `Ok(None)` when another instance already holds the lock.
+    #[cfg(windows)]
+    pub fn acquire() -> io::Result<Option<Self>> {
+        use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS};
+        use windows_sys::Win32::System::Threading::CreateMutexW;
+
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        let name = crate::gui::wide(&format!("Local\\whistxt-instance-{user}"));
+        unsafe {
+            let handle = CreateMutexW(std::ptr::null(), 0, name.as_ptr());
+            if handle == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if GetLastError() == ERROR_ALREADY_EXISTS {
+                CloseHandle(handle);
+                return Ok(None);
+            }
+            Ok(Some(Self { handle }))
+        }
+    }
+
+    /// `Ok(None)` when another instance already holds the lock.
+    #[cfg(unix)]
+    pub fn acquire() -> io::Result<Option<Self>> {
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        let path = lock_path();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(&path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::WouldBlock => Ok(None),
+                _ => Err(err),
+            };
+        }
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.handle) };
+    }
+}
+
+/// Lock file beside the control socket, so both live in the same per-user dir.
+#[cfg(unix)]
+fn lock_path() -> std::path::PathBuf {
+    let socket = ipc::endpoint();
+    let stem = socket.strip_suffix(".sock").unwrap_or(&socket);
+    format!("{stem}.lock").into()
+}
+
+/// Send `actions` to the running instance. The lock can be held a moment
+/// before the control API is listening, so connection failures are retried.
+pub fn forward_to_running(actions: &[&str]) -> Result<(), String> {
+    let deadline = Instant::now() + FORWARD_TIMEOUT;
+    for method in actions {
+        loop {
+            match ipc::call(method, Value::Null) {
+                Ok(Ok(_)) => break,
+                Ok(Err(e)) => return Err(format!("{method}: {e}")),
+                Err(_) if Instant::now() < deadline => thread::sleep(FORWARD_RETRY),
+                Err(e) => return Err(format!("cannot reach running instance at {}: {e}", ipc::endpoint())),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,388 @@
+// ----- Local control API (JSON-RPC 2.0 over a local socket) -----
+//
+// One JSON object per line in each direction. The endpoint is a Unix domain
+// socket in the user's runtime dir on Unix and a per-user named pipe on
+// Windows. Methods: start, stop, toggle, cancel, reinject, retranscribe,
+// status, get_config, reload_config, set_model {model}, transcribe_file
+// {path}, subscribe.
+// After `subscribe` the connection only receives `event` notifications;
+// clients wanting both open a second connection.
+
+use crate::outputs::TranscriptRecord;
+use crate::{resolve_model_path, SharedConfig, SpeechCommand, SpeechEvent};
+use interprocess::local_socket::{prelude::*, GenericFilePath, ListenerOptions};
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+pub use whistxt_ipc::endpoint;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum RuntimeState {
+    #[default]
+    Idle,
+    Recording,
+    Processing,
+}
+
+impl RuntimeState {
+    fn as_str(self) -> &'static str {
+        match self {
+            RuntimeState::Idle => "idle",
+            RuntimeState::Recording => "recording",
+            RuntimeState::Processing => "processing",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Status {
+    state: RuntimeState,
+    last_transcript: Option<Value>,
+    last_error: Option<String>,
+}
+
+/// Fan-out of speech events to `subscribe`d connections; also tracks the
+/// runtime state reported by `status`.
+#[derive(Default)]
+pub struct EventHub {
+    subscribers: Mutex<Vec<Sender<Arc<str>>>>,
+    status: Mutex<Status>,
+}
+
+impl EventHub {
+    pub fn publish(&self, ev: &SpeechEvent) {
+        {
+            let mut st = self.status.lock();
+            match ev {
+                SpeechEvent::RecordingStarted(_) => st.state = RuntimeState::Recording,
+                SpeechEvent::RecordingStopped(_) => st.state = RuntimeState::Idle,
+                SpeechEvent::Processing => st.state = RuntimeState::Processing,
+                SpeechEvent::Transcript { .. } | SpeechEvent::Revised { .. } => {
+                    st.state = RuntimeState::Idle;
+                    st.last_transcript = Some(event_to_json(ev));
+                }
+                SpeechEvent::Error(msg) => {
+                    if st.state == RuntimeState::Processing {
+                        st.state = RuntimeState::Idle;
+                    }
+                    st.last_error = Some(msg.clone());
+                }
+                SpeechEvent::Partial(_) | SpeechEvent::Info(_) | SpeechEvent::Reinject(_) | SpeechEvent::MicOpen(_) => {}
+            }
+        }
+        let mut subs = self.subscribers.lock();
+        if subs.is_empty() {
+            return;
+        }
+        let line: Arc<str> = notification("event", event_to_json(ev)).into();
+        subs.retain(|tx| tx.send(line.clone()).is_ok());
+    }
+
+    fn state(&self) -> RuntimeState {
+        self.status.lock().state
+    }
+
+    fn status_json(&self, config: &SharedConfig) -> Value {
+        let st = self.status.lock();
+        let cfg = config.get();
+        json!({
+            "state": st.state.as_str(),
+            "final_model_path": cfg.final_model_path,
+            "partial_model_path": cfg.partial_model_path,
+            "language": cfg.language,
+            "config_path": config.path().to_string_lossy(),
+            "last_transcript": st.last_transcript,
+            "last_error": st.last_error,
+        })
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<Arc<str>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+}
+
+pub fn event_to_json(ev: &SpeechEvent) -> Value {
+    match ev {
+        SpeechEvent::RecordingStarted(_) => json!({ "event": "recording_started" }),
+        SpeechEvent::RecordingStopped(_) => json!({ "event": "recording_stopped" }),
+        SpeechEvent::Processing => json!({ "event": "processing" }),
+        SpeechEvent::Partial(text) => json!({ "event": "partial", "text": text }),
+        SpeechEvent::Info(msg) => json!({ "event": "info", "message": msg }),
+        SpeechEvent::Error(msg) => json!({ "event": "error", "message": msg }),
+        SpeechEvent::Reinject(text) => json!({ "event": "reinject", "text": text }),
+        SpeechEvent::MicOpen(open) => json!({ "event": "mic", "open": open }),
+        SpeechEvent::Transcript { text, meta } => {
+            let mut v = TranscriptRecord::new(text.clone(), meta.clone()).to_json();
+            v["event"] = json!("transcript");
+            v
+        }
+        SpeechEvent::Revised { text, meta } => {
+            let mut v = TranscriptRecord::new(text.clone(), meta.clone()).to_json();
+            v["event"] = json!("revised");
+            v
+        }
+    }
+}
+
+pub fn start_server(
+    speech_tx: Sender<SpeechCommand>,
+    config: SharedConfig,
+    events: Arc<EventHub>,
+) -> io::Result<()> {
+    let path = endpoint();
+    let listener = bind(&path)?;
+    info!("Control API listening on {path}");
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let conn = match conn {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("control API accept failed: {e}");
+                    continue;
+                }
+            };
+            let speech_tx = speech_tx.clone();
+            let config = config.clone();
+            let events = events.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve_connection(conn, &speech_tx, &config, &events) {
+                    info!("control API client disconnected: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn bind(path: &str) -> io::Result<LocalSocketListener> {
+    let listen = || {
+        let name = path.to_fs_name::<GenericFilePath>()?;
+        ListenerOptions::new().name(name).create_sync()
+    };
+    match listen() {
+        #[cfg(unix)]
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            // A socket file left by a crashed run refuses connections; reclaim it.
+            let name = path.to_fs_name::<GenericFilePath>()?;
+            if LocalSocketStream::connect(name).is_ok() {
+                return Err(e);
+            }
+            std::fs::remove_file(path)?;
+            let listener = listen()?;
+            restrict_permissions(path);
+            Ok(listener)
+        }
+        Ok(listener) => {
+            #[cfg(unix)]
+            restrict_permissions(path);
+            Ok(listener)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+        warn!("could not restrict {path}: {e}");
+    }
+}
+
+fn serve_connection(
+    conn: LocalSocketStream,
+    speech_tx: &Sender<SpeechCommand>,
+    config: &SharedConfig,
+    events: &EventHub,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(conn);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let req: Value = match serde_json::from_str(&line) {
+            Ok(v) => v,
+            Err(e) => {
+                write_line(reader.get_mut(), &error_response(Value::Null, PARSE_ERROR, &e.to_string()))?;
+                continue;
+            }
+        };
+        let id = req.get("id").cloned();
+        let Some(method) = req.get("method").and_then(Value::as_str) else {
+            let resp = error_response(id.unwrap_or(Value::Null), INVALID_REQUEST, "missing method");
+            write_line(reader.get_mut(), &resp)?;
+            continue;
+        };
+        let params = req.get("params").cloned().unwrap_or(Value::Null);
+
+        if method == "subscribe" {
+            let rx = events.subscribe();
+            if let Some(id) = id {
+                write_line(reader.get_mut(), &result_response(id, json!({ "subscribed": true })))?;
+            }
+            let conn = reader.get_mut();
+            while let Ok(event_line) = rx.recv() {
+                conn.write_all(event_line.as_bytes())?;
+                conn.write_all(b"\n")?;
+                conn.flush()?;
+            }
+            return Ok(());
+        }
+
+        let result = dispatch(method, &params, speech_tx, config, events);
+        // Requests without an id are notifications: run them, never answer.
+        let Some(id) = id else { continue };
+        let resp = match result {
+            Ok(v) => result_response(id, v),
+            Err((code, msg)) => error_response(id, code, &msg),
+        };
+        write_line(reader.get_mut(), &resp)?;
+    }
+}
+
+fn dispatch(
+    method: &str,
+    params: &Value,
+    speech_tx: &Sender<SpeechCommand>,
+    config: &SharedConfig,
+    events: &EventHub,
+) -> Result<Value, (i64, String)> {
+    let send = |cmd: SpeechCommand| {
+        speech_tx
+            .send(cmd)
+            .map(|_| json!("ok"))
+            .map_err(|_| (INTERNAL_ERROR, "speech runtime stopped".to_string()))
+    };
+    let start = || send(config.get().start_command(Instant::now()));
+    match method {
+        "start" => start(),
+        "stop" => send(SpeechCommand::Stop { at: Instant::now() }),
+        "toggle" => {
+            if events.state() == RuntimeState::Recording {
+                send(SpeechCommand::Stop { at: Instant::now() })
+            } else {
+                start()
+            }
+        }
+        "cancel" => send(SpeechCommand::Cancel),
+        "reinject" => send(SpeechCommand::Reinject),
+        "retranscribe" => send(config.get().retranscribe_command()),
+        "status" => Ok(events.status_json(config)),
+        "get_config" => serde_json::to_value(&*config.get())
+            .map_err(|e| (INTERNAL_ERROR, e.to_string())),
+        "transcribe_file" => {
+            let path = params
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or_else(|| (INVALID_PARAMS, "expected {\"path\": <audio file>}".to_string()))?;
+            let cfg = config.get();
+            let (reply_tx, reply_rx) = mpsc::channel();
+            send(SpeechCommand::TranscribeFile {
+                path: path.into(),
+                model_path: cfg.final_model_file(),
+                language: cfg.language.clone(),
+                engine: cfg.engine.clone(),
+                reply: reply_tx,
+            })?;
+            match reply_rx.recv() {
+                Ok(Ok((text, meta))) => Ok(TranscriptRecord::new(text, meta).to_json()),
+                Ok(Err(e)) => Err((INTERNAL_ERROR, e)),
+                Err(_) => Err((INTERNAL_ERROR, "speech runtime stopped".to_string())),
+            }
+        }
+        "reload_config" => {
+            let cfg = config
+                .reload()
+                .map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+            info!("Config reloaded via control API");
+            Ok(json!({ "final_model_path": cfg.final_model_path }))
+        }
+        "set_model" => {
+            let name = params
+                .get("model")
+                .or_else(|| params.get("path"))
+                .and_then(Value::as_str)
+                .ok_or_else(|| (INVALID_PARAMS, "expected {\"model\": <name or path>}".to_string()))?;
+            let current = config.get().final_model_file();
+            let path = resolve_model_path(name, &current)
+                .ok_or_else(|| (INVALID_PARAMS, format!("model not found: {name}")))?;
+            let path = path.to_string_lossy().into_owned();
+            config
+                .set("final_model_path", json!(path))
+                .map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+            info!("Model set to {path} via control API");
+            Ok(json!({ "final_model_path": path }))
+        }
+        other => Err((METHOD_NOT_FOUND, format!("unknown method: {other}"))),
+    }
+}
+
+/// Run a method in-process, as if it had arrived over the socket.
+pub fn dispatch_local(
+    method: &str,
+    speech_tx: &Sender<SpeechCommand>,
+    config: &SharedConfig,
+    events: &EventHub,
+) -> Result<Value, String> {
+    dispatch(method, &Value::Null, speech_tx, config, events).map_err(|(_, msg)| msg)
+}
+
+/// One request/response round trip to a running instance. The outer error
+/// means nobody is listening; the inner one is the method's own error.
+pub fn call(method: &str, params: Value) -> io::Result<Result<Value, String>> {
+    let path = endpoint();
+    let name = path.as_str().to_fs_name::<GenericFilePath>()?;
+    let mut conn = BufReader::new(LocalSocketStream::connect(name)?);
+    let mut req = json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+    if !params.is_null() {
+        req["params"] = params;
+    }
+    write_line(conn.get_mut(), &req)?;
+    let mut line = String::new();
+    if conn.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    let mut resp: Value =
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let Some(err) = resp.get("error") {
+        let msg = err.get("message").and_then(Value::as_str).unwrap_or("request failed");
+        return Ok(Err(msg.to_string()));
+    }
+    Ok(Ok(resp.get_mut("result").map(Value::take).unwrap_or(Value::Null)))
+}
+
+fn result_response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn notification(method: &str, params: Value) -> String {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params }).to_string()
+}
+
+fn write_line(conn: &mut LocalSocketStream, msg: &Value) -> io::Result<()> {
+    conn.write_all(msg.to_string().as_bytes())?;
+    conn.write_all(b"\n")?;
+    conn.flush()
+}
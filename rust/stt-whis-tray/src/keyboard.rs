@@ -0,0 +1,544 @@
+// ----- Keyboard events -----
+//
+// Key presses and releases for the global hotkeys, each with the time it
+// happened. Windows uses a low-level keyboard hook and Linux reads the
+// keyboards under /dev/input (evdev; the user needs to be in the `input`
+// group), so even the shortest tap is seen. Linux looks for newly plugged
+// keyboards every few seconds. Elsewhere, or when the event source cannot be opened, the key
+// state is polled `poll_hz` times a second instead. Keystrokes that programs
+// synthesize, such as an injected transcript, are left out on Windows.
+
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardBackend {
+    /// Key events from the OS, falling back to polling.
+    #[default]
+    Auto,
+    /// Poll the key state `poll_hz` times a second.
+    Poll,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEvent {
+    pub key: Keycode,
+    pub down: bool,
+    /// When the key moved, as reported by the OS.
+    pub at: Instant,
+}
+
+/// Send key events to `tx` from a background thread; returns a name for
+/// the source in use. `poll_interval` is read before every poll.
+pub fn start(
+    backend: KeyboardBackend,
+    tx: Sender<KeyEvent>,
+    poll_interval: impl Fn() -> Duration + Send + 'static,
+) -> &'static str {
+    if backend == KeyboardBackend::Auto {
+        match start_events(tx.clone()) {
+            Ok(name) => return name,
+            Err(e) => warn!("Keyboard events unavailable ({e}); polling instead"),
+        }
+    }
+    thread::spawn(move || poll(tx, poll_interval));
+    "polling"
+}
+
+/// The OS's own view of which keys are down, for dropping keys whose
+/// release never arrived: Win+L and UAC prompts swallow key-ups, Windows
+/// removes hooks that are slow to return, and keyboards get unplugged.
+pub struct KeyState(Option<DeviceState>);
+
+impl KeyState {
+    pub fn new() -> Self {
+        // Under Wayland, X11 only sees keys while one of its windows has focus.
+        #[cfg(target_os = "linux")]
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            return Self(None);
+        }
+        Self(DeviceState::checked_new())
+    }
+
+    /// Keys down right now; `None` when the OS cannot tell.
+    pub fn held(&self) -> Option<Vec<Keycode>> {
+        self.0.as_ref().map(DeviceQuery::get_keys)
+    }
+}
+
+/// Report the difference between successive `get_keys` snapshots.
+fn poll(tx: Sender<KeyEvent>, interval: impl Fn() -> Duration) {
+    let device_state = DeviceState::new();
+    let mut held: Vec<Keycode> = Vec::new();
+    loop {
+        let keys = device_state.get_keys();
+        let at = Instant::now();
+        let released = held.iter().filter(|k| !keys.contains(k)).map(|&key| KeyEvent { key, down: false, at });
+        let pressed = keys.iter().filter(|k| !held.contains(k)).map(|&key| KeyEvent { key, down: true, at });
+        for event in released.chain(pressed).collect::<Vec<_>>() {
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+        held = keys;
+        thread::sleep(interval());
+    }
+}
+
+/// Windows virtual-key codes as `Keycode`s. The low-level hook reports the
+/// left and right modifiers separately.
+#[cfg(windows)]
+const KEYMAP: &[(u32, Keycode)] = &[
+    (0x08, Keycode::Backspace),
+    (0x09, Keycode::Tab),
+    (0x0D, Keycode::Enter),
+    (0x14, Keycode::CapsLock),
+    (0x1B, Keycode::Escape),
+    (0x20, Keycode::Space),
+    (0x21, Keycode::PageUp),
+    (0x22, Keycode::PageDown),
+    (0x23, Keycode::End),
+    (0x24, Keycode::Home),
+    (0x25, Keycode::Left),
+    (0x26, Keycode::Up),
+    (0x27, Keycode::Right),
+    (0x28, Keycode::Down),
+    (0x2D, Keycode::Insert),
+    (0x2E, Keycode::Delete),
+    (0x30, Keycode::Key0),
+    (0x31, Keycode::Key1),
+    (0x32, Keycode::Key2),
+    (0x33, Keycode::Key3),
+    (0x34, Keycode::Key4),
+    (0x35, Keycode::Key5),
+    (0x36, Keycode::Key6),
+    (0x37, Keycode::Key7),
+    (0x38, Keycode::Key8),
+    (0x39, Keycode::Key9),
+    (0x41, Keycode::A),
+    (0x42, Keycode::B),
+    (0x43, Keycode::C),
+    (0x44, Keycode::D),
+    (0x45, Keycode::E),
+    (0x46, Keycode::F),
+    (0x47, Keycode::G),
+    (0x48, Keycode::H),
+    (0x49, Keycode::I),
+    (0x4A, Keycode::J),
+    (0x4B, Keycode::K),
+    (0x4C, Keycode::L),
+    (0x4D, Keycode::M),
+    (0x4E, Keycode::N),
+    (0x4F, Keycode::O),
+    (0x50, Keycode::P),
+    (0x51, Keycode::Q),
+    (0x52, Keycode::R),
+    (0x53, Keycode::S),
+    (0x54, Keycode::T),
+    (0x55, Keycode::U),
+    (0x56, Keycode::V),
+    (0x57, Keycode::W),
+    (0x58, Keycode::X),
+    (0x59, Keycode::Y),
+    (0x5A, Keycode::Z),
+    (0x5B, Keycode::LMeta),
+    (0x5C, Keycode::RMeta),
+    (0x60, Keycode::Numpad0),
+    (0x61, Keycode::Numpad1),
+    (0x62, Keycode::Numpad2),
+    (0x63, Keycode::Numpad3),
+    (0x64, Keycode::Numpad4),
+    (0x65, Keycode::Numpad5),
+    (0x66, Keycode::Numpad6),
+    (0x67, Keycode::Numpad7),
+    (0x68, Keycode::Numpad8),
+    (0x69, Keycode::Numpad9),
+    (0x6A, Keycode::NumpadMultiply),
+    (0x6B, Keycode::NumpadAdd),
+    (0x6D, Keycode::NumpadSubtract),
+    (0x6F, Keycode::NumpadDivide),
+    (0x70, Keycode::F1),
+    (0x71, Keycode::F2),
+    (0x72, Keycode::F3),
+    (0x73, Keycode::F4),
+    (0x74, Keycode::F5),
+    (0x75, Keycode::F6),
+    (0x76, Keycode::F7),
+    (0x77, Keycode::F8),
+    (0x78, Keycode::F9),
+    (0x79, Keycode::F10),
+    (0x7A, Keycode::F11),
+    (0x7B, Keycode::F12),
+    (0xA0, Keycode::LShift),
+    (0xA1, Keycode::RShift),
+    (0xA2, Keycode::LControl),
+    (0xA3, Keycode::RControl),
+    (0xA4, Keycode::LAlt),
+    (0xA5, Keycode::RAlt),
+    (0xBA, Keycode::Semicolon),
+    (0xBB, Keycode::Equal),
+    (0xBC, Keycode::Comma),
+    (0xBD, Keycode::Minus),
+    (0xBE, Keycode::Dot),
+    (0xBF, Keycode::Slash),
+    (0xC0, Keycode::Grave),
+    (0xDB, Keycode::LeftBracket),
+    (0xDC, Keycode::BackSlash),
+    (0xDD, Keycode::RightBracket),
+    (0xDE, Keycode::Apostrophe),
+];
+
+/// Linux input event codes (`KEY_*`) as `Keycode`s.
+#[cfg(target_os = "linux")]
+const KEYMAP: &[(u32, Keycode)] = &[
+    (1, Keycode::Escape),
+    (2, Keycode::Key1),
+    (3, Keycode::Key2),
+    (4, Keycode::Key3),
+    (5, Keycode::Key4),
+    (6, Keycode::Key5),
+    (7, Keycode::Key6),
+    (8, Keycode::Key7),
+    (9, Keycode::Key8),
+    (10, Keycode::Key9),
+    (11, Keycode::Key0),
+    (12, Keycode::Minus),
+    (13, Keycode::Equal),
+    (14, Keycode::Backspace),
+    (15, Keycode::Tab),
+    (16, Keycode::Q),
+    (17, Keycode::W),
+    (18, Keycode::E),
+    (19, Keycode::R),
+    (20, Keycode::T),
+    (21, Keycode::Y),
+    (22, Keycode::U),
+    (23, Keycode::I),
+    (24, Keycode::O),
+    (25, Keycode::P),
+    (26, Keycode::LeftBracket),
+    (27, Keycode::RightBracket),
+    (28, Keycode::Enter),
+    (29, Keycode::LControl),
+    (30, Keycode::A),
+    (31, Keycode::S),
+    (32, Keycode::D),
+    (33, Keycode::F),
+    (34, Keycode::G),
+    (35, Keycode::H),
+    (36, Keycode::J),
+    (37, Keycode::K),
+    (38, Keycode::L),
+    (39, Keycode::Semicolon),
+    (40, Keycode::Apostrophe),
+    (41, Keycode::Grave),
+    (42, Keycode::LShift),
+    (43, Keycode::BackSlash),
+    (44, Keycode::Z),
+    (45, Keycode::X),
+    (46, Keycode::C),
+    (47, Keycode::V),
+    (48, Keycode::B),
+    (49, Keycode::N),
+    (50, Keycode::M),
+    (51, Keycode::Comma),
+    (52, Keycode::Dot),
+    (53, Keycode::Slash),
+    (54, Keycode::RShift),
+    (55, Keycode::NumpadMultiply),
+    (56, Keycode::LAlt),
+    (57, Keycode::Space),
+    (58, Keycode::CapsLock),
+    (59, Keycode::F1),
+    (60, Keycode::F2),
+    (61, Keycode::F3),
+    (62, Keycode::F4),
+    (63, Keycode::F5),
+    (64, Keycode::F6),
+    (65, Keycode::F7),
+    (66, Keycode::F8),
+    (67, Keycode::F9),
+    (68, Keycode::F10),
+    (71, Keycode::Numpad7),
+    (72, Keycode::Numpad8),
+    (73, Keycode::Numpad9),
+    (74, Keycode::NumpadSubtract),
+    (75, Keycode::Numpad4),
+    (76, Keycode::Numpad5),
+    (77, Keycode::Numpad6),
+    (78, Keycode::NumpadAdd),
+    (79, Keycode::Numpad1),
+    (80, Keycode::Numpad2),
+    (81, Keycode::Numpad3),
+    (82, Keycode::Numpad0),
+    (87, Keycode::F11),
+    (88, Keycode::F12),
+    (97, Keycode::RControl),
+    (98, Keycode::NumpadDivide),
+    (100, Keycode::RAlt),
+    (102, Keycode::Home),
+    (103, Keycode::Up),
+    (104, Keycode::PageUp),
+    (105, Keycode::Left),
+    (106, Keycode::Right),
+    (107, Keycode::End),
+    (108, Keycode::Down),
+    (109, Keycode::PageDown),
+    (110, Keycode::Insert),
+    (111, Keycode::Delete),
+    (125, Keycode::LMeta),
+    (126, Keycode::RMeta),
+];
+
+#[cfg(any(windows, target_os = "linux"))]
+fn keycode(code: u32) -> Option<Keycode> {
+    KEYMAP.iter().find(|(c, _)| *c == code).map(|&(_, key)| key)
+}
+
+#[cfg(windows)]
+static HOOK_TX: parking_lot::Mutex<Option<Sender<KeyEvent>>> = parking_lot::Mutex::new(None);
+
+/// Install a low-level keyboard hook on a thread of its own; the hook is
+/// only called while that thread pumps messages.
+#[cfg(windows)]
+fn start_events(tx: Sender<KeyEvent>) -> Result<&'static str, String> {
+    use std::sync::mpsc;
+    use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GetMessageW, SetWindowsHookExW, UnhookWindowsHookEx, MSG, WH_KEYBOARD_LL,
+    };
+
+    *HOOK_TX.lock() = Some(tx);
+    let (ready_tx, ready_rx) = mpsc::channel();
+    thread::spawn(move || unsafe {
+        let hook = SetWindowsHookExW(WH_KEYBOARD_LL, Some(hook_proc), GetModuleHandleW(std::ptr::null()), 0);
+        if hook == 0 {
+            let _ = ready_tx.send(Err(format!("SetWindowsHookExW failed: {}", std::io::Error::last_os_error())));
+            return;
+        }
+        let _ = ready_tx.send(Ok(()));
+        let mut msg: MSG = std::mem::zeroed();
+        while GetMessageW(&mut msg, 0, 0, 0) > 0 {}
+        UnhookWindowsHookEx(hook);
+    });
+    ready_rx
+        .recv()
+        .map_err(|_| "keyboard hook thread exited".to_string())?
+        .map(|_| "low-level keyboard hook")
+}
+
+/// Must return quickly: Windows drops hooks that hold up input.
+#[cfg(windows)]
+unsafe extern "system" fn hook_proc(
+    code: i32,
+    wparam: windows_sys::Win32::Foundation::WPARAM,
+    lparam: windows_sys::Win32::Foundation::LPARAM,
+) -> windows_sys::Win32::Foundation::LRESULT {
+    use windows_sys::Win32::System::SystemInformation::GetTickCount;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        CallNextHookEx, HC_ACTION, KBDLLHOOKSTRUCT, LLKHF_INJECTED, WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN,
+        WM_SYSKEYUP,
+    };
+
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam as *const KBDLLHOOKSTRUCT);
+        let down = match wparam as u32 {
+            // Synthesized, e.g. by our own text injection: not the user's keys.
+            _ if info.flags & LLKHF_INJECTED != 0 => None,
+            WM_KEYDOWN | WM_SYSKEYDOWN => Some(true),
+            WM_KEYUP | WM_SYSKEYUP => Some(false),
+            _ => None,
+        };
+        if let (Some(down), Some(key)) = (down, keycode(info.vkCode)) {
+            // `time` is on the GetTickCount clock.
+            let age = Duration::from_millis(GetTickCount().wrapping_sub(info.time) as u64);
+            let now = Instant::now();
+            let at = now.checked_sub(age).unwrap_or(now);
+            if let Some(tx) = HOOK_TX.lock().as_ref() {
+                let _ = tx.send(KeyEvent { key, down, at });
+            }
+        }
+    }
+    CallNextHookEx(0, code, wparam, lparam)
+}
+
+/// Read every keyboard listed in /proc/bus/input/devices, one thread each.
+#[cfg(target_os = "linux")]
+fn start_events(tx: Sender<KeyEvent>) -> Result<&'static str, String> {
+    let open = OpenNodes::default();
+    if open_keyboards(&tx, &open)? == 0 {
+        return Err("no keyboard found".into());
+    }
+    // Pick up keyboards plugged in later.
+    thread::spawn(move || loop {
+        thread::sleep(RESCAN);
+        // Nodes that cannot be opened were reported at startup or stay closed.
+        if let Err(e) = open_keyboards(&tx, &open) {
+            log::debug!("{e}");
+        }
+    });
+    Ok("evdev")
+}
+
+/// How often Linux looks for new keyboards.
+#[cfg(target_os = "linux")]
+const RESCAN: Duration = Duration::from_secs(2);
+
+/// Event nodes with a reader thread; each removes its own when the device
+/// goes away.
+#[cfg(target_os = "linux")]
+type OpenNodes = std::sync::Arc<parking_lot::Mutex<std::collections::HashSet<String>>>;
+
+/// Start a reader for every keyboard not in `open`; how many are open now.
+#[cfg(target_os = "linux")]
+fn open_keyboards(tx: &Sender<KeyEvent>, open: &OpenNodes) -> Result<usize, String> {
+    use std::fs::{self, File};
+
+    let devices = fs::read_to_string("/proc/bus/input/devices").map_err(|e| format!("/proc/bus/input/devices: {e}"))?;
+    let mut error = None;
+    for node in evdev_keyboards(&devices) {
+        if open.lock().contains(&node) {
+            continue;
+        }
+        let path = format!("/dev/input/{node}");
+        match File::open(&path) {
+            Ok(file) => {
+                open.lock().insert(node.clone());
+                let (tx, open) = (tx.clone(), open.clone());
+                thread::spawn(move || {
+                    if let Err(e) = read_evdev(file, tx) {
+                        warn!("{path}: {e}");
+                    }
+                    open.lock().remove(&node);
+                });
+            }
+            Err(e) => error = Some(format!("{path}: {e}")),
+        }
+    }
+    let opened = open.lock().len();
+    match error {
+        Some(e) if opened == 0 => Err(e),
+        _ => Ok(opened),
+    }
+}
+
+/// Event nodes of devices with the `kbd` handler that also auto-repeat,
+/// which leaves out power buttons and the like.
+#[cfg(target_os = "linux")]
+fn evdev_keyboards(devices: &str) -> Vec<String> {
+    const EV_REP: u64 = 1 << 0x14;
+    devices
+        .split("\n\n")
+        .filter_map(|block| {
+            let field = |prefix: &str| block.lines().find_map(|l| l.strip_prefix(prefix));
+            let handlers: Vec<&str> = field("H: Handlers=")?.split_whitespace().collect();
+            let ev = u64::from_str_radix(field("B: EV=")?.trim(), 16).ok()?;
+            if !handlers.contains(&"kbd") || ev & EV_REP == 0 {
+                return None;
+            }
+            handlers.iter().find(|h| h.starts_with("event")).map(|h| h.to_string())
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn read_evdev(mut file: std::fs::File, tx: Sender<KeyEvent>) -> std::io::Result<()> {
+    use std::io::Read;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    const EV_KEY: u16 = 0x01;
+
+    let mut buf = [0u8; std::mem::size_of::<libc::input_event>()];
+    // Down on this keyboard; released if it goes away.
+    let mut held: Vec<Keycode> = Vec::new();
+    loop {
+        if let Err(e) = file.read_exact(&mut buf) {
+            let at = Instant::now();
+            for key in held {
+                let _ = tx.send(KeyEvent { key, down: false, at });
+            }
+            return Err(e);
+        }
+        // SAFETY: the buffer holds exactly one kernel `input_event`.
+        let event: libc::input_event = unsafe { std::ptr::read_unaligned(buf.as_ptr().cast()) };
+        // Value 2 is auto-repeat.
+        if event.type_ != EV_KEY || event.value > 1 {
+            continue;
+        }
+        let Some(key) = keycode(event.code as u32) else {
+            continue;
+        };
+        // Event times are on the wall clock.
+        let time = Duration::new(event.time.tv_sec as u64, event.time.tv_usec as u32 * 1000);
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|now| now.checked_sub(time))
+            .unwrap_or_default();
+        let now = Instant::now();
+        let at = now.checked_sub(age).unwrap_or(now);
+        let down = event.value == 1;
+        held.retain(|&k| k != key);
+        if down {
+            held.push(key);
+        }
+        if tx.send(KeyEvent { key, down, at }).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn start_events(_tx: Sender<KeyEvent>) -> Result<&'static str, String> {
+    Err("not supported on this platform".into())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn evdev_keyboards_skips_buttons_and_mice() {
+        let devices = "\
+I: Bus=0019 Vendor=0000 Product=0001 Version=0000
+N: Name=\"Power Button\"
+P: Phys=LNXPWRBN/button/input0
+S: Sysfs=/devices/LNXSYSTM:00/LNXPWRBN:00/input/input2
+U: Uniq=
+H: Handlers=kbd event2
+B: PROP=0
+B: EV=3
+B: KEY=10000000000000 0
+
+I: Bus=0011 Vendor=0001 Product=0001 Version=ab41
+N: Name=\"AT Translated Set 2 keyboard\"
+P: Phys=isa0060/serio0/input0
+S: Sysfs=/devices/platform/i8042/serio0/input/input3
+U: Uniq=
+H: Handlers=sysrq kbd leds event3
+B: PROP=0
+B: EV=120013
+B: KEY=402000000 3803078f800d001 feffffdfffefffff fffffffffffffffe
+B: MSC=10
+B: LED=7
+
+I: Bus=0003 Vendor=046d Product=c52b Version=0111
+N: Name=\"Logitech USB Receiver Mouse\"
+H: Handlers=mouse0 event5
+B: PROP=0
+B: EV=17
+
+I: Bus=0003 Vendor=046d Product=c52b Version=0111
+N: Name=\"Logitech K270\"
+H: Handlers=sysrq kbd leds event6
+B: PROP=0
+B: EV=120013
+";
+        assert_eq!(super::evdev_keyboards(devices), ["event3", "event6"]);
+        assert!(super::evdev_keyboards("").is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+// ----- Logging -----
+//
+// Log lines go to stderr and, once the primary instance is up, to a rotating
+// file in the platform log dir (the tray app usually has no console). The
+// level comes from `--log-level`, else `RUST_LOG` (full env_logger syntax),
+// else the `log_level` config key; only the last one changes at runtime.
+//
+// Rotation: the active file is `whistxt.log`. It is moved to `whistxt.1.log`
+// (shifting older ones up) when it would pass `MAX_FILE_BYTES` or when the
+// day changes. At most `KEEP_FILES` old files are kept, none older than
+// `MAX_AGE`.
+
+use chrono::{DateTime, Local, NaiveDate};
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::{Mutex, RwLock};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+
+const LOG_STEM: &str = "whistxt";
+const MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+const KEEP_FILES: usize = 5;
+const MAX_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+static LOGGER: OnceLock<AppLogger> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// env_logger does the filtering and formatting; it is rebuilt when the
+/// config level changes, while the shared sink keeps the open file.
+struct AppLogger {
+    inner: RwLock<env_logger::Logger>,
+    sink: Arc<Mutex<Sink>>,
+    /// Set by `--log-level` or `RUST_LOG`; the config level is then ignored.
+    pinned: bool,
+    config_level: Mutex<LevelFilter>,
+}
+
+impl Log for AppLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.read().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        self.inner.read().log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.read().flush();
+    }
+}
+
+/// Install the logger (stderr only until `open_file`).
+pub fn init(cli_level: Option<LevelFilter>) {
+    let env_spec = env::var("RUST_LOG").ok().filter(|s| !s.trim().is_empty());
+    let pinned = cli_level.is_some() || env_spec.is_some();
+    let spec = match (cli_level, env_spec) {
+        (Some(level), _) => level.to_string(),
+        (None, Some(spec)) => spec,
+        (None, None) => LevelFilter::Info.to_string(),
+    };
+    let sink = Arc::new(Mutex::new(Sink { file: None }));
+    let inner = build(&spec, &sink);
+    let max_level = inner.filter();
+    let logger = LOGGER.get_or_init(|| AppLogger {
+        inner: RwLock::new(inner),
+        sink,
+        pinned,
+        config_level: Mutex::new(LevelFilter::Info),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+fn build(spec: &str, sink: &Arc<Mutex<Sink>>) -> env_logger::Logger {
+    env_logger::Builder::new()
+        .parse_filters(spec)
+        .format_timestamp_millis()
+        .write_style(env_logger::WriteStyle::Never)
+        .target(env_logger::Target::Pipe(Box::new(SinkWriter(sink.clone()))))
+        .build()
+}
+
+/// Apply the `log_level` config key, unless the level was pinned at launch.
+pub fn set_config_level(level: LogLevel) {
+    let Some(logger) = LOGGER.get() else { return };
+    let level = level.filter();
+    let mut current = logger.config_level.lock();
+    if logger.pinned || *current == level {
+        return;
+    }
+    *current = level;
+    let inner = build(&level.to_string(), &logger.sink);
+    log::set_max_level(inner.filter());
+    *logger.inner.write() = inner;
+    log::info!("Log level: {level}");
+}
+
+/// Start writing to the log file. Only the primary instance does this, so two
+/// processes never rotate the same file.
+pub fn open_file(dir: &Path) -> io::Result<PathBuf> {
+    let file = RotatingFile::open(dir)?;
+    let path = file.path();
+    if let Some(logger) = LOGGER.get() {
+        logger.sink.lock().file = Some(file);
+    }
+    Ok(path)
+}
+
+/// The active log file (it may not exist yet).
+pub fn log_file() -> PathBuf {
+    crate::paths::get().log_dir.join(format!("{LOG_STEM}.log"))
+}
+
+struct Sink {
+    file: Option<RotatingFile>,
+}
+
+/// `Write` handed to env_logger; receives one formatted record per call.
+struct SinkWriter(Arc<Mutex<Sink>>);
+
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = io::stderr().write_all(buf);
+        let mut sink = self.0.lock();
+        if let Some(file) = sink.file.as_mut() {
+            if let Err(e) = file.write_record(buf) {
+                // Keep going on stderr rather than failing every log call.
+                let _ = writeln!(io::stderr(), "log file disabled: {e}");
+                sink.file = None;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+struct RotatingFile {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+    day: NaiveDate,
+}
+
+impl RotatingFile {
+    fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = numbered(dir, 0);
+        if let Ok(meta) = fs::metadata(&path) {
+            let day = meta.modified().map(local_day).unwrap_or_else(|_| today());
+            if meta.len() >= MAX_FILE_BYTES || day != today() {
+                rotate(dir)?;
+            }
+        }
+        prune(dir);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file,
+            size,
+            day: today(),
+        })
+    }
+
+    fn path(&self) -> PathBuf {
+        numbered(&self.dir, 0)
+    }
+
+    fn write_record(&mut self, buf: &[u8]) -> io::Result<()> {
+        if (self.size > 0 && self.size + buf.len() as u64 > MAX_FILE_BYTES) || self.day != today() {
+            self.file.flush()?;
+            rotate(&self.dir)?;
+            prune(&self.dir);
+            self.file = OpenOptions::new().create(true).append(true).open(self.path())?;
+            self.size = 0;
+            self.day = today();
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// `whistxt.log` for 0, `whistxt.<n>.log` for older files.
+fn numbered(dir: &Path, n: usize) -> PathBuf {
+    match n {
+        0 => dir.join(format!("{LOG_STEM}.log")),
+        n => dir.join(format!("{LOG_STEM}.{n}.log")),
+    }
+}
+
+/// Shift every file up by one; the oldest falls off the end.
+fn rotate(dir: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(numbered(dir, KEEP_FILES));
+    for n in (0..KEEP_FILES).rev() {
+        let from = numbered(dir, n);
+        if from.exists() {
+            fs::rename(&from, numbered(dir, n + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop rotated files older than `MAX_AGE`.
+fn prune(dir: &Path) {
+    let Some(cutoff) = SystemTime::now().checked_sub(MAX_AGE) else { return };
+    for n in 1..=KEEP_FILES {
+        let path = numbered(dir, n);
+        let expired = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .is_ok_and(|modified| modified < cutoff);
+        if expired {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+fn local_day(time: SystemTime) -> NaiveDate {
+    DateTime::<Local>::from(time).date_naive()
+}
@@ -1,61 +1,66 @@
-use arboard::Clipboard;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{SampleFormat, Stream, StreamConfig};
-use device_query::{DeviceQuery, DeviceState, Keycode};
-use enigo::{Enigo, KeyboardControllable, Key};
+mod audio_file;
+mod capture;
+mod cli;
+mod clipboard;
+mod commands;
+mod config_file;
+mod config_watch;
+mod engine;
+#[cfg(windows)]
+mod gui;
+mod history;
+mod http_api;
+mod inject;
+mod instance;
+mod ipc;
+mod keyboard;
+mod logging;
+mod mock_engine;
+mod models;
+mod outputs;
+mod partials;
+mod paths;
+#[cfg(feature = "vosk")]
+mod vosk_engine;
+mod whisper_engine;
+mod wyoming;
+mod ws_api;
+
+use audio_file::{downmix, read_audio_file};
+use capture::CaptureConfig;
+use clap::Parser;
+use cli::{Cli, Command, RunArgs};
+use config_file::Severity;
+use device_query::Keycode;
+use engine::{ActiveEngine, CancelToken, DecodeOptions, EngineConfig, SpeechEngine};
+use history::{History, HistoryConfig, HistoryEntry};
+use inject::{inject_text, replace_text, ForegroundWindow, InjectMode, InjectionConfig, ReplaceMode};
+use instance::InstanceLock;
+use ipc::EventHub;
+use keyboard::{KeyEvent, KeyboardBackend};
+use logging::LogLevel;
 use log::{error, info, warn};
+use outputs::{default_outputs, OutputSink, Outputs, TranscriptRecord};
+use partials::{PartialMode, PartialRun};
 use parking_lot::Mutex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::ffi::CStr;
 use std::fs;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 use thiserror::Error;
-use whisper_rs::{
-    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters,
-};
-use windows_sys::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, RECT, WPARAM};
-use windows_sys::Win32::Graphics::Gdi::{
-    BeginPaint, CreateSolidBrush, DeleteObject, Ellipse, EndPaint, FillRect, InvalidateRect,
-    SelectObject, SetBkMode, SetTextColor, TextOutW, HBRUSH, HDC, PAINTSTRUCT, TRANSPARENT,
-};
-use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
-use windows_sys::Win32::UI::Shell::{
-    Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NOTIFYICONDATAW,
-};
-use windows_sys::Win32::UI::WindowsAndMessaging::{
-    CreatePopupMenu, CreateWindowExW, DefWindowProcW, DispatchMessageW, GetCursorPos, GetMessageW,
-    GetWindowLongPtrW, KillTimer, LoadIconW, LoadImageW, MoveWindow, PeekMessageW, PostMessageW,
-    PostQuitMessage, RegisterClassW, RemoveMenu, SetForegroundWindow, SetTimer, SetWindowLongPtrW,
-    SetWindowPos, ShowWindow, TrackPopupMenu, TranslateMessage, CW_USEDEFAULT, GWLP_USERDATA, HMENU,
-    HWND_TOPMOST, IDI_APPLICATION, IMAGE_ICON, LR_SHARED, MF_BYPOSITION, MSG, PM_REMOVE, SW_HIDE,
-    SW_SHOWNOACTIVATE, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SWP_SHOWWINDOW, TPM_BOTTOMALIGN,
-    TPM_LEFTALIGN, TPM_RETURNCMD, TPM_RIGHTBUTTON, WNDCLASSW, WM_APP, WM_COMMAND, WM_DESTROY,
-    WM_NULL, WM_PAINT, WM_QUIT, WM_RBUTTONUP, WM_TIMER, WS_EX_TOOLWINDOW, WS_EX_TOPMOST,
-    WS_OVERLAPPEDWINDOW, WS_POPUP,
-};
-
-const DEFAULT_MODEL_PATH: &str = "J:/whistxt/models/ggml-small-q5_1.bin";
+
 const DEFAULT_HOTKEY: &str = "Ctrl+Shift";
 const DEFAULT_POLL_HZ: u64 = 30;
+/// How often the hotkey thread re-reads the config while no key moves.
+const CONFIG_SYNC: Duration = Duration::from_millis(250);
 const CUDA_BUILD_ENABLED: bool = cfg!(feature = "cuda");
-const MAX_SAMPLES: usize = 16_000 * 120; // ~2 minutes of audio
-const STARTUP_LINK_NAME: &str = "WhisTxtR.lnk";
-const WM_TRAYICON: u32 = 0x0400 + 1;
-const ID_TRAY_QUIT: u16 = 1;
-const ID_TRAY_STARTUP: u16 = 2;
-const OVERLAY_WIDTH: i32 = 520;
-const OVERLAY_HEIGHT: i32 = 120;
-const WM_OVERLAY_UPDATE: u32 = WM_APP + 1;
-const OVERLAY_TIMER_ID: usize = 1;
-const OVERLAY_BLINK_MS: u32 = 500;
-const OVERLAY_HIDE_DELAY_MS: u128 = 2500;
+const MAX_SAMPLES: usize = 16_000 * 120; // ~2 minutes of audio at 16 kHz
+const DEFAULT_BEAM_SIZE: u32 = 5;
 
 #[derive(Debug, Error)]
 enum AppError {
@@ -67,37 +72,122 @@ enum AppError {
     Msg(String),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum InjectMode {
-    Clipboard,
-    Keystroke,
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+struct HotkeyConfig {
+    /// Hold-to-talk combo, e.g. "Ctrl+Shift" or "Ctrl+Alt+D".
+    combo: String,
+    /// Types the last transcript again, e.g. "Ctrl+Alt+V". Off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reinject: Option<String>,
+    /// Runs the last recording through `retranscribe.model_path` and replaces
+    /// the text it produced, e.g. "Ctrl+Alt+R". Off when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retranscribe: Option<String>,
+    /// Where key presses come from: OS key events (`auto`) or polling
+    /// `poll_hz` times a second (`poll`). Read at start.
+    #[serde(default)]
+    backend: KeyboardBackend,
 }
 
-impl Default for InjectMode {
+impl Default for HotkeyConfig {
     fn default() -> Self {
-        InjectMode::Clipboard
+        Self {
+            combo: DEFAULT_HOTKEY.to_string(),
+            reinject: None,
+            retranscribe: None,
+            backend: KeyboardBackend::Auto,
+        }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct HotkeyConfig {
-    /// Simple hold-to-talk combo. Currently only Ctrl+Shift is supported.
-    combo: String,
+/// Second, slower pass over the last recording.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+struct RetranscribeConfig {
+    /// A more accurate model, e.g. `ggml-large-v3.bin`; `final_model_path` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model_path: Option<String>,
+    /// Beam search width; 1 decodes greedily like the first pass.
+    #[serde(default = "default_beam_size")]
+    beam_size: u32,
+    /// How the first result is removed before the new one is injected.
+    #[serde(default)]
+    replace: ReplaceMode,
 }
 
-impl Default for HotkeyConfig {
+impl Default for RetranscribeConfig {
     fn default() -> Self {
         Self {
-            combo: DEFAULT_HOTKEY.to_string(),
+            model_path: None,
+            beam_size: DEFAULT_BEAM_SIZE,
+            replace: ReplaceMode::Backspace,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+fn default_beam_size() -> u32 {
+    DEFAULT_BEAM_SIZE
+}
+
+/// Parsed `HotkeyConfig::combo`: every group needs one of its keys held
+/// (so "Ctrl" is satisfied by either Control key).
+struct HotkeyCombo(Vec<Vec<Keycode>>);
+
+impl HotkeyCombo {
+    fn parse(combo: &str) -> Result<Self, String> {
+        let mut groups = Vec::new();
+        for part in combo.split('+').map(str::trim) {
+            let keys = match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => vec![Keycode::LControl, Keycode::RControl],
+                "shift" => vec![Keycode::LShift, Keycode::RShift],
+                "alt" => vec![Keycode::LAlt, Keycode::RAlt],
+                "" => return Err(format!("empty key in hotkey \"{combo}\"")),
+                lower => {
+                    // device_query names digits Key0..Key9 and letters in upper case.
+                    let name = if lower.len() == 1 && lower.as_bytes()[0].is_ascii_digit() {
+                        format!("Key{lower}")
+                    } else {
+                        let mut chars = lower.chars();
+                        chars
+                            .next()
+                            .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                            .unwrap_or_default()
+                    };
+                    let key = name
+                        .parse::<Keycode>()
+                        .map_err(|_| format!("unknown key \"{part}\" in hotkey \"{combo}\""))?;
+                    vec![key]
+                }
+            };
+            groups.push(keys);
+        }
+        Ok(Self(groups))
+    }
+
+    fn is_down(&self, keys: &[Keycode]) -> bool {
+        self.0
+            .iter()
+            .all(|group| group.iter().any(|k| keys.contains(k)))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 struct Config {
+    /// Editor hint; points at the generated `config.schema.json`.
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    /// File format version; older files are migrated on load.
+    #[serde(default = "default_config_version")]
+    config_version: u32,
+    /// Speech recognizer; `{"type": "whisper"}` by default.
+    #[serde(default)]
+    engine: EngineConfig,
+    /// Model for the injected result, e.g. small or medium.
     #[serde(default = "default_model_path")]
-    model_path: String,
+    final_model_path: String,
+    /// Smaller, faster model for the live overlay text, e.g. tiny.en;
+    /// `final_model_path` when unset.
+    #[serde(default)]
+    partial_model_path: Option<String>,
     #[serde(default)]
     language: Option<String>,
     #[serde(default)]
@@ -105,29 +195,112 @@ struct Config {
     #[serde(default)]
     inject_mode: InjectMode,
     #[serde(default)]
+    injection: InjectionConfig,
+    /// Where final transcripts go; `[{"type": "inject"}]` by default.
+    #[serde(default = "default_outputs")]
+    outputs: Vec<OutputSink>,
+    #[serde(default)]
     append_newline: bool,
+    /// Hotkey checks per second when polling (`hotkey.backend: "poll"`, or
+    /// no key events available).
     #[serde(default = "default_poll_hz")]
     poll_hz: u64,
     #[serde(default = "default_use_cuda")]
     use_cuda: bool,
+    /// Transcripts scoring below this confidence (0.0-1.0) are shown but not injected.
+    #[serde(default)]
+    min_confidence: f32,
+    /// Log verbosity; `--log-level` and `RUST_LOG` take precedence.
+    #[serde(default)]
+    log_level: LogLevel,
+    /// Local transcript history (tray Recent menu, `history` command).
+    #[serde(default)]
+    history: HistoryConfig,
+    /// The `hotkey.retranscribe` action.
+    #[serde(default)]
+    retranscribe: RetranscribeConfig,
+    /// Microphone handling between recordings (pre-roll).
+    #[serde(default)]
+    capture: CaptureConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            model_path: DEFAULT_MODEL_PATH.to_string(),
+            schema: Some(config_file::SCHEMA_REF.to_string()),
+            config_version: config_file::CONFIG_VERSION,
+            engine: EngineConfig::default(),
+            final_model_path: default_model_path(),
+            partial_model_path: None,
             language: None,
             hotkey: HotkeyConfig::default(),
             inject_mode: InjectMode::Clipboard,
+            injection: InjectionConfig::default(),
+            outputs: default_outputs(),
             append_newline: false,
             poll_hz: DEFAULT_POLL_HZ,
             use_cuda: true,
+            min_confidence: 0.0,
+            log_level: LogLevel::Info,
+            history: HistoryConfig::default(),
+            retranscribe: RetranscribeConfig::default(),
+            capture: CaptureConfig::default(),
         }
     }
 }
 
 fn default_model_path() -> String {
-    DEFAULT_MODEL_PATH.to_string()
+    paths::get().default_model_path()
+}
+
+impl Config {
+    /// `final_model_path` with relative paths taken from the config directory.
+    fn final_model_file(&self) -> String {
+        paths::get()
+            .resolve(&self.final_model_path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Model for the overlay's partial text, resolved like `final_model_file`.
+    fn partial_model_file(&self) -> String {
+        match &self.partial_model_path {
+            Some(path) => paths::get().resolve(path).to_string_lossy().into_owned(),
+            None => self.final_model_file(),
+        }
+    }
+
+    /// Model for the re-transcribe pass, resolved like `final_model_file`.
+    fn retranscribe_model_file(&self) -> String {
+        match &self.retranscribe.model_path {
+            Some(path) => paths::get().resolve(path).to_string_lossy().into_owned(),
+            None => self.final_model_file(),
+        }
+    }
+
+    /// Start recording; the hotkey went down `at`.
+    fn start_command(&self, at: Instant) -> SpeechCommand {
+        SpeechCommand::Start {
+            at,
+            final_model: self.final_model_file(),
+            partial_model: self.partial_model_file(),
+            language: self.language.clone(),
+            language_prob: self.language.is_none() && self.min_confidence > 0.0,
+            engine: self.engine.clone(),
+        }
+    }
+
+    fn retranscribe_command(&self) -> SpeechCommand {
+        SpeechCommand::Retranscribe {
+            model_path: self.retranscribe_model_file(),
+            beam_size: self.retranscribe.beam_size,
+            engine: self.engine.clone(),
+        }
+    }
+}
+
+fn default_config_version() -> u32 {
+    config_file::CONFIG_VERSION
 }
 
 fn default_poll_hz() -> u64 {
@@ -138,31 +311,179 @@ fn default_use_cuda() -> bool {
     true
 }
 
+/// Decoder statistics attached to a final transcript.
+#[derive(Clone, Debug, Default)]
+struct TranscriptMeta {
+    /// Language code used for decoding (detected when `Config::language` is unset).
+    language: Option<String>,
+    /// Probability of `language`; only present when it was auto-detected.
+    language_prob: Option<f32>,
+    /// Mean log-probability of the non-special tokens.
+    avg_logprob: f32,
+    /// Probability of the no-speech token at the start of the first window.
+    no_speech_prob: Option<f32>,
+    audio_duration: Duration,
+    decode_time: Duration,
+    /// File name of the model that produced the transcript.
+    model: String,
+    backend: String,
+    /// The overlay pass that ran while recording, if any.
+    partial: Option<PartialStats>,
+    /// Timed pieces of the transcript, in order.
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Segment {
+    start: Duration,
+    end: Duration,
+    text: String,
+}
+
+/// Timing of the partial (overlay) passes over one recording.
+#[derive(Clone, Debug, Default)]
+struct PartialStats {
+    model: String,
+    passes: u32,
+    total: Duration,
+    max: Duration,
+    /// From recording start to the first partial text.
+    first_text: Option<Duration>,
+}
+
+impl PartialStats {
+    fn add_pass(&mut self, took: Duration) {
+        self.passes += 1;
+        self.total += took;
+        self.max = self.max.max(took);
+    }
+
+    fn mean(&self) -> Duration {
+        self.total.checked_div(self.passes).unwrap_or_default()
+    }
+}
+
+impl TranscriptMeta {
+    /// Rough 0.0-1.0 score combining token likelihood, the no-speech
+    /// estimate and, when measured, how sure the language detection was.
+    fn confidence(&self) -> f32 {
+        let token_conf = self.avg_logprob.exp().clamp(0.0, 1.0);
+        let speech_conf = 1.0 - self.no_speech_prob.unwrap_or(0.0).clamp(0.0, 1.0);
+        let language_conf = self.language_prob.unwrap_or(1.0).clamp(0.0, 1.0);
+        token_conf * speech_conf * language_conf
+    }
+}
+
 #[derive(Debug)]
 enum SpeechEvent {
-    RecordingStarted,
-    RecordingStopped,
+    /// With the time from `SpeechCommand::Start`.
+    RecordingStarted(Instant),
+    /// With the time from `SpeechCommand::Stop`, or of cancelling.
+    RecordingStopped(Instant),
     Processing,
     Partial(String),
     Info(String),
-    Transcript(String),
+    Transcript { text: String, meta: TranscriptMeta },
+    /// Better text for the last recording; replaces what `Transcript` injected.
+    Revised { text: String, meta: TranscriptMeta },
+    /// Type the last transcript again.
+    Reinject(String),
+    /// The microphone was opened or closed, for recording or pre-roll.
+    MicOpen(bool),
     Error(String),
 }
 
 #[derive(Debug)]
 enum SpeechCommand {
     Start {
-        model_path: String,
+        /// When the hotkey went down (or the request came in); pre-roll from
+        /// before it is dropped.
+        at: Instant,
+        final_model: String,
+        partial_model: String,
         language: Option<String>,
+        /// Measure the detected language's probability for `min_confidence`.
+        language_prob: bool,
+        engine: EngineConfig,
+    },
+    Stop {
+        /// When the hotkey was released (or the request came in).
+        at: Instant,
     },
-    Stop,
     Cancel,
+    /// Keep the microphone open between recordings with this much pre-roll
+    /// (ms); 0 closes it.
+    PreRoll(u32),
+    /// Re-emit the last transcript as `SpeechEvent::Reinject`.
+    Reinject,
+    /// Decode the last recording again, with `beam_size` beams.
+    Retranscribe {
+        model_path: String,
+        beam_size: u32,
+        engine: EngineConfig,
+    },
+    /// Transcribe an audio file without touching capture, overlay or injection.
+    TranscribeFile {
+        path: PathBuf,
+        model_path: String,
+        language: Option<String>,
+        engine: EngineConfig,
+        reply: Sender<Result<(String, TranscriptMeta), String>>,
+    },
+    /// The runtime's engine, for decoding on another thread while sharing
+    /// its loaded models (see `shared_engine`).
+    ShareEngine {
+        engine: EngineConfig,
+        reply: Sender<Arc<dyn SpeechEngine>>,
+    },
 }
 
 fn main() -> Result<(), AppError> {
-    init_logging();
-    let config_path = default_config_path();
+    let cli = Cli::parse().validate().unwrap_or_else(|e| e.exit());
+    logging::init(cli.log_level);
+    let paths = paths::init(cli.config.clone());
+    match &cli.command {
+        None | Some(Command::Run(_) | Command::Serve(_)) => {}
+        Some(Command::Transcribe(args)) => std::process::exit(commands::transcribe(&cli, args)),
+        Some(Command::Devices) => std::process::exit(commands::devices()),
+        Some(Command::Models) => std::process::exit(commands::models(&cli)),
+        Some(Command::Config(cmd)) => std::process::exit(commands::config(&cli, cmd)),
+        Some(Command::History(cmd)) => std::process::exit(commands::history(cmd)),
+    }
+    let run = cli.run_args();
+    let actions = run.actions();
+    let _instance = match InstanceLock::acquire() {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            if matches!(cli.command, Some(Command::Serve(_))) {
+                return Err(AppError::Msg(
+                    "already running; quit the running copy and start it with `serve`".into(),
+                ));
+            }
+            if actions.is_empty() {
+                info!("Already running; nothing to forward.");
+            } else {
+                instance::forward_to_running(&actions).map_err(AppError::Msg)?;
+                info!("Forwarded {} to the running instance.", actions.join(", "));
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            warn!("Single-instance check failed ({e}); continuing.");
+            None
+        }
+    };
+    match logging::open_file(&paths.log_dir) {
+        Ok(path) => info!("Logging to {}", path.display()),
+        Err(e) => warn!("Log file unavailable ({e}); logging to stderr only."),
+    }
+
+    let config_path = paths.config_file.clone();
+    if paths.portable {
+        info!("Portable mode: keeping files next to the executable");
+    }
     let config = load_or_init_config(&config_path)?;
+    logging::set_config_level(config.log_level);
     info!(
         "Loaded config from {} (use_cuda: {}, cuda build: {})",
         config_path.display(),
@@ -172,123 +493,396 @@ fn main() -> Result<(), AppError> {
     if config.use_cuda && !CUDA_BUILD_ENABLED {
         warn!("Config requests CUDA but binary not built with --features cuda; recompile with --features cuda for GPU.");
     }
+    let overrides = ConfigOverrides::from_cli(&cli, &config).map_err(AppError::Msg)?;
+
+    let (evt_tx, speech_rx) = mpsc::channel::<SpeechEvent>();
+    let speech_tx = spawn_speech_runtime(evt_tx.clone()).map_err(AppError::Msg)?;
+    let outputs = Outputs::start(&config.outputs, evt_tx.clone());
+    let config = SharedConfig::new(config_path, config, overrides);
+    if let Err(e) = config_watch::start(config.clone(), evt_tx) {
+        warn!("Live config reload unavailable: {e}");
+    }
 
-    let (speech_tx, speech_rx) = spawn_speech_runtime().map_err(AppError::Msg)?;
-
-    let overlay_handle = start_overlay();
+    let mut history = History::open(paths.history_file());
+    if let Err(e) = history.purge_if_due(&config.get().history) {
+        warn!("History purge failed: {e}");
+    }
+    let ui = Ui::start(&run)?;
 
     start_keyboard_loop(config.clone(), speech_tx.clone());
 
+    let events = Arc::new(EventHub::default());
+    if let Err(e) = ipc::start_server(speech_tx.clone(), config.clone(), events.clone()) {
+        warn!("Control API unavailable: {e}");
+    }
+    if let Some(Command::Serve(args)) = &cli.command {
+        http_api::start(args.bind, speech_tx.clone(), config.clone()).map_err(AppError::Msg)?;
+        if let Some(bind) = args.wyoming {
+            wyoming::start(bind, speech_tx.clone(), config.clone()).map_err(AppError::Msg)?;
+        }
+    }
+    for action in &actions {
+        if let Err(e) = ipc::dispatch_local(action, &speech_tx, &config, &events) {
+            warn!("Launch action {action} failed: {e}");
+        }
+    }
+
     // Main loop: handle speech events and inject transcripts.
-    app_loop(speech_rx, config, overlay_handle)?;
+    app_loop(speech_rx, config, ui, outputs, events, history)?;
     Ok(())
 }
 
-fn init_logging() {
-    let mut builder = env_logger::Builder::from_default_env();
-    builder
-        .format_timestamp_millis()
-        .filter_level(log::LevelFilter::Info)
-        .init();
+/// Read config.json, creating it on first run. Problems are logged and the
+/// affected fields fall back to defaults in memory; the file is left alone.
+fn load_or_init_config(path: &Path) -> Result<Config, AppError> {
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let cfg = Config::default();
+        fs::write(path, serde_json::to_string_pretty(&cfg)?)?;
+        if let Err(e) = config_file::write_schema(path) {
+            warn!("Could not write config schema: {e}");
+        }
+        return Ok(cfg);
+    }
+    let data = fs::read_to_string(path)?;
+    let report = config_file::parse(&data);
+    for issue in &report.issues {
+        match issue.severity {
+            Severity::Error => error!("{}:{issue}", path.display()),
+            Severity::Warning => warn!("{}:{issue}", path.display()),
+        }
+    }
+    if let Some(from) = report.migrated_from {
+        if report.has_errors() {
+            warn!("Not migrating config from version {from} until its errors are fixed.");
+        } else if let Err(e) = config_file::write_migrated(path, &report) {
+            warn!("Could not migrate config from version {from}: {e}");
+        } else {
+            info!("Migrated config from version {from} to {}", config_file::CONFIG_VERSION);
+        }
+    }
+    if let Err(e) = config_file::write_schema(path) {
+        warn!("Could not write config schema: {e}");
+    }
+    Ok(report.config)
 }
 
-fn default_config_path() -> PathBuf {
-    if let Ok(appdata) = env::var("APPDATA") {
-        PathBuf::from(appdata).join("WhisTray").join("config.json")
-    } else {
-        PathBuf::from("config.json")
+/// `--model` / `--language` for this run, layered over the file on every
+/// (re)load and never written back.
+#[derive(Clone, Debug, Default)]
+struct ConfigOverrides {
+    final_model_path: Option<String>,
+    /// `Some(None)` is `--language auto`.
+    language: Option<Option<String>>,
+}
+
+impl ConfigOverrides {
+    fn from_cli(cli: &Cli, config: &Config) -> Result<Self, String> {
+        let final_model_path = match &cli.model {
+            Some(name) => Some(
+                resolve_model_path(name, &config.final_model_file())
+                    .ok_or_else(|| format!("--model: model not found: {name}"))?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            None => None,
+        };
+        let language = cli
+            .language
+            .as_ref()
+            .map(|lang| (lang != "auto").then(|| lang.clone()));
+        Ok(Self {
+            final_model_path,
+            language,
+        })
+    }
+
+    fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if let Some(path) = &self.final_model_path {
+            config.final_model_path = path.clone();
+        }
+        if let Some(language) = &self.language {
+            config.language = language.clone();
+        }
+        config
     }
 }
 
-fn startup_link_path() -> PathBuf {
-    let startup = env::var("APPDATA").unwrap_or_default();
-    PathBuf::from(startup)
-        .join("Microsoft")
-        .join("Windows")
-        .join("Start Menu")
-        .join("Programs")
-        .join("Startup")
-        .join(STARTUP_LINK_NAME)
+struct ConfigSlot {
+    /// As stored in the file; what `set` edits and persists.
+    file: Config,
+    overrides: ConfigOverrides,
+    /// `file` with `overrides` applied; what readers get.
+    effective: Arc<Config>,
 }
 
-fn enable_startup() -> Result<(), String> {
-    let exe = env::current_exe().map_err(|e| format!("exe path: {e}"))?;
-    let link_path = startup_link_path();
-    let sl = mslnk::ShellLink::new(exe).map_err(|e| format!("ShellLink: {e}"))?;
-    sl.create_lnk(&link_path)
-        .map_err(|e| format!("create startup link: {e}"))
+/// Process-wide handle to the current config. Readers take an `Arc` snapshot;
+/// updates build a new `Config` and swap it in whole.
+#[derive(Clone)]
+struct SharedConfig {
+    path: PathBuf,
+    current: Arc<Mutex<ConfigSlot>>,
 }
 
-fn disable_startup() -> Result<(), String> {
-    let link_path = startup_link_path();
-    if link_path.exists() {
-        fs::remove_file(&link_path).map_err(|e| format!("remove link: {e}"))?;
+impl SharedConfig {
+    fn new(path: PathBuf, config: Config, overrides: ConfigOverrides) -> Self {
+        let effective = Arc::new(overrides.apply(&config));
+        Self {
+            path,
+            current: Arc::new(Mutex::new(ConfigSlot {
+                file: config,
+                overrides,
+                effective,
+            })),
+        }
     }
-    Ok(())
-}
 
-fn is_startup_enabled() -> bool {
-    startup_link_path().exists()
-}
+    fn get(&self) -> Arc<Config> {
+        self.current.lock().effective.clone()
+    }
 
-fn load_or_init_config(path: &Path) -> Result<Config, AppError> {
-    if path.exists() {
-        let data = fs::read_to_string(path)?;
-        let mut cfg: Config = serde_json::from_str(&data)?;
-        // If the stored model path is missing, reset to default local model copy.
-        if !Path::new(&cfg.model_path).exists() {
-            cfg.model_path = default_model_path();
-            let updated = serde_json::to_string_pretty(&cfg)?;
-            fs::write(path, updated)?;
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Re-read and validate the config file, then swap it in. On error the
+    /// running config is left untouched.
+    fn reload(&self) -> Result<Arc<Config>, AppError> {
+        let data = fs::read_to_string(&self.path)?;
+        let report = config_file::parse(&data);
+        if report.has_errors() {
+            return Err(AppError::Msg(report.error_summary()));
         }
-        return Ok(cfg);
+        for issue in &report.issues {
+            warn!("{}:{issue}", self.path.display());
+        }
+        let mut slot = self.current.lock();
+        slot.effective = Arc::new(slot.overrides.apply(&report.config));
+        slot.file = report.config;
+        logging::set_config_level(slot.effective.log_level);
+        Ok(slot.effective.clone())
     }
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+
+    /// Set one top-level field in the config file, then swap the result in.
+    /// Setting a field that was overridden on the command line drops the override.
+    fn set(&self, key: &str, value: serde_json::Value) -> Result<Arc<Config>, AppError> {
+        let mut slot = self.current.lock();
+        let report = config_file::set_field(&self.path, key, value).map_err(AppError::Msg)?;
+        match key {
+            "final_model_path" => slot.overrides.final_model_path = None,
+            "language" => slot.overrides.language = None,
+            _ => {}
+        }
+        slot.effective = Arc::new(slot.overrides.apply(&report.config));
+        slot.file = report.config;
+        logging::set_config_level(slot.effective.log_level);
+        Ok(slot.effective.clone())
+    }
+}
+
+/// The runtime's engine, switched to `cfg` if needed, for decoding on the
+/// caller's thread while sharing the runtime's loaded models.
+fn shared_engine(speech_tx: &Sender<SpeechCommand>, cfg: &EngineConfig) -> Option<Arc<dyn SpeechEngine>> {
+    let (reply, reply_rx) = mpsc::channel();
+    let cmd = SpeechCommand::ShareEngine {
+        engine: cfg.clone(),
+        reply,
+    };
+    speech_tx.send(cmd).ok()?;
+    reply_rx.recv().ok()
+}
+
+/// Resolve a model given as a path, a file name next to the current model or
+/// in the models directory, or a short name like `small.en` (looked up as
+/// `ggml-<name>.bin`).
+fn resolve_model_path(name: &str, current: &str) -> Option<PathBuf> {
+    let direct = paths::get().resolve(name);
+    if direct.is_file() {
+        return Some(direct);
     }
-    let cfg = Config::default();
-    let data = serde_json::to_string_pretty(&cfg)?;
-    fs::write(path, data)?;
-    Ok(cfg)
+    find_model(name, current)
 }
 
-fn start_keyboard_loop(config: Config, speech_tx: Sender<SpeechCommand>) {
+/// The model a network client asked for by `name`, or the configured final
+/// model. Only bare names are looked up, next to the current model or in the
+/// models directory, so a client cannot point the engine at any other file.
+fn network_model_path(name: Option<&str>, cfg: &Config) -> String {
+    let current = cfg.final_model_file();
+    name.filter(|n| !n.is_empty() && !n.contains(['/', '\\', ':']) && *n != "." && *n != "..")
+        .and_then(|n| find_model(n, &current))
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or(current)
+}
+
+fn find_model(name: &str, current: &str) -> Option<PathBuf> {
+    let mut search: Vec<PathBuf> = Path::new(current).parent().map(Path::to_path_buf).into_iter().collect();
+    search.push(paths::get().models_dir());
+    search
+        .iter()
+        .flat_map(|dir| {
+            [
+                dir.join(name),
+                dir.join(format!("{name}.bin")),
+                dir.join(format!("ggml-{name}.bin")),
+            ]
+        })
+        .find(|p| p.is_file())
+}
+
+fn start_keyboard_loop(config: SharedConfig, speech_tx: Sender<SpeechCommand>) {
+    let (key_tx, key_rx) = mpsc::channel::<KeyEvent>();
+    let poll_config = config.clone();
+    let source = keyboard::start(config.get().hotkey.backend, key_tx, move || {
+        Duration::from_millis(1000 / poll_config.get().poll_hz.max(1))
+    });
+    info!("Keyboard input: {source}");
     thread::spawn(move || {
-        let device_state = DeviceState::new();
+        let mut keys: Vec<Keycode> = Vec::new();
         let mut hotkey_down = false;
-        let poll = Duration::from_millis(1000 / config.poll_hz.max(1));
+        let mut combo_src = String::new();
+        let mut combo = HotkeyCombo(Vec::new());
+        let mut reinject = ActionHotkey::default();
+        let mut retranscribe = ActionHotkey::default();
+        // Set while an action combo is held, and until the dictation combo is released.
+        let mut suppressed = false;
+        let mut pre_roll_ms = None;
+        let key_state = keyboard::KeyState::new();
         loop {
-            let keys = device_state.get_keys();
-            let ctrl = keys.contains(&Keycode::LControl)
-                || keys.contains(&Keycode::RControl);
-            let shift =
-                keys.contains(&Keycode::LShift) || keys.contains(&Keycode::RShift);
-            let down = ctrl && shift; // current combo support
-            if down && !hotkey_down {
-                hotkey_down = true;
-                let _ = speech_tx.send(SpeechCommand::Start {
-                    model_path: config.model_path.clone(),
-                    language: config.language.clone(),
+            // Wake up now and then without key events to pick up config changes.
+            let event = match key_rx.recv_timeout(CONFIG_SYNC) {
+                Ok(event) => Some(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    warn!("Keyboard input stopped; hotkeys are off");
+                    return;
+                }
+            };
+            let mut reconciled = false;
+            match event {
+                Some(event) => {
+                    keys.retain(|&k| k != event.key);
+                    if event.down {
+                        keys.push(event.key);
+                    }
+                }
+                // A missed key-up would otherwise keep a recording going forever.
+                None => {
+                    if let Some(held) = key_state.held() {
+                        let before = keys.len();
+                        keys.retain(|k| held.contains(k));
+                        reconciled = keys.len() != before;
+                    }
+                }
+            }
+            let cfg = config.get();
+            let pre_roll = cfg.capture.pre_roll_ms();
+            if pre_roll_ms != Some(pre_roll) {
+                pre_roll_ms = Some(pre_roll);
+                let _ = speech_tx.send(SpeechCommand::PreRoll(pre_roll));
+            }
+            if cfg.hotkey.combo != combo_src {
+                combo_src = cfg.hotkey.combo.clone();
+                combo = HotkeyCombo::parse(&combo_src).unwrap_or_else(|e| {
+                    warn!("{e}; using {DEFAULT_HOTKEY}");
+                    HotkeyCombo::parse(DEFAULT_HOTKEY).expect("default hotkey parses")
                 });
-                info!("Hotkey pressed: start recording");
+                info!("Hotkey: {combo_src}");
+            }
+            reinject.sync("Re-inject", cfg.hotkey.reinject.as_deref());
+            retranscribe.sync("Re-transcribe", cfg.hotkey.retranscribe.as_deref());
+            let at = match event {
+                Some(event) => event.at,
+                None if reconciled => Instant::now(),
+                None => continue,
+            };
+            let mut actions = Vec::new();
+            if reinject.pressed(&keys) {
+                actions.push(SpeechCommand::Reinject);
+            }
+            if retranscribe.pressed(&keys) {
+                actions.push(cfg.retranscribe_command());
+            }
+            if reinject.down || retranscribe.down {
+                // Action combos often contain the dictation combo; pressing one
+                // must not leave a stray recording behind.
+                if hotkey_down {
+                    hotkey_down = false;
+                    let _ = speech_tx.send(SpeechCommand::Cancel);
+                }
+                suppressed = true;
+            }
+            for cmd in actions {
+                info!("Hotkey pressed: {cmd:?}");
+                let _ = speech_tx.send(cmd);
+            }
+            let down = combo.is_down(&keys);
+            if suppressed {
+                suppressed = down || reinject.down || retranscribe.down;
+            } else if down && !hotkey_down {
+                hotkey_down = true;
+                let _ = speech_tx.send(cfg.start_command(at));
+                info!("Hotkey pressed: start recording (+{} ms)", at.elapsed().as_millis());
             } else if !down && hotkey_down {
                 hotkey_down = false;
-                let _ = speech_tx.send(SpeechCommand::Stop);
-                info!("Hotkey released: stop recording");
+                let _ = speech_tx.send(SpeechCommand::Stop { at });
+                info!("Hotkey released: stop recording (+{} ms)", at.elapsed().as_millis());
             }
-            thread::sleep(poll);
         }
     });
 }
 
+/// A hotkey that fires once per press rather than being held.
+#[derive(Default)]
+struct ActionHotkey {
+    src: String,
+    combo: Option<HotkeyCombo>,
+    down: bool,
+}
+
+impl ActionHotkey {
+    /// Pick up config changes; an unparsable combo disables the action.
+    fn sync(&mut self, name: &str, spec: Option<&str>) {
+        let spec = spec.unwrap_or_default();
+        if spec == self.src {
+            return;
+        }
+        self.src = spec.to_string();
+        if spec.is_empty() {
+            self.combo = None;
+            return;
+        }
+        self.combo = match HotkeyCombo::parse(spec) {
+            Ok(combo) => {
+                info!("{name} hotkey: {spec}");
+                Some(combo)
+            }
+            Err(e) => {
+                warn!("{e}; {name} hotkey disabled");
+                None
+            }
+        };
+    }
+
+    /// True on the poll where the combo goes down.
+    fn pressed(&mut self, keys: &[Keycode]) -> bool {
+        let down = self.combo.as_ref().is_some_and(|c| c.is_down(keys));
+        let pressed = down && !self.down;
+        self.down = down;
+        pressed
+    }
+}
+
 fn app_loop(
     event_rx: Receiver<SpeechEvent>,
-    config: Config,
-    overlay: OverlayHandle,
+    config: SharedConfig,
+    mut ui: Ui,
+    mut outputs: Outputs,
+    events: Arc<EventHub>,
+    mut history: History,
 ) -> Result<(), AppError> {
-    // Start Win32 tray (hidden window + notify icon)
-    let mut tray = Tray::start(is_startup_enabled())?;
-
     // Block on Ctrl+C; process speech events and inject.
     ctrlc::set_handler(move || {
         info!("Ctrl+C received, exiting.");
@@ -296,10 +890,12 @@ fn app_loop(
     })
     .map_err(|e| AppError::Msg(format!("failed to set Ctrl+C handler: {e}")))?;
 
+    let mut session = Session::default();
+    let mut last_injection: Option<Injection> = None;
+    ui.set_recent(&history.recent(&config.get().history));
     loop {
-        // Pump window messages (tray); break if WM_QUIT received.
-        if !tray.pump() {
-            info!("Tray requested quit (WM_QUIT)");
+        // Pump window messages and tray menu commands; stop once the user quits.
+        if !ui.pump(&config.get(), &history) {
             break;
         }
 
@@ -308,387 +904,511 @@ fn app_loop(
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
             Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
         };
+        events.publish(&ev);
+        let cfg = config.get();
+        outputs.sync(&cfg.outputs);
+        ui.set_recent(&history.recent(&cfg.history));
+        let revised = matches!(ev, SpeechEvent::Revised { .. });
         match ev {
-            SpeechEvent::RecordingStarted => {
-                info!("(speech) recording started");
-                overlay.send(OverlayMsg::RecordingStart);
+            SpeechEvent::RecordingStarted(at) => {
+                session.start(at);
+                info!("session={} event=recording_started", session.id);
+                ui.show(OverlayMsg::RecordingStart);
             }
-            SpeechEvent::RecordingStopped => {
-                info!("(speech) recording stopped");
-                overlay.send(OverlayMsg::Hide);
+            SpeechEvent::RecordingStopped(at) => {
+                session.stopped = Some(at);
+                info!(
+                    "session={} event=recording_stopped record_ms={}",
+                    session.id,
+                    session.record_time().as_millis()
+                );
+                ui.show(OverlayMsg::Hide);
             }
             SpeechEvent::Processing => {
                 info!("(speech) processing");
-                overlay.send(OverlayMsg::Processing);
+                ui.show(OverlayMsg::Processing);
             }
             SpeechEvent::Partial(text) => {
-                overlay.send(OverlayMsg::Transcript(text));
+                ui.show(OverlayMsg::Transcript(text));
             }
             SpeechEvent::Info(msg) => info!("(speech) {}", msg),
             SpeechEvent::Error(msg) => error!("(speech) {}", msg),
-            SpeechEvent::Transcript(text) => {
-                info!("(speech) transcript len={}", text.len());
-                let to_inject = if config.append_newline {
+            SpeechEvent::MicOpen(open) => ui.set_mic_open(open),
+            SpeechEvent::Reinject(text) => {
+                if !outputs.inject() {
+                    info!("Re-inject skipped: the inject output is not configured");
+                    continue;
+                }
+                let to_inject = if cfg.append_newline {
                     format!("{text}\n")
                 } else {
                     text
                 };
-                overlay.send(OverlayMsg::Transcript(to_inject.clone()));
-                if let Err(e) = inject_text(&to_inject, config.inject_mode.clone()) {
-                    error!("inject failed: {}", e);
+                let window = inject::foreground_window();
+                match inject_text(&to_inject, &cfg.inject_mode, &cfg.injection) {
+                    Ok(()) => {
+                        last_injection = Some(Injection {
+                            text: to_inject,
+                            window,
+                        })
+                    }
+                    Err(e) => error!("Re-inject failed: {e}"),
+                }
+            }
+            SpeechEvent::Transcript { text, meta } | SpeechEvent::Revised { text, meta } => {
+                let accepted = meta.confidence() >= cfg.min_confidence;
+                info!(
+                    "session={} event={} model={} backend={} chars={} lang={} lang_p={} avg_logprob={:.3} no_speech_p={} audio_ms={} decode_ms={} latency_ms={} confidence={:.3} accepted={}",
+                    session.id,
+                    if revised { "revised" } else { "transcript" },
+                    meta.model,
+                    meta.backend,
+                    text.chars().count(),
+                    meta.language.as_deref().unwrap_or("?"),
+                    meta.language_prob
+                        .map(|p| format!("{p:.3}"))
+                        .unwrap_or_else(|| "-".into()),
+                    meta.avg_logprob,
+                    meta.no_speech_prob
+                        .map(|p| format!("{p:.3}"))
+                        .unwrap_or_else(|| "-".into()),
+                    meta.audio_duration.as_millis(),
+                    meta.decode_time.as_millis(),
+                    session.latency().as_millis(),
+                    meta.confidence(),
+                    accepted
+                );
+                if let Some(partial) = &meta.partial {
+                    info!(
+                        "session={} event=partial_stats model={} passes={} mean_ms={} max_ms={} first_text_ms={}",
+                        session.id,
+                        partial.model,
+                        partial.passes,
+                        partial.mean().as_millis(),
+                        partial.max.as_millis(),
+                        partial
+                            .first_text
+                            .map(|t| t.as_millis().to_string())
+                            .unwrap_or_else(|| "-".into())
+                    );
+                }
+                let app = if cfg.history.enabled && cfg.history.record_app {
+                    let win = inject::foreground_window();
+                    Some(if win.process.is_empty() { win.class } else { win.process })
+                        .filter(|name| !name.is_empty())
+                } else {
+                    None
+                };
+                let entry = HistoryEntry {
+                    id: 0,
+                    timestamp: chrono::Local::now(),
+                    text: text.clone(),
+                    duration_ms: meta.audio_duration.as_millis() as u64,
+                    model: meta.model.clone(),
+                    language: meta.language.clone(),
+                    app,
+                    delivered: accepted,
+                };
+                if let Err(e) = history.append(&cfg.history, entry) {
+                    warn!("History write failed: {e}");
+                }
+                ui.set_recent(&history.recent(&cfg.history));
+                if !accepted {
+                    warn!(
+                        "Skipping injection: confidence {:.3} below min_confidence {:.3}",
+                        meta.confidence(),
+                        cfg.min_confidence
+                    );
+                    ui.show(OverlayMsg::Transcript(format!("(skipped) {text}")));
+                    if !revised {
+                        last_injection = None;
+                    }
+                    continue;
+                }
+                let to_inject = if cfg.append_newline {
+                    format!("{text}\n")
+                } else {
+                    text.clone()
+                };
+                ui.show(OverlayMsg::Transcript(to_inject.clone()));
+                outputs.publish(TranscriptRecord::new(text, meta));
+                if !outputs.inject() {
+                    last_injection = None;
+                    continue;
+                }
+                let window = inject::foreground_window();
+                let result = match last_injection.take() {
+                    Some(prev) if revised && prev.window == window => replace_text(
+                        prev.text.chars().count(),
+                        &to_inject,
+                        cfg.retranscribe.replace,
+                        &cfg.inject_mode,
+                        &cfg.injection,
+                    ),
+                    Some(_) if revised => {
+                        warn!("Focus moved since the first result; not replacing it");
+                        continue;
+                    }
+                    _ => inject_text(&to_inject, &cfg.inject_mode, &cfg.injection),
+                };
+                match result {
+                    Ok(()) => {
+                        last_injection = Some(Injection {
+                            text: to_inject,
+                            window,
+                        })
+                    }
+                    Err(e) => error!("inject failed: {}", e),
                 }
             }
         }
+    }
+    Ok(())
+}
+
+/// What the app typed last and where, so a revision can take it back.
+struct Injection {
+    text: String,
+    window: ForegroundWindow,
+}
+
+/// One dictation, from hotkey press to transcript, for the session log lines.
+#[derive(Default)]
+struct Session {
+    id: u64,
+    started: Option<Instant>,
+    stopped: Option<Instant>,
+}
+
+impl Session {
+    fn start(&mut self, at: Instant) {
+        self.id += 1;
+        self.started = Some(at);
+        self.stopped = None;
+    }
+
+    fn record_time(&self) -> Duration {
+        match (self.started, self.stopped) {
+            (Some(start), Some(stop)) => stop.duration_since(start),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Time from releasing the hotkey to the transcript arriving.
+    fn latency(&self) -> Duration {
+        self.stopped.map(|stop| stop.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Overlay HUD and tray icon; either may be off (`--no-overlay`, `--no-tray`,
+/// `--headless`). Other platforms have no GUI and always run headless.
+struct Ui {
+    #[cfg(windows)]
+    overlay: Option<gui::OverlayHandle>,
+    #[cfg(windows)]
+    tray: Option<gui::Tray>,
+}
+
+impl Ui {
+    #[cfg(windows)]
+    fn start(run: &RunArgs) -> Result<Self, AppError> {
+        let overlay = run.show_overlay().then(gui::start_overlay);
+        let tray = if run.show_tray() {
+            Some(gui::Tray::start(gui::is_startup_enabled())?)
+        } else {
+            None
+        };
+        Ok(Self { overlay, tray })
+    }
 
-        // Handle tray commands (quit/startup toggle)
+    #[cfg(not(windows))]
+    fn start(run: &RunArgs) -> Result<Self, AppError> {
+        if run.show_overlay() || run.show_tray() {
+            info!("No overlay or tray on this platform; running headless.");
+        }
+        Ok(Self {})
+    }
+
+    fn show(&self, msg: OverlayMsg) {
+        #[cfg(windows)]
+        if let Some(overlay) = &self.overlay {
+            overlay.send(msg);
+        }
+        #[cfg(not(windows))]
+        let _ = msg;
+    }
+
+    /// Entries for the tray's Recent menu, newest first.
+    fn set_recent(&self, entries: &[HistoryEntry]) {
+        #[cfg(windows)]
+        if let Some(tray) = &self.tray {
+            tray.set_recent(entries);
+        }
+        #[cfg(not(windows))]
+        let _ = entries;
+    }
+
+    /// Show in the tray whether the microphone is open.
+    fn set_mic_open(&self, open: bool) {
+        #[cfg(windows)]
+        if let Some(tray) = &self.tray {
+            tray.set_mic_open(open);
+        }
+        #[cfg(not(windows))]
+        let _ = open;
+    }
+
+    /// Pump window messages and handle tray menu commands; false once the
+    /// user asked to quit.
+    #[cfg(windows)]
+    fn pump(&mut self, cfg: &Config, history: &History) -> bool {
+        let Some(tray) = self.tray.as_mut() else {
+            return true;
+        };
+        if !tray.pump() {
+            info!("Tray requested quit (WM_QUIT)");
+            return false;
+        }
         while let Some(cmd) = tray.try_recv_cmd() {
             match cmd {
-                TrayCmd::Quit => {
+                gui::TrayCmd::Quit => {
                     info!("Tray quit requested");
-                    return Ok(());
+                    return false;
                 }
-                TrayCmd::ToggleStartup => {
-                    let now = !is_startup_enabled();
+                gui::TrayCmd::ToggleStartup => {
+                    let now = !gui::is_startup_enabled();
                     if now {
-                        if let Err(e) = enable_startup() {
+                        if let Err(e) = gui::enable_startup() {
                             error!("Enable startup failed: {e}");
                         }
-                    } else if let Err(e) = disable_startup() {
+                    } else if let Err(e) = gui::disable_startup() {
                         error!("Disable startup failed: {e}");
                     }
-                    tray.set_startup_checked(is_startup_enabled());
+                    tray.set_startup_checked(gui::is_startup_enabled());
+                }
+                gui::TrayCmd::OpenLog => {
+                    if let Err(e) = gui::open_path(&logging::log_file()) {
+                        error!("Open log failed: {e}");
+                    }
+                }
+                gui::TrayCmd::PasteRecent(id) => {
+                    let Some(entry) = history.get(id) else {
+                        warn!("History entry {id} is no longer available");
+                        continue;
+                    };
+                    // The menu took focus; give it back to the app the user was in.
+                    tray.focus_last_target();
+                    let text = if cfg.append_newline {
+                        format!("{}\n", entry.text)
+                    } else {
+                        entry.text.clone()
+                    };
+                    if let Err(e) = inject_text(&text, &cfg.inject_mode, &cfg.injection) {
+                        error!("Re-paste failed: {e}");
+                    }
+                }
+                gui::TrayCmd::CopyRecent(id) => {
+                    let Some(entry) = history.get(id) else {
+                        warn!("History entry {id} is no longer available");
+                        continue;
+                    };
+                    if let Err(e) = clipboard::copy_text(&entry.text) {
+                        error!("Copy failed: {e}");
+                    }
                 }
             }
         }
+        true
     }
-    Ok(())
-}
 
-fn inject_text(text: &str, mode: InjectMode) -> Result<(), String> {
-    if text.trim().is_empty() {
-        return Ok(());
-    }
-    // Small delay to allow focus to return after hotkey release.
-    thread::sleep(Duration::from_millis(30));
-    match mode {
-        InjectMode::Clipboard => inject_via_clipboard(text),
-        InjectMode::Keystroke => inject_via_keystrokes(text),
+    #[cfg(not(windows))]
+    fn pump(&mut self, _cfg: &Config, _history: &History) -> bool {
+        true
     }
 }
 
-fn inject_via_clipboard(text: &str) -> Result<(), String> {
-    let mut clipboard = Clipboard::new().map_err(|e| format!("clipboard: {e}"))?;
-    let prior = clipboard.get_text().ok();
-    clipboard
-        .set_text(text.to_string())
-        .map_err(|e| format!("set clipboard: {e}"))?;
-    // Paste via Ctrl+V
-    let mut enigo = Enigo::new();
-    enigo.key_down(Key::Control);
-    enigo.key_click(Key::Layout('v'));
-    enigo.key_up(Key::Control);
-    // Best-effort restore
-    if let Some(old) = prior {
-        let _ = clipboard.set_text(old);
-    }
-    Ok(())
+/// What the overlay shows.
+#[cfg_attr(not(windows), allow(dead_code))]
+enum OverlayMsg {
+    RecordingStart,
+    Processing,
+    Transcript(String),
+    Hide,
 }
 
-fn inject_via_keystrokes(text: &str) -> Result<(), String> {
-    let mut enigo = Enigo::new();
-    enigo.key_sequence(text);
-    Ok(())
+fn spawn_speech_runtime(evt_tx: Sender<SpeechEvent>) -> Result<Sender<SpeechCommand>, String> {
+    spawn_speech_runtime_with(evt_tx, |audio, pre_roll_ms, on_error| {
+        capture::Mic::open(&cpal::default_host(), audio, pre_roll_ms, on_error)
+    })
 }
 
-fn spawn_speech_runtime(
-) -> Result<(Sender<SpeechCommand>, Receiver<SpeechEvent>), String> {
+/// Opens the microphone for the runtime: `(buffer, pre_roll_ms, on_error)`.
+type OpenMic =
+    dyn Fn(Arc<Mutex<Vec<f32>>>, u32, Box<dyn FnMut(cpal::StreamError) + Send>) -> Result<capture::Mic, String>;
+
+/// `spawn_speech_runtime` with the microphone opened by `open`.
+fn spawn_speech_runtime_with(
+    evt_tx: Sender<SpeechEvent>,
+    open: impl Fn(Arc<Mutex<Vec<f32>>>, u32, Box<dyn FnMut(cpal::StreamError) + Send>) -> Result<capture::Mic, String>
+        + Send
+        + 'static,
+) -> Result<Sender<SpeechCommand>, String> {
     let (cmd_tx, cmd_rx) = mpsc::channel::<SpeechCommand>();
-    let (evt_tx, evt_rx) = mpsc::channel::<SpeechEvent>();
     thread::spawn(move || {
-        let mut stream: Option<Stream> = None;
+        let mut mic: Option<capture::Mic> = None;
+        // Kept open between recordings when above 0 (`capture.pre_roll_ms`).
+        let mut pre_roll_ms: u32 = 0;
         let mut last_sr: u32 = 16_000;
-        let mut last_channels: u16 = 1;
-        let mut ctx: Option<WhisperContext> = None;
-        let mut ctx_model: Option<String> = None;
+        // Replaced when a command asks for a different engine.
+        let mut engine = ActiveEngine::new(&EngineConfig::default());
+        // Final-pass model of the current dictation.
+        let mut final_model = String::new();
+        let mut last_dictation: Option<LastDictation> = None;
         let mut lang_opt: Option<String> = None;
+        let mut probe_language = false;
         let audio_buf = Arc::new(Mutex::new(Vec::<f32>::new()));
         let partial_flag = Arc::new(AtomicBool::new(false));
-        let mut partial_handle: Option<thread::JoinHandle<()>> = None;
-        let host = cpal::default_host();
-
-        let load_ctx = |path: &str,
-                        cached_path: &mut Option<String>,
-                        cached_ctx: &mut Option<WhisperContext>| {
-            if cached_path.as_deref() == Some(path) && cached_ctx.is_some() {
-                return Ok(());
-            }
-            let params = WhisperContextParameters::default();
-            match WhisperContext::new_with_params(path, params) {
-                Ok(c) => {
-                    *cached_ctx = Some(c);
-                    *cached_path = Some(path.to_string());
-                    Ok(())
-                }
-                Err(e) => Err(format!("failed to load model: {e}")),
-            }
-        };
-
-        let transcribe = |ctx: &WhisperContext,
-                          audio: &[f32],
-                          sr: u32,
-                          language: Option<String>,
-                          evt_tx: &Sender<SpeechEvent>|
-         -> Result<String, String> {
-            let mut state = ctx.create_state().map_err(|e| format!("state: {e}"))?;
-            let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-            let threads = std::thread::available_parallelism()
-                .unwrap_or_else(|_| NonZeroUsize::new(2).unwrap())
-                .get();
-            params.set_n_threads(threads as i32);
-            if let Some(lang) = language.as_deref() {
-                params.set_language(Some(lang));
-            } else {
-                params.set_language(None);
-            }
-            params.set_translate(false);
-            params.set_print_realtime(false);
-            params.set_print_progress(false);
-            params.set_print_timestamps(false);
-
-            // Stream partial segments via callback.
-            unsafe extern "C" fn on_new_segment(
-                _: *mut whisper_rs::whisper_rs_sys::whisper_context,
-                state: *mut whisper_rs::whisper_rs_sys::whisper_state,
-                n_new: std::ffi::c_int,
-                user: *mut std::ffi::c_void,
-            ) {
-                if user.is_null() || state.is_null() {
-                    return;
-                }
-                let tx = &*(user as *const Sender<SpeechEvent>);
-                let total =
-                    whisper_rs::whisper_rs_sys::whisper_full_n_segments_from_state(state);
-                if total <= 0 {
-                    return;
-                }
-                let start = (total - n_new).max(0);
-                for i in start..total {
-                    let cstr = whisper_rs::whisper_rs_sys::whisper_full_get_segment_text_from_state(
-                        state, i,
-                    );
-                    if !cstr.is_null() {
-                        if let Ok(txt) = CStr::from_ptr(cstr).to_str() {
-                            let _ = tx.send(SpeechEvent::Partial(txt.to_string()));
-                        }
-                    }
-                }
-            }
-
-            // Keep sender alive for the duration of `full`.
-            let tx_box: Box<Sender<SpeechEvent>> = Box::new(evt_tx.clone());
-            let tx_ptr = Box::into_raw(tx_box);
-            unsafe {
-                params.set_new_segment_callback(Some(on_new_segment));
-                params.set_new_segment_callback_user_data(tx_ptr as *mut _);
-            }
-
-            let pcm = if sr == 16_000 {
-                audio.to_vec()
-            } else {
-                resample_to_16k(audio, sr)
-            };
-            let res = state.full(params, &pcm).map_err(|e| format!("transcribe: {e}"));
-
-            // Reclaim the boxed sender to avoid leak.
-            unsafe {
-                let _ = Box::from_raw(tx_ptr);
-            }
-            res?;
-
-            let num_segments = state
-                .full_n_segments()
-                .map_err(|e| format!("segments: {e}"))?;
-            let mut out = String::new();
-            for i in 0..num_segments {
-                out.push_str(&state.full_get_segment_text(i).unwrap_or_default());
-            }
-            Ok(out.trim().to_string())
-        };
+        // Cuts the partial pass in flight short when the dictation stops.
+        let mut partial_cancel = CancelToken::default();
+        let mut partial_handle: Option<thread::JoinHandle<PartialRun>> = None;
 
         while let Ok(cmd) = cmd_rx.recv() {
             match cmd {
-                SpeechCommand::Start { model_path, language } => {
+                SpeechCommand::TranscribeFile {
+                    path,
+                    model_path,
+                    language,
+                    engine: engine_cfg,
+                    reply,
+                } => {
+                    engine.sync(&engine_cfg);
+                    // No segment callback: file jobs must not drive the overlay.
+                    let result = engine.load(&model_path).and_then(|_| {
+                        let (samples, sr) = read_audio_file(&path, max_samples)?;
+                        let opts = DecodeOptions {
+                            language,
+                            ..DecodeOptions::default()
+                        };
+                        engine.transcribe(&model_path, &resample_to_16k(&samples, sr), &opts)
+                    });
+                    let _ = reply.send(result);
+                }
+                SpeechCommand::ShareEngine {
+                    engine: engine_cfg,
+                    reply,
+                } => {
+                    engine.sync(&engine_cfg);
+                    let _ = reply.send(engine.shared());
+                }
+                SpeechCommand::Start {
+                    at,
+                    final_model: model_path,
+                    partial_model,
+                    language,
+                    language_prob,
+                    engine: engine_cfg,
+                } => {
                     lang_opt = language;
-                    if !Path::new(&model_path).exists() {
+                    probe_language = language_prob;
+                    engine.sync(&engine_cfg);
+                    if !engine.has_model(&model_path) {
                         let _ = evt_tx.send(SpeechEvent::Error(format!(
                             "Model not found at {model_path}"
                         )));
                         continue;
                     }
+                    final_model = model_path.clone();
                     if let Some(mut buf) = audio_buf.try_lock() {
                         buf.clear();
                     }
-                    if stream.is_some() {
-                        let _ = evt_tx.send(SpeechEvent::RecordingStarted);
+                    if mic.as_ref().is_some_and(capture::Mic::is_recording) {
+                        let _ = evt_tx.send(SpeechEvent::RecordingStarted(at));
                         continue;
                     }
-                    let device = match host.default_input_device() {
-                        Some(d) => d,
-                        None => {
-                            let _ = evt_tx.send(SpeechEvent::Error(
-                                "No input device available".into(),
-                            ));
-                            continue;
-                        }
-                    };
-                    let supported = match device.default_input_config() {
-                        Ok(c) => c,
-                        Err(e) => {
-                            let _ = evt_tx.send(SpeechEvent::Error(format!(
-                                "Failed to get input config: {e}"
-                            )));
-                            continue;
-                        }
-                    };
-                    last_sr = supported.sample_rate().0;
-                    last_channels = supported.channels();
-                    let stream_config: StreamConfig = supported.clone().into();
-                    let err_tx = evt_tx.clone();
-                    let buf_clone = audio_buf.clone();
-                    let build_stream = |sample_format: SampleFormat| -> Result<Stream, String> {
-                        match sample_format {
-                            SampleFormat::F32 => device
-                                .build_input_stream(
-                                    &stream_config,
-                                    move |data: &[f32], _| {
-                                        push_samples(&buf_clone, data, last_channels);
-                                    },
-                                    move |e| {
-                                        let _ = err_tx.send(SpeechEvent::Error(format!(
-                                            "Input stream error: {e}"
-                                        )));
-                                    },
-                                    None,
-                                )
-                                .map_err(|e| format!("Failed to build input stream: {e}")),
-                            SampleFormat::I16 => device
-                                .build_input_stream(
-                                    &stream_config,
-                                    move |data: &[i16], _| {
-                                        let mut f32buf: Vec<f32> =
-                                            data.iter().map(|s| *s as f32 / i16::MAX as f32).collect();
-                                        push_samples(&buf_clone, &f32buf, last_channels);
-                                    },
-                                    move |e| {
-                                        let _ = err_tx.send(SpeechEvent::Error(format!(
-                                            "Input stream error: {e}"
-                                        )));
-                                    },
-                                    None,
-                                )
-                                .map_err(|e| format!("Failed to build input stream: {e}")),
-                            SampleFormat::U16 => device
-                                .build_input_stream(
-                                    &stream_config,
-                                    move |data: &[u16], _| {
-                                        let mut f32buf: Vec<f32> = data
-                                            .iter()
-                                            .map(|s| (*s as f32 / u16::MAX as f32) * 2.0 - 1.0)
-                                            .collect();
-                                        push_samples(&buf_clone, &f32buf, last_channels);
-                                    },
-                                    move |e| {
-                                        let _ = err_tx.send(SpeechEvent::Error(format!(
-                                            "Input stream error: {e}"
-                                        )));
-                                    },
-                                    None,
-                                )
-                                .map_err(|e| format!("Failed to build input stream: {e}")),
-                            _ => Err(format!(
-                                "Unsupported input sample format: {:?}",
-                                sample_format
-                            )),
-                        }
-                    };
-
-                    match build_stream(supported.sample_format()) {
-                        Ok(s) => {
-                            if let Err(e) = s.play() {
-                                let _ = evt_tx.send(SpeechEvent::Error(format!(
-                                    "Failed to start capture: {e}"
-                                )));
-                            } else {
-                                stream = Some(s);
-                                let _ = evt_tx.send(SpeechEvent::RecordingStarted);
-                                // Kick off partial transcription thread (streams overlay only).
-                                if partial_handle.is_none() {
-                                    let buf_clone = audio_buf.clone();
-                                    let flag = partial_flag.clone();
-                                    let evt_partial = evt_tx.clone();
-                                    let lang_partial = lang_opt.clone();
-                                    let model_partial = model_path.clone();
-                                    partial_flag.store(true, Ordering::SeqCst);
-                                    partial_handle = Some(thread::spawn(move || {
-                                        let mut local_ctx: Option<WhisperContext> = None;
-                                        let mut last_text = String::new();
-                                        while flag.load(Ordering::SeqCst) {
-                                            if !Path::new(&model_partial).exists() {
-                                                break;
-                                            }
-                                            if local_ctx.is_none() {
-                                                local_ctx = WhisperContext::new(&model_partial).ok();
-                                            }
-                                            if let Some(ctx_local) = local_ctx.as_ref() {
-                                                let samples: Vec<f32> = {
-                                                    if let Some(guard) = buf_clone.try_lock() {
-                                                        let tail = guard.len().saturating_sub(16_000 * 10);
-                                                        guard[tail..].to_vec()
-                                                    } else {
-                                                        Vec::new()
-                                                    }
-                                                };
-                                                if samples.len() > 16_000 {
-                                                    if let Ok(txt) = transcribe_partial(
-                                                        ctx_local,
-                                                        &samples,
-                                                        last_sr,
-                                                        lang_partial.clone(),
-                                                    ) {
-                                                        if !txt.is_empty() && txt != last_text {
-                                                            last_text = txt.clone();
-                                                            let _ = evt_partial
-                                                                .send(SpeechEvent::Partial(txt));
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            thread::sleep(Duration::from_millis(900));
-                                        }
-                                    }));
-                                } else {
-                                    partial_flag.store(true, Ordering::SeqCst);
-                                }
+                    if mic.is_none() {
+                        match open_mic(&open, &audio_buf, pre_roll_ms, &evt_tx) {
+                            Ok(m) => mic = Some(m),
+                            Err(e) => {
+                                let _ = evt_tx.send(SpeechEvent::Error(e));
                             }
                         }
-                        Err(e) => {
-                            let _ = evt_tx.send(SpeechEvent::Error(e));
-                        }
                     }
-                    if let Err(e) = load_ctx(&model_path, &mut ctx_model, &mut ctx) {
+                    if let Some(m) = &mic {
+                        last_sr = m.sample_rate;
+                        m.start_recording(&audio_buf, at);
+                        let _ = evt_tx.send(SpeechEvent::RecordingStarted(at));
+                    }
+                    // The final model comes first: when both do not fit, the
+                    // partial pass borrows it instead of loading its own.
+                    if let Err(e) = engine.load(&model_path) {
                         let _ = evt_tx.send(SpeechEvent::Error(e));
+                        continue;
+                    }
+                    if mic.is_none() {
+                        continue;
                     }
+                    let evt_partial = evt_tx.clone();
+                    let on_text = move |txt| {
+                        let _ = evt_partial.send(SpeechEvent::Partial(txt));
+                    };
+                    let live_opts = DecodeOptions {
+                        language: lang_opt.clone(),
+                        ..DecodeOptions::default()
+                    };
+                    let live = match engine.live(&model_path, &live_opts) {
+                        Some(Ok(decoder)) => Some(decoder),
+                        Some(Err(e)) => {
+                            warn!("Live decoding unavailable ({e}); re-decoding for partials");
+                            None
+                        }
+                        None => None,
+                    };
+                    partial_cancel = CancelToken::default();
+                    let mode = match live {
+                        // Native streaming: feed new audio as it arrives and
+                        // finish the same decoder on Stop.
+                        Some(decoder) => PartialMode::Live(decoder),
+                        None => PartialMode::Redecode {
+                            model: partials::choose_model(&*engine, partial_model, model_path.clone()),
+                            opts: DecodeOptions {
+                                language: lang_opt.clone(),
+                                partial: true,
+                                cancel: partial_cancel.clone(),
+                                ..DecodeOptions::default()
+                            },
+                        },
+                    };
+                    let stats_model = match &mode {
+                        PartialMode::Live(_) => model_path,
+                        PartialMode::Redecode { model, .. } => model.clone(),
+                    };
+                    partial_handle = Some(partials::spawn(
+                        engine.shared(),
+                        &stats_model,
+                        mode,
+                        audio_buf.clone(),
+                        last_sr,
+                        partial_flag.clone(),
+                        on_text,
+                    ));
                 }
-                SpeechCommand::Stop | SpeechCommand::Cancel => {
-                    // Stop partial thread
+                SpeechCommand::Stop { .. } | SpeechCommand::Cancel => {
+                    let (at, cancelled) = match cmd {
+                        SpeechCommand::Stop { at } => (at, false),
+                        _ => (Instant::now(), true),
+                    };
+                    // Stop partial thread; a pass in flight would hold up the final one.
                     partial_flag.store(false, Ordering::SeqCst);
-                    if let Some(h) = partial_handle.take() {
-                        let _ = h.join();
-                    }
-                    if stream.is_some() {
-                        stream = None;
-                        let _ = evt_tx.send(SpeechEvent::RecordingStopped);
+                    partial_cancel.cancel();
+                    let (partial_stats, live) = match partial_handle.take().and_then(|h| h.join().ok()) {
+                        Some(run) => (Some(run.stats), run.live),
+                        None => (None, None),
+                    };
+                    if let Some(m) = mic.as_ref().filter(|m| m.is_recording()) {
+                        m.stop_recording();
+                        let _ = evt_tx.send(SpeechEvent::RecordingStopped(at));
                     }
+                    idle_mic(&mut mic, &open, &audio_buf, pre_roll_ms, &evt_tx);
                     let samples = {
                         if let Some(mut guard) = audio_buf.try_lock() {
                             let data = guard.clone();
@@ -706,60 +1426,198 @@ fn spawn_speech_runtime(
                     if samples.is_empty() {
                         continue;
                     }
+                    if cancelled {
+                        let _ = evt_tx.send(SpeechEvent::Info("Cancelled; audio discarded".into()));
+                        continue;
+                    }
                     let _ = evt_tx.send(SpeechEvent::Processing);
-                    if let Some(ctx_loaded) = ctx.as_ref() {
-                        match transcribe(
-                            ctx_loaded,
-                            &samples,
-                            last_sr,
-                            lang_opt.clone(),
-                            &evt_tx,
-                        ) {
-                            Ok(t) => {
-                                let _ = evt_tx.send(SpeechEvent::Info(format!(
-                                    "Transcript length: {} chars",
-                                    t.len()
-                                )));
-                                let _ = evt_tx.send(SpeechEvent::Transcript(t));
-                            }
-                            Err(e) => {
-                                let _ = evt_tx.send(SpeechEvent::Error(e));
-                            }
+                    let mut text_out = String::new();
+                    let pcm = resample_to_16k(&samples, last_sr);
+                    let opts = DecodeOptions {
+                        language: lang_opt.clone(),
+                        language_prob: probe_language,
+                        ..DecodeOptions::default()
+                    };
+                    let mut on_segment = |txt: &str| {
+                        let _ = evt_tx.send(SpeechEvent::Partial(txt.to_string()));
+                    };
+                    let result = match live {
+                        // Once the buffer is full, audio the decoder saw has
+                        // been dropped; decode what is left instead.
+                        Some((mut decoder, fed)) if fed <= samples.len() && samples.len() < max_samples(last_sr) => {
+                            decoder.accept(&resample_to_16k(&samples[fed..], last_sr));
+                            decoder.finish()
                         }
-                    } else {
+                        _ => engine.transcribe_streaming(&final_model, &pcm, &opts, &mut on_segment),
+                    };
+                    match result {
+                        Ok((text, mut meta)) => {
+                            let _ = evt_tx.send(SpeechEvent::Info(format!(
+                                "Transcript length: {} chars",
+                                text.len()
+                            )));
+                            meta.partial = partial_stats;
+                            text_out = text.clone();
+                            let _ = evt_tx.send(SpeechEvent::Transcript { text, meta });
+                        }
+                        Err(e) => {
+                            let _ = evt_tx.send(SpeechEvent::Error(e));
+                        }
+                    }
+                    // Kept even when decoding failed: a second pass may still succeed.
+                    last_dictation = Some(LastDictation {
+                        samples: pcm,
+                        language: lang_opt.clone(),
+                        text: text_out,
+                    });
+                }
+                SpeechCommand::PreRoll(ms) => {
+                    pre_roll_ms = ms;
+                    // A recording in progress keeps its stream until it stops.
+                    if !mic.as_ref().is_some_and(capture::Mic::is_recording) {
+                        idle_mic(&mut mic, &open, &audio_buf, pre_roll_ms, &evt_tx);
+                    }
+                }
+                SpeechCommand::Reinject => match &last_dictation {
+                    Some(last) if !last.text.is_empty() => {
+                        let _ = evt_tx.send(SpeechEvent::Reinject(last.text.clone()));
+                    }
+                    _ => {
+                        let _ = evt_tx.send(SpeechEvent::Error("No transcript to re-inject yet".into()));
+                    }
+                },
+                SpeechCommand::Retranscribe {
+                    model_path,
+                    beam_size,
+                    engine: engine_cfg,
+                } => {
+                    if mic.as_ref().is_some_and(capture::Mic::is_recording) {
                         let _ = evt_tx.send(SpeechEvent::Error(
-                            "Model not loaded; cannot transcribe".into(),
+                            "Recording in progress; re-transcribe after it ends".into(),
                         ));
+                        continue;
+                    }
+                    let Some(last) = last_dictation.as_mut() else {
+                        let _ = evt_tx.send(SpeechEvent::Error("No recording to re-transcribe yet".into()));
+                        continue;
+                    };
+                    let _ = evt_tx.send(SpeechEvent::Processing);
+                    engine.sync(&engine_cfg);
+                    let opts = DecodeOptions {
+                        language: last.language.clone(),
+                        beam_size,
+                        ..DecodeOptions::default()
+                    };
+                    let mut on_segment = |txt: &str| {
+                        let _ = evt_tx.send(SpeechEvent::Partial(txt.to_string()));
+                    };
+                    match engine.transcribe_streaming(&model_path, &last.samples, &opts, &mut on_segment) {
+                        Ok((text, meta)) => {
+                            last.text = text.clone();
+                            let _ = evt_tx.send(SpeechEvent::Revised { text, meta });
+                        }
+                        Err(e) => {
+                            let _ = evt_tx.send(SpeechEvent::Error(e));
+                        }
                     }
                 }
             }
         }
     });
-    Ok((cmd_tx, evt_rx))
+    Ok(cmd_tx)
+}
+
+/// Audio and result of the last dictation, for re-inject and re-transcribe.
+struct LastDictation {
+    /// 16 kHz mono.
+    samples: Vec<f32>,
+    language: Option<String>,
+    text: String,
+}
+
+/// File name of a model path, as shown in logs and transcript metadata.
+fn model_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Open the microphone; stream errors are reported as `SpeechEvent::Error`.
+fn open_mic(
+    open: &OpenMic,
+    audio: &Arc<Mutex<Vec<f32>>>,
+    pre_roll_ms: u32,
+    evt_tx: &Sender<SpeechEvent>,
+) -> Result<capture::Mic, String> {
+    let err_tx = evt_tx.clone();
+    let mic = open(
+        audio.clone(),
+        pre_roll_ms,
+        Box::new(move |e| {
+            let _ = err_tx.send(SpeechEvent::Error(format!("Input stream error: {e}")));
+        }),
+    )?;
+    let _ = evt_tx.send(SpeechEvent::MicOpen(true));
+    Ok(mic)
+}
+
+/// Between recordings, keep the microphone open only for a pre-roll of
+/// `pre_roll_ms`, reopening it when the length changed.
+fn idle_mic(
+    mic: &mut Option<capture::Mic>,
+    open: &OpenMic,
+    audio: &Arc<Mutex<Vec<f32>>>,
+    pre_roll_ms: u32,
+    evt_tx: &Sender<SpeechEvent>,
+) {
+    if pre_roll_ms > 0 && mic.as_ref().is_some_and(|m| m.pre_roll_ms() == pre_roll_ms) {
+        return;
+    }
+    if mic.take().is_some() {
+        let _ = evt_tx.send(SpeechEvent::MicOpen(false));
+    }
+    if pre_roll_ms > 0 {
+        match open_mic(open, audio, pre_roll_ms, evt_tx) {
+            Ok(m) => *mic = Some(m),
+            Err(e) => {
+                let _ = evt_tx.send(SpeechEvent::Error(format!("Pre-roll capture: {e}")));
+            }
+        }
+    }
 }
 
-fn push_samples(buf: &Arc<Mutex<Vec<f32>>>, data: &[f32], channels: u16) {
+fn push_samples(buf: &Arc<Mutex<Vec<f32>>>, data: &[f32], channels: u16, sample_rate: u32) {
     let mut guard = match buf.try_lock() {
         Some(g) => g,
         None => return,
     };
-    for frame in data.chunks(channels as usize) {
-        let mut sum = 0.0f32;
-        for s in frame {
-            sum += *s;
-        }
-        guard.push(sum / channels as f32);
-        if guard.len() > MAX_SAMPLES {
-            let drop = guard.len() - MAX_SAMPLES;
-            guard.drain(0..drop);
-        }
+    downmix(data, channels as usize, &mut guard);
+    let max = max_samples(sample_rate);
+    if guard.len() > max {
+        let drop = guard.len() - max;
+        guard.drain(0..drop);
     }
 }
 
+/// `MAX_SAMPLES` worth of audio at `sample_rate`.
+fn max_samples(sample_rate: u32) -> usize {
+    (MAX_SAMPLES as u64 * sample_rate as u64 / 16_000) as usize
+}
+
 fn resample_to_16k(samples: &[f32], from_rate: u32) -> Vec<f32> {
     if from_rate == 16_000 || samples.is_empty() {
         return samples.to_vec();
     }
+    // Multiples of 16 kHz are averaged down, which also filters out most of
+    // what would alias.
+    let factor = (from_rate / 16_000) as usize;
+    if factor > 1 && factor as u32 * 16_000 == from_rate {
+        return samples
+            .chunks(factor)
+            .map(|c| c.iter().sum::<f32>() / c.len() as f32)
+            .collect();
+    }
     let ratio = 16_000f32 / from_rate as f32;
     let new_len = (samples.len() as f32 * ratio).ceil() as usize;
     let mut out = Vec::with_capacity(new_len);
@@ -774,612 +1632,215 @@ fn resample_to_16k(samples: &[f32], from_rate: u32) -> Vec<f32> {
     out
 }
 
-fn transcribe_partial(
-    ctx: &WhisperContext,
-    audio: &[f32],
-    sr: u32,
-    language: Option<String>,
-) -> Result<String, String> {
-    let mut state = ctx.create_state().map_err(|e| format!("state: {e}"))?;
-    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-    let threads = std::thread::available_parallelism()
-        .unwrap_or_else(|_| NonZeroUsize::new(2).unwrap())
-        .get();
-    params.set_n_threads(threads as i32);
-    if let Some(lang) = language.as_deref() {
-        params.set_language(Some(lang));
-    } else {
-        params.set_language(None);
-    }
-    params.set_translate(false);
-    params.set_print_realtime(false);
-    params.set_print_progress(false);
-    params.set_print_timestamps(false);
-    params.set_single_segment(true);
-
-    let pcm = if sr == 16_000 {
-        audio.to_vec()
-    } else {
-        resample_to_16k(audio, sr)
-    };
-    // Shorten to last 10s to keep latency low
-    let max_samples = 16_000 * 10;
-    let pcm_tail = if pcm.len() > max_samples {
-        pcm[pcm.len() - max_samples..].to_vec()
-    } else {
-        pcm
-    };
-
-    state
-        .full(params, &pcm_tail)
-        .map_err(|e| format!("transcribe partial: {e}"))?;
-
-    let num_segments = state
-        .full_n_segments()
-        .map_err(|e| format!("segments: {e}"))?;
-    let mut out = String::new();
-    for i in 0..num_segments {
-        out.push_str(&state.full_get_segment_text(i).unwrap_or_default());
-    }
-    Ok(out.trim().to_string())
-}
-
-// ----- Overlay (recording HUD) -----
-
-enum OverlayMsg {
-    RecordingStart,
-    Processing,
-    Transcript(String),
-    Hide,
-}
-
-struct OverlayState {
-    recording: bool,
-    processing: bool,
-    text: String,
-    visible: bool,
-    last_update: Instant,
-    blink_on: bool,
-}
-
-struct OverlayShared {
-    state: Mutex<OverlayState>,
-    queue: Mutex<Vec<OverlayMsg>>,
-}
-
-struct OverlayHandle {
-    tx: Sender<OverlayMsg>,
-}
-
-impl OverlayHandle {
-    fn send(&self, msg: OverlayMsg) {
-        let _ = self.tx.send(msg);
-    }
-}
-
-fn start_overlay() -> OverlayHandle {
-    let (tx, rx) = mpsc::channel::<OverlayMsg>();
-    thread::spawn(move || {
-        if let Err(e) = run_overlay(rx) {
-            eprintln!("overlay error: {e}");
-        }
-    });
-    OverlayHandle { tx }
-}
-
-fn run_overlay(rx: Receiver<OverlayMsg>) -> Result<(), String> {
-    unsafe {
-        let hinstance = GetModuleHandleW(std::ptr::null());
-        let class_name = wide("WhisOverlay");
-        let wc = WNDCLASSW {
-            style: 0,
-            lpfnWndProc: Some(overlay_wnd_proc),
-            cbClsExtra: 0,
-            cbWndExtra: 0,
-            hInstance: hinstance,
-            hIcon: LoadIconW(0, IDI_APPLICATION as *const u16),
-            hCursor: 0,
-            hbrBackground: 0 as HBRUSH,
-            lpszMenuName: std::ptr::null(),
-            lpszClassName: class_name.as_ptr(),
-        };
-        if RegisterClassW(&wc) == 0 {
-            return Err("RegisterClassW failed".into());
-        }
-
-        let hwnd = CreateWindowExW(
-            WS_EX_TOPMOST | WS_EX_TOOLWINDOW,
-            class_name.as_ptr(),
-            class_name.as_ptr(),
-            WS_POPUP,
-            CW_USEDEFAULT,
-            CW_USEDEFAULT,
-            OVERLAY_WIDTH,
-            OVERLAY_HEIGHT,
-            0,
-            0,
-            hinstance,
-            std::ptr::null(),
-        );
-        if hwnd == 0 {
-            return Err("CreateWindowExW failed".into());
-        }
-
-        let shared = Arc::new(OverlayShared {
-            state: Mutex::new(OverlayState {
-                recording: false,
-                processing: false,
-                text: String::from("Ready"),
-                visible: true,
-                last_update: std::time::Instant::now(),
-                blink_on: true,
-            }),
-            queue: Mutex::new(Vec::new()),
-        });
-        let shared_for_wnd = Arc::clone(&shared);
-        SetWindowLongPtrW(hwnd, GWLP_USERDATA, Arc::into_raw(shared_for_wnd) as isize);
-
-        // Position at bottom center
-        let sx = GetSystemMetrics(0);
-        let sy = GetSystemMetrics(1);
-        let x = (sx - OVERLAY_WIDTH) / 2;
-        let y = sy - OVERLAY_HEIGHT - 40;
-        MoveWindow(hwnd, x, y, OVERLAY_WIDTH, OVERLAY_HEIGHT, 1);
-        SetWindowPos(
-            hwnd,
-            HWND_TOPMOST,
-            x,
-            y,
-            OVERLAY_WIDTH,
-            OVERLAY_HEIGHT,
-            SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE | SWP_SHOWWINDOW,
-        );
-        ShowWindow(hwnd, SW_SHOWNOACTIVATE);
-        InvalidateRect(hwnd, std::ptr::null(), 1);
-
-        // Bridge the channel onto the window thread so GetMessageW can stay blocking.
-        let hwnd_for_thread = hwnd;
-        let shared_for_queue = Arc::clone(&shared);
-        thread::spawn(move || {
-            while let Ok(msg) = rx.recv() {
-                {
-                    let mut q = shared_for_queue.queue.lock();
-                    q.push(msg);
-                }
-                PostMessageW(hwnd_for_thread, WM_OVERLAY_UPDATE, 0, 0);
-            }
-            PostMessageW(hwnd_for_thread, WM_QUIT, 0, 0);
-        });
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockConfig;
 
-        SetTimer(hwnd, OVERLAY_TIMER_ID, OVERLAY_BLINK_MS, None);
+    /// How long to wait for any one event.
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
 
-        let mut msg: MSG = std::mem::zeroed();
-        while GetMessageW(&mut msg, 0, 0, 0) != 0 {
-            if msg.message == WM_TIMER && msg.wParam == OVERLAY_TIMER_ID {
-                let mut hide_now = false;
-                {
-                    let mut st = shared.state.lock();
-                    if st.recording || st.processing {
-                        st.blink_on = !st.blink_on;
-                    }
-                    if st.visible
-                        && !st.recording
-                        && !st.processing
-                        && st.last_update.elapsed().as_millis() > OVERLAY_HIDE_DELAY_MS
-                    {
-                        st.visible = false;
-                        hide_now = true;
-                    }
-                }
-                if hide_now {
-                    ShowWindow(hwnd, SW_HIDE);
-                }
-                InvalidateRect(hwnd, std::ptr::null(), 0);
-                continue;
-            }
-            if msg.message == WM_OVERLAY_UPDATE {
-                apply_overlay_queue(hwnd, &shared);
-                continue;
-            }
-            TranslateMessage(&msg);
-            DispatchMessageW(&msg);
-        }
+    /// Stops the synthetic source when the mic is dropped.
+    struct Running(Arc<AtomicBool>);
 
-        KillTimer(hwnd, OVERLAY_TIMER_ID);
-        let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const OverlayShared;
-        if !ptr.is_null() {
-            let _ = Arc::from_raw(ptr);
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.0.store(false, Ordering::SeqCst);
         }
-        Ok(())
     }
-}
 
-#[allow(non_snake_case)]
-extern "system" fn overlay_wnd_proc(
-    hwnd: HWND,
-    msg: u32,
-    wparam: WPARAM,
-    lparam: LPARAM,
-) -> LRESULT {
-    unsafe {
-        match msg {
-            WM_PAINT => {
-                let mut ps: PAINTSTRUCT = std::mem::zeroed();
-                let hdc = BeginPaint(hwnd, &mut ps);
-                if hdc != 0 {
-                    let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const OverlayShared;
-                    if !ptr.is_null() {
-                        let shared = &*ptr;
-                        let st = shared.state.lock();
-                        paint_overlay(hdc, &st);
-                    }
-                    EndPaint(hwnd, &mut ps);
+    /// A 16 kHz mono tone, delivered ten times faster than real time so
+    /// partial passes have enough audio quickly.
+    fn synthetic_mic(
+        audio: Arc<Mutex<Vec<f32>>>,
+        pre_roll_ms: u32,
+        _on_error: Box<dyn FnMut(cpal::StreamError) + Send>,
+    ) -> Result<capture::Mic, String> {
+        capture::Mic::with_source(16_000, 1, audio, pre_roll_ms, |mut on_data| {
+            let running = Arc::new(AtomicBool::new(true));
+            let flag = running.clone();
+            thread::spawn(move || {
+                let block: Vec<f32> = (0..1600).map(|i| (i as f32 * 0.1).sin() * 0.5).collect();
+                while flag.load(Ordering::SeqCst) {
+                    on_data(&block);
+                    thread::sleep(Duration::from_millis(10));
                 }
-                0
-            }
-            WM_DESTROY => {
-                PostQuitMessage(0);
-                0
-            }
-            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
-        }
+            });
+            Ok(Running(running))
+        })
     }
-}
 
-fn apply_overlay_queue(hwnd: HWND, shared: &Arc<OverlayShared>) {
-    let mut do_show = false;
-    let mut do_hide = false;
-    {
-        let mut queue = shared.queue.lock();
-        if queue.is_empty() {
-            return;
-        }
-        let mut st = shared.state.lock();
-        for msg in queue.drain(..) {
-            match msg {
-                OverlayMsg::RecordingStart => {
-                    st.recording = true;
-                    st.processing = false;
-                    st.text.clear();
-                    st.visible = true;
-                    st.last_update = Instant::now();
-                    st.blink_on = true;
-                }
-                OverlayMsg::Processing => {
-                    st.processing = true;
-                    st.recording = false;
-                    st.visible = true;
-                    st.last_update = Instant::now();
-                    st.blink_on = true;
-                }
-                OverlayMsg::Transcript(t) => {
-                    st.text = t;
-                    st.processing = false;
-                    st.recording = false;
-                    st.visible = true;
-                    st.last_update = Instant::now();
-                    st.blink_on = true;
-                }
-                OverlayMsg::Hide => {
-                    st.recording = false;
-                    st.processing = false;
-                    st.visible = false;
-                }
-            }
-        }
-        do_show = st.visible;
-        do_hide = !st.visible;
-    }
-    if do_show {
-        unsafe {
-            SetWindowPos(
-                hwnd,
-                HWND_TOPMOST,
-                0,
-                0,
-                0,
-                0,
-                SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE | SWP_SHOWWINDOW,
-            );
-            ShowWindow(hwnd, SW_SHOWNOACTIVATE);
-            InvalidateRect(hwnd, std::ptr::null(), 1);
-        }
-    } else if do_hide {
-        unsafe {
-            ShowWindow(hwnd, SW_HIDE);
-        }
+    fn mock(decode_ms: u64) -> EngineConfig {
+        EngineConfig::Mock(MockConfig {
+            replies: vec!["One. Two.".into(), "Better take.".into()],
+            decode_ms,
+            partial_ms: 10,
+            confidence: 0.9,
+        })
     }
-}
 
-fn paint_overlay(hdc: HDC, state: &OverlayState) {
-    unsafe {
-        let rect = RECT {
-            left: 0,
-            top: 0,
-            right: OVERLAY_WIDTH,
-            bottom: OVERLAY_HEIGHT,
-        };
-        // black background
-        let back_br = CreateSolidBrush(colorref(0, 0, 0));
-        FillRect(hdc, &rect, back_br);
-        DeleteObject(back_br as _);
-
-        // Text color white
-        SetTextColor(hdc, colorref(255, 255, 255));
-        SetBkMode(hdc, TRANSPARENT as i32);
-
-        // Blinking circle
-        let dot_br = CreateSolidBrush(if state.blink_on {
-            colorref(200, 40, 40)
-        } else {
-            colorref(120, 120, 120)
-        });
-        let circle_size = 18;
-        let old = SelectObject(hdc, dot_br as _);
-        Ellipse(
-            hdc,
-            12,
-            (OVERLAY_HEIGHT - circle_size) / 2,
-            12 + circle_size,
-            (OVERLAY_HEIGHT - circle_size) / 2 + circle_size,
-        );
-        if old != 0 {
-            SelectObject(hdc, old);
+    fn start(engine: EngineConfig) -> SpeechCommand {
+        SpeechCommand::Start {
+            at: Instant::now(),
+            final_model: "final.bin".into(),
+            partial_model: "partial.bin".into(),
+            language: None,
+            language_prob: false,
+            engine,
         }
-        DeleteObject(dot_br as _);
-
-        // Status line
-        let status = if state.recording {
-            "Recording..."
-        } else if state.processing {
-            "Processing..."
-        } else {
-            "Transcript"
-        };
-        let status_w = wide(&format!("WhisTxtR - {}", status));
-        TextOutW(hdc, 40, 14, status_w.as_ptr(), (status_w.len() - 1) as i32);
+    }
 
-        // Body text
-        let body = if state.recording {
-            if state.text.is_empty() {
-                "Listening..."
-            } else {
-                state.text.as_str()
-            }
-        } else if state.processing {
-            "Processing..."
-        } else if state.text.is_empty() {
-            "No transcript"
-        } else {
-            state.text.as_str()
+    /// A speech runtime and a config that selects the mock engine with
+    /// `replies`, for the servers' tests. No microphone is ever opened.
+    pub(crate) fn mock_service(replies: &[&str]) -> (Sender<SpeechCommand>, SharedConfig) {
+        let (evt_tx, _) = mpsc::channel();
+        let speech_tx = spawn_speech_runtime_with(evt_tx, |_, _, _| Err("no microphone in tests".into())).unwrap();
+        let config = Config {
+            engine: EngineConfig::Mock(MockConfig {
+                replies: replies.iter().map(|r| r.to_string()).collect(),
+                decode_ms: 10,
+                partial_ms: 10,
+                confidence: 0.9,
+            }),
+            ..Config::default()
         };
-        let body_w = wide(body);
-        TextOutW(hdc, 44, 50, body_w.as_ptr(), (body_w.len() - 1) as i32);
+        let path = std::env::temp_dir().join("whistxt-test-config.json");
+        (speech_tx, SharedConfig::new(path, config, ConfigOverrides::default()))
     }
-}
 
-fn GetSystemMetrics(index: i32) -> i32 {
-    unsafe { windows_sys::Win32::UI::WindowsAndMessaging::GetSystemMetrics(index) }
-}
-
-fn colorref(r: u8, g: u8, b: u8) -> u32 {
-    (r as u32) | ((g as u32) << 8) | ((b as u32) << 16)
-}
-
-// ----- Win32 tray (NotifyIcon) -----
-
-enum TrayCmd {
-    Quit,
-    ToggleStartup,
-}
-
-struct Tray {
-    hwnd: HWND,
-    hmenu: HMENU,
-    cmd_rx: Receiver<TrayCmd>,
-}
-
-static mut TRAY_CMD_TX: Option<Sender<TrayCmd>> = None;
-fn send_tray_cmd(cmd: TrayCmd) {
-    unsafe {
-        if let Some(tx) = TRAY_CMD_TX.as_ref() {
-            let _ = tx.send(cmd);
-        }
+    fn runtime() -> (Sender<SpeechCommand>, Receiver<SpeechEvent>) {
+        let (evt_tx, evt_rx) = mpsc::channel();
+        let cmd_tx = spawn_speech_runtime_with(evt_tx, synthetic_mic).unwrap();
+        (cmd_tx, evt_rx)
     }
-}
-
-impl Tray {
-    fn start(startup_checked: bool) -> Result<Self, AppError> {
-        unsafe {
-            let hinstance = GetModuleHandleW(std::ptr::null());
-            let class_name = wide("WhisTrayHidden");
-            let wc = WNDCLASSW {
-                style: 0,
-                lpfnWndProc: Some(tray_wnd_proc),
-                cbClsExtra: 0,
-                cbWndExtra: 0,
-                hInstance: hinstance,
-                hIcon: LoadIconW(0, IDI_APPLICATION as *const u16),
-                hCursor: 0,
-                hbrBackground: 0 as HBRUSH,
-                lpszMenuName: std::ptr::null(),
-                lpszClassName: class_name.as_ptr(),
-            };
-            if RegisterClassW(&wc) == 0 {
-                return Err(AppError::Msg("RegisterClassW failed".into()));
-            }
-
-            let hwnd = CreateWindowExW(
-                0,
-                class_name.as_ptr(),
-                class_name.as_ptr(),
-                WS_OVERLAPPEDWINDOW,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                0,
-                0,
-                hinstance,
-                std::ptr::null(),
-            );
-            if hwnd == 0 {
-                return Err(AppError::Msg("CreateWindowExW failed".into()));
-            }
 
-            let hmenu = CreatePopupMenu();
-            append_menu_str(hmenu, ID_TRAY_STARTUP as u32, startup_label(startup_checked));
-            append_menu_str(hmenu, ID_TRAY_QUIT as u32, "Quit");
-
-            let (tx, rx) = mpsc::channel::<TrayCmd>();
-            TRAY_CMD_TX = Some(tx);
-
-            let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
-            nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
-            nid.hWnd = hwnd;
-            nid.uID = 1;
-            nid.uFlags = NIF_MESSAGE | NIF_TIP | NIF_ICON;
-            nid.uCallbackMessage = WM_TRAYICON;
-            nid.hIcon = LoadImageW(
-                0,
-                IDI_APPLICATION as *const u16,
-                IMAGE_ICON,
-                16,
-                16,
-                LR_SHARED,
-            ) as isize;
-            let tip = wide("WhisTxtR (right-click for menu)");
-            nid.szTip[..tip.len()].copy_from_slice(&tip);
-            Shell_NotifyIconW(NIM_ADD, &mut nid);
-
-            Ok(Tray { hwnd, hmenu, cmd_rx: rx })
-        }
+    /// Events as short labels, without `Info`.
+    fn label(event: &SpeechEvent) -> Option<String> {
+        Some(match event {
+            SpeechEvent::RecordingStarted(_) => "started".into(),
+            SpeechEvent::RecordingStopped(_) => "stopped".into(),
+            SpeechEvent::Processing => "processing".into(),
+            SpeechEvent::Partial(text) => format!("partial:{}", text.trim()),
+            SpeechEvent::Info(_) => return None,
+            SpeechEvent::Transcript { text, .. } => format!("transcript:{text}"),
+            SpeechEvent::Revised { text, .. } => format!("revised:{text}"),
+            SpeechEvent::Reinject(text) => format!("reinject:{text}"),
+            SpeechEvent::MicOpen(open) => format!("mic:{open}"),
+            SpeechEvent::Error(e) => format!("error:{e}"),
+        })
     }
 
-    fn pump(&mut self) -> bool {
-        unsafe {
-            let mut msg: MSG = std::mem::zeroed();
-            while PeekMessageW(&mut msg, 0, 0, 0, PM_REMOVE) != 0 {
-                if msg.message == WM_QUIT as u32 {
-                    send_tray_cmd(TrayCmd::Quit);
-                    return false;
+    /// Labels of the events up to and including the first one that starts with `last`.
+    fn events_until(rx: &Receiver<SpeechEvent>, last: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        loop {
+            let event = rx
+                .recv_timeout(EVENT_TIMEOUT)
+                .unwrap_or_else(|_| panic!("no {last:?} event; got {out:?}"));
+            if let Some(label) = label(&event) {
+                let done = label.starts_with(last);
+                out.push(label);
+                if done {
+                    return out;
                 }
-                TranslateMessage(&msg);
-                DispatchMessageW(&msg);
             }
         }
-        true
     }
 
-    fn try_recv_cmd(&self) -> Option<TrayCmd> {
-        self.cmd_rx.try_recv().ok()
+    #[test]
+    fn dictation_streams_partials_then_the_transcript() {
+        let (cmd_tx, evt_rx) = runtime();
+        cmd_tx.send(start(mock(300))).unwrap();
+        assert_eq!(events_until(&evt_rx, "started"), ["mic:true", "started"]);
+        let partial = events_until(&evt_rx, "partial:");
+        assert_eq!(partial.len(), 1, "{partial:?}");
+        assert!("partial:One. Two.".starts_with(&partial[0]), "{partial:?}");
+
+        cmd_tx.send(SpeechCommand::Stop { at: Instant::now() }).unwrap();
+        let events = events_until(&evt_rx, "transcript:");
+        let stopped = events.iter().position(|e| e == "stopped").unwrap();
+        assert!(events[..stopped].iter().all(|e| e.starts_with("partial:")), "{events:?}");
+        assert_eq!(
+            events[stopped..],
+            ["stopped", "mic:false", "processing", "partial:One.", "partial:Two.", "transcript:One. Two."]
+        );
     }
 
-    fn set_startup_checked(&self, on: bool) {
-        unsafe {
-            // Rebuild the menu label to reflect current state.
-            while RemoveMenu(self.hmenu, 0, MF_BYPOSITION) != 0 {}
-            append_menu_str(self.hmenu, ID_TRAY_STARTUP as u32, startup_label(on));
-            append_menu_str(self.hmenu, ID_TRAY_QUIT as u32, "Quit");
-        }
+    #[test]
+    fn cancel_discards_the_recording() {
+        let (cmd_tx, evt_rx) = runtime();
+        cmd_tx.send(start(mock(300))).unwrap();
+        events_until(&evt_rx, "started");
+        thread::sleep(Duration::from_millis(50));
+        cmd_tx.send(SpeechCommand::Cancel).unwrap();
+        cmd_tx.send(SpeechCommand::Reinject).unwrap();
+        let events = events_until(&evt_rx, "error:");
+        let events: Vec<&String> = events.iter().filter(|e| !e.starts_with("partial:")).collect();
+        assert_eq!(events, ["stopped", "mic:false", "error:No transcript to re-inject yet"]);
     }
-}
 
-fn startup_label(enabled: bool) -> &'static str {
-    if enabled {
-        "Disable Start with Windows"
-    } else {
-        "Enable Start with Windows"
-    }
-}
-
-extern "system" fn tray_wnd_proc(
-    hwnd: HWND,
-    msg: u32,
-    wparam: WPARAM,
-    lparam: LPARAM,
-) -> LRESULT {
-    unsafe {
-        match msg {
-            WM_TRAYICON => {
-                if lparam as u32 == WM_RBUTTONUP {
-                    SetForegroundWindow(hwnd);
-                    // Build a fresh menu near the cursor.
-                    let hmenu = CreatePopupMenu();
-                    let startup = is_startup_enabled();
-                    append_menu_str(hmenu, ID_TRAY_STARTUP as u32, startup_label(startup));
-                    append_menu_str(hmenu, ID_TRAY_QUIT as u32, "Quit");
-                    let mut pt = POINT { x: 0, y: 0 };
-                    GetCursorPos(&mut pt);
-                    let sel = TrackPopupMenu(
-                        hmenu,
-                        TPM_LEFTALIGN | TPM_BOTTOMALIGN | TPM_RIGHTBUTTON | TPM_RETURNCMD,
-                        pt.x,
-                        pt.y,
-                        0,
-                        hwnd,
-                        std::ptr::null(),
-                    );
-                    match sel as u16 {
-                        ID_TRAY_QUIT => {
-                            send_tray_cmd(TrayCmd::Quit);
-                            PostQuitMessage(0);
-                        }
-                        ID_TRAY_STARTUP => {
-                            send_tray_cmd(TrayCmd::ToggleStartup);
-                        }
-                        _ => {}
-                    }
-                    // Per docs, post a null message after TrackPopupMenu.
-                    PostMessageW(hwnd, WM_NULL, 0, 0);
-                }
-                0
-            }
-            WM_COMMAND => {
-                let id = (wparam & 0xFFFF) as u16;
-                match id {
-                    ID_TRAY_QUIT => {
-                        send_tray_cmd(TrayCmd::Quit);
-                        PostQuitMessage(0);
-                    }
-                    ID_TRAY_STARTUP => {
-                        send_tray_cmd(TrayCmd::ToggleStartup);
-                    }
-                    _ => {}
-                }
-                0
-            }
-            WM_DESTROY => {
-                Shell_NotifyIconW(
-                    NIM_DELETE,
-                    &mut NOTIFYICONDATAW {
-                        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
-                        hWnd: hwnd,
-                        uID: 1,
-                        ..unsafe { std::mem::zeroed() }
-                    },
-                );
-                PostQuitMessage(0);
-                0
-            }
-            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
-        }
+    #[test]
+    fn stop_cancels_the_partial_pass_in_flight() {
+        let (cmd_tx, evt_rx) = runtime();
+        let slow_partials = EngineConfig::Mock(MockConfig {
+            replies: vec!["One. Two.".into()],
+            decode_ms: 10,
+            partial_ms: 60_000,
+            confidence: 0.9,
+        });
+        cmd_tx.send(start(slow_partials)).unwrap();
+        events_until(&evt_rx, "started");
+        // Past the first re-decode tick, so a partial pass is running.
+        thread::sleep(Duration::from_millis(1_200));
+        cmd_tx.send(SpeechCommand::Stop { at: Instant::now() }).unwrap();
+        let events = events_until(&evt_rx, "transcript:");
+        assert_eq!(events.last().unwrap(), "transcript:One. Two.");
+        assert!(!events.iter().any(|e| e.starts_with("error:")), "{events:?}");
     }
-}
 
-fn append_menu_str(hmenu: HMENU, id: u32, label: &str) {
-    let w = wide(label);
-    unsafe {
-        windows_sys::Win32::UI::WindowsAndMessaging::AppendMenuW(
-            hmenu,
-            0,
-            id as usize,
-            w.as_ptr(),
+    #[test]
+    fn retranscribe_revises_the_last_dictation() {
+        let (cmd_tx, evt_rx) = runtime();
+        cmd_tx.send(start(mock(300))).unwrap();
+        events_until(&evt_rx, "started");
+        thread::sleep(Duration::from_millis(50));
+        cmd_tx.send(SpeechCommand::Stop { at: Instant::now() }).unwrap();
+        events_until(&evt_rx, "transcript:");
+
+        cmd_tx
+            .send(SpeechCommand::Retranscribe {
+                model_path: "large.bin".into(),
+                beam_size: 5,
+                engine: mock(300),
+            })
+            .unwrap();
+        assert_eq!(
+            events_until(&evt_rx, "revised:"),
+            ["processing", "partial:Better take.", "revised:Better take."]
         );
+        cmd_tx.send(SpeechCommand::Reinject).unwrap();
+        assert_eq!(events_until(&evt_rx, "reinject:"), ["reinject:Better take."]);
     }
-}
 
-fn wide(s: &str) -> Vec<u16> {
-    use std::os::windows::ffi::OsStrExt;
-    std::ffi::OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+    #[test]
+    fn network_model_names_stay_in_the_model_directories() {
+        let dir = std::env::temp_dir().join(format!("whistxt-models-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in ["current.bin", "ggml-tiny.bin", "sub/other.bin"] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        let cfg = Config {
+            final_model_path: dir.join("current.bin").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let current = cfg.final_model_file();
+        assert_eq!(network_model_path(Some("tiny"), &cfg), dir.join("ggml-tiny.bin").to_string_lossy());
+        assert_eq!(network_model_path(Some("ggml-tiny.bin"), &cfg), dir.join("ggml-tiny.bin").to_string_lossy());
+        let outside = dir.join("sub/other.bin").to_string_lossy().into_owned();
+        for name in ["whisper-1", "sub/other.bin", "sub\\other.bin", "../sub/other.bin", "..", "", &outside] {
+            assert_eq!(network_model_path(Some(name), &cfg), current, "{name}");
+        }
+        assert_eq!(network_model_path(None, &cfg), current);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
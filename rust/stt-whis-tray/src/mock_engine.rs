@@ -0,0 +1,203 @@
+// ----- Mock engine -----
+//
+// Deterministic stand-in for a recognizer (`"engine": {"type": "mock"}`).
+// Final and re-transcribe passes return `replies` in turn, wrapping around,
+// with each sentence streamed as a segment over `decode_ms`. Partial passes
+// return the start of the reply that comes next, WORDS_PER_SEC words per
+// second of audio, after `partial_ms`. No model is read.
+
+use crate::engine::{CancelToken, DecodeOptions, SpeechEngine};
+use crate::{model_name, Segment, TranscriptMeta};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WORDS_PER_SEC: f32 = 2.5;
+const POLL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MockConfig {
+    /// Transcripts, used in order and then from the start again.
+    #[serde(default = "default_replies")]
+    pub replies: Vec<String>,
+    /// Simulated time of a final or re-transcribe pass.
+    #[serde(default = "default_decode_ms")]
+    pub decode_ms: u64,
+    /// Simulated time of a partial (overlay) pass.
+    #[serde(default = "default_partial_ms")]
+    pub partial_ms: u64,
+    /// Confidence (0.0-1.0) reported for every transcript.
+    #[serde(default = "default_confidence")]
+    pub confidence: f32,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            replies: default_replies(),
+            decode_ms: default_decode_ms(),
+            partial_ms: default_partial_ms(),
+            confidence: default_confidence(),
+        }
+    }
+}
+
+fn default_replies() -> Vec<String> {
+    vec!["This is a mock transcript.".to_string()]
+}
+
+fn default_decode_ms() -> u64 {
+    300
+}
+
+fn default_partial_ms() -> u64 {
+    50
+}
+
+fn default_confidence() -> f32 {
+    0.9
+}
+
+pub struct MockEngine {
+    cfg: MockConfig,
+    /// Index of the next reply.
+    next: AtomicUsize,
+}
+
+impl MockEngine {
+    pub fn new(cfg: MockConfig) -> Self {
+        Self {
+            cfg,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn reply(&self, index: usize) -> &str {
+        match self.cfg.replies.len() {
+            0 => "",
+            n => &self.cfg.replies[index % n],
+        }
+    }
+
+    /// Sleep until `until`, or fail once `cancel` is cancelled.
+    fn wait(until: Instant, cancel: &CancelToken) -> Result<(), String> {
+        loop {
+            if cancel.is_cancelled() {
+                return Err("transcribe: aborted".into());
+            }
+            let now = Instant::now();
+            if now >= until {
+                return Ok(());
+            }
+            thread::sleep(POLL.min(until - now));
+        }
+    }
+}
+
+impl SpeechEngine for MockEngine {
+    fn backend(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn has_model(&self, _model: &str) -> bool {
+        true
+    }
+
+    fn load(&self, _model: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn transcribe_streaming(
+        &self,
+        model: &str,
+        audio: &[f32],
+        opts: &DecodeOptions,
+        on_segment: &mut dyn FnMut(&str),
+    ) -> Result<(String, TranscriptMeta), String> {
+        let decode_start = Instant::now();
+        let audio_duration = Duration::from_secs_f32(audio.len() as f32 / 16_000.0);
+        let mut segments = Vec::new();
+        let text = if opts.partial {
+            let words = (audio_duration.as_secs_f32() * WORDS_PER_SEC).ceil() as usize;
+            let reply = self.reply(self.next.load(Ordering::SeqCst));
+            Self::wait(decode_start + Duration::from_millis(self.cfg.partial_ms), &opts.cancel)?;
+            reply.split_whitespace().take(words).collect::<Vec<_>>().join(" ")
+        } else {
+            let reply = self.reply(self.next.fetch_add(1, Ordering::SeqCst));
+            let sentences = sentences(reply);
+            let step = Duration::from_millis(self.cfg.decode_ms) / sentences.len().max(1) as u32;
+            // Sentences share the audio evenly.
+            let span = audio_duration / sentences.len().max(1) as u32;
+            for (i, sentence) in sentences.iter().enumerate() {
+                Self::wait(decode_start + step * (i as u32 + 1), &opts.cancel)?;
+                on_segment(sentence);
+                segments.push(Segment {
+                    start: span * i as u32,
+                    end: span * (i as u32 + 1),
+                    text: sentence.trim().to_string(),
+                });
+            }
+            Self::wait(decode_start + Duration::from_millis(self.cfg.decode_ms), &opts.cancel)?;
+            reply.trim().to_string()
+        };
+        let meta = TranscriptMeta {
+            language: Some(opts.language.clone().unwrap_or_else(|| "en".to_string())),
+            avg_logprob: self.cfg.confidence.max(f32::MIN_POSITIVE).ln(),
+            audio_duration,
+            decode_time: decode_start.elapsed(),
+            model: model_name(model),
+            backend: self.backend(),
+            segments,
+            ..TranscriptMeta::default()
+        };
+        Ok((text, meta))
+    }
+}
+
+/// `text` cut after every `.`, `!` or `?` that ends a word, each piece with
+/// its leading space like whisper segments.
+fn sentences(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        current.push(' ');
+        current.push_str(word);
+        if word.ends_with(['.', '!', '?']) {
+            out.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn cancelling_one_decode_leaves_the_others_running() {
+        let engine = Arc::new(MockEngine::new(MockConfig {
+            replies: vec!["Kept.".into()],
+            decode_ms: 300,
+            ..MockConfig::default()
+        }));
+        let audio = vec![0.0; 16_000];
+        let cancelled = DecodeOptions::default();
+        let other = {
+            let (engine, audio) = (engine.clone(), audio.clone());
+            thread::spawn(move || engine.transcribe("m", &audio, &DecodeOptions::default()))
+        };
+        let cancel = cancelled.cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        assert_eq!(engine.transcribe("m", &audio, &cancelled).unwrap_err(), "transcribe: aborted");
+        assert_eq!(other.join().unwrap().unwrap().0, "Kept.");
+    }
+}
@@ -0,0 +1,142 @@
+// ----- Loaded models -----
+//
+// Whisper contexts are loaded once and shared (`Arc`) by the final decode,
+// the partial thread and re-transcribe. Before a load the model's footprint
+// is estimated from its file size and compared with the memory the OS
+// reports as available; when it does not fit, loaded models the caller does
+// not need are unloaded first, least recently used first. A model some decode
+// still holds is never picked: dropping it would free nothing. On CUDA builds
+// the weights live in VRAM, which is not checked.
+
+use log::{info, warn};
+use std::fs;
+use std::sync::Arc;
+use std::time::Instant;
+use whisper_rs::{WhisperContext, WhisperContextParameters};
+
+const MB: u64 = 1024 * 1024;
+/// Decoder state and scratch buffers on top of the weights.
+const RUNTIME_OVERHEAD: u64 = 200 * MB;
+/// Left free for the rest of the system after a load.
+const HEADROOM: u64 = 512 * MB;
+
+struct Loaded {
+    path: String,
+    ctx: Arc<WhisperContext>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+pub struct ModelCache {
+    loaded: Vec<Loaded>,
+}
+
+impl ModelCache {
+    /// The context for `path` if it is loaded.
+    pub fn get(&mut self, path: &str) -> Option<Arc<WhisperContext>> {
+        let model = self.loaded.iter_mut().find(|m| m.path == path)?;
+        model.last_used = Instant::now();
+        Some(model.ctx.clone())
+    }
+
+    /// The context for `path`, loading it if needed. When memory is short,
+    /// loaded models not listed in `keep` are unloaded first; if that is not
+    /// enough the model is loaded anyway.
+    pub fn load(&mut self, path: &str, keep: &[&str]) -> Result<Arc<WhisperContext>, String> {
+        if let Some(ctx) = self.get(path) {
+            return Ok(ctx);
+        }
+        let need = estimate(path);
+        if !self.make_room(path, need, keep) {
+            warn!(
+                "{path} needs about {} MB but only {} MB is free; loading anyway",
+                need / MB,
+                available_memory().unwrap_or(0) / MB
+            );
+        }
+        self.insert(path, need)
+    }
+
+    /// Like `load`, but `None` when the model does not fit even after
+    /// unloading everything outside `keep`.
+    pub fn try_load(&mut self, path: &str, keep: &[&str]) -> Option<Result<Arc<WhisperContext>, String>> {
+        if let Some(ctx) = self.get(path) {
+            return Some(Ok(ctx));
+        }
+        let need = estimate(path);
+        self.make_room(path, need, keep)
+            .then(|| self.insert(path, need))
+    }
+
+    /// Unload least recently used models outside `keep` that nobody else
+    /// holds until `need` bytes are free. False if that was not enough.
+    fn make_room(&mut self, path: &str, need: u64, keep: &[&str]) -> bool {
+        while !has_room(need) {
+            let victim = self
+                .loaded
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| !keep.contains(&m.path.as_str()) && Arc::strong_count(&m.ctx) == 1)
+                .min_by_key(|(_, m)| m.last_used)
+                .map(|(i, _)| i);
+            let Some(i) = victim else { return false };
+            let model = self.loaded.remove(i);
+            info!("Unloading {} to make room for {path}", model.path);
+        }
+        true
+    }
+
+    fn insert(&mut self, path: &str, need: u64) -> Result<Arc<WhisperContext>, String> {
+        let started = Instant::now();
+        let ctx = WhisperContext::new_with_params(path, WhisperContextParameters::default())
+            .map_err(|e| format!("failed to load model: {e}"))?;
+        info!(
+            "Loaded {path} (~{} MB) in {} ms",
+            need / MB,
+            started.elapsed().as_millis()
+        );
+        let ctx = Arc::new(ctx);
+        self.loaded.push(Loaded {
+            path: path.to_string(),
+            ctx: ctx.clone(),
+            last_used: Instant::now(),
+        });
+        Ok(ctx)
+    }
+}
+
+/// Rough resident size of a model: its weights plus runtime buffers.
+fn estimate(path: &str) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0) + RUNTIME_OVERHEAD
+}
+
+/// True when the OS cannot tell, so loading is never blocked on a guess.
+fn has_room(need: u64) -> bool {
+    available_memory().is_none_or(|free| free >= need + HEADROOM)
+}
+
+#[cfg(windows)]
+fn available_memory() -> Option<u64> {
+    use windows_sys::Win32::System::SystemInformation::{GlobalMemoryStatusEx, MEMORYSTATUSEX};
+
+    let mut status: MEMORYSTATUSEX = unsafe { std::mem::zeroed() };
+    status.dwLength = std::mem::size_of::<MEMORYSTATUSEX>() as u32;
+    if unsafe { GlobalMemoryStatusEx(&mut status) } == 0 {
+        return None;
+    }
+    Some(status.ullAvailPhys)
+}
+
+/// `MemAvailable` from /proc/meminfo (reported in kB).
+#[cfg(target_os = "linux")]
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn available_memory() -> Option<u64> {
+    None
+}
@@ -0,0 +1,380 @@
+// ----- Transcript outputs (sinks) -----
+//
+// `Config::outputs` lists where final transcripts go. `inject` is the classic
+// focused-window injection and runs on the app thread; every other sink gets
+// its own worker so a slow webhook or a pipe without a reader never delays the
+// others. Sink failures are reported as `SpeechEvent::Error`.
+
+use crate::{SpeechEvent, TranscriptMeta};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const JSON_LINE_TEMPLATE: &str = "{json}\n";
+const NOTES_TEMPLATE: &str = "- **{time}** {text}\n";
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputSink {
+    /// Type/paste into the focused window (see `inject_mode` / `injection`).
+    Inject,
+    /// Append to a file; `{date}` in the path gives one file per day. Paths
+    /// take only `{date}`, `{time}` and `{timestamp}`.
+    File {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        template: Option<String>,
+    },
+    Stdout {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        template: Option<String>,
+    },
+    /// Unix FIFO or Windows named pipe (`\\.\pipe\name`) that a reader already opened.
+    Pipe {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        template: Option<String>,
+    },
+    /// Unix domain stream socket; on other platforms every write fails.
+    UnixSocket {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        template: Option<String>,
+    },
+    /// HTTP POST (plain `http://` only) with the rendered template as the body.
+    Webhook {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        template: Option<String>,
+        #[serde(default = "default_content_type")]
+        content_type: String,
+    },
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+pub fn default_outputs() -> Vec<OutputSink> {
+    vec![OutputSink::Inject]
+}
+
+impl OutputSink {
+    fn name(&self) -> &'static str {
+        match self {
+            OutputSink::Inject => "inject",
+            OutputSink::File { .. } => "file",
+            OutputSink::Stdout { .. } => "stdout",
+            OutputSink::Pipe { .. } => "pipe",
+            OutputSink::UnixSocket { .. } => "unix_socket",
+            OutputSink::Webhook { .. } => "webhook",
+        }
+    }
+
+    fn template(&self) -> &str {
+        let (custom, fallback) = match self {
+            OutputSink::Inject => (None, "{text}"),
+            OutputSink::File { template, .. } => (template.as_deref(), NOTES_TEMPLATE),
+            OutputSink::Stdout { template }
+            | OutputSink::Pipe { template, .. }
+            | OutputSink::Webhook { template, .. } => (template.as_deref(), JSON_LINE_TEMPLATE),
+            OutputSink::UnixSocket { template, .. } => (template.as_deref(), JSON_LINE_TEMPLATE),
+        };
+        custom.unwrap_or(fallback)
+    }
+
+    fn write(&self, record: &TranscriptRecord) -> Result<(), String> {
+        let body = record.render(self.template());
+        match self {
+            OutputSink::Inject => Ok(()),
+            OutputSink::File { path, .. } => {
+                let path = record.render_path(path);
+                if let Some(parent) = Path::new(&path).parent() {
+                    fs::create_dir_all(parent).map_err(|e| format!("{path}: {e}"))?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| format!("{path}: {e}"))?;
+                file.write_all(body.as_bytes())
+                    .map_err(|e| format!("{path}: {e}"))
+            }
+            OutputSink::Stdout { .. } => {
+                let mut out = std::io::stdout().lock();
+                out.write_all(body.as_bytes())
+                    .and_then(|_| out.flush())
+                    .map_err(|e| format!("stdout: {e}"))
+            }
+            OutputSink::Pipe { path, .. } => {
+                let mut pipe = open_pipe(path).map_err(|e| format!("{path}: {e}"))?;
+                pipe.write_all(body.as_bytes())
+                    .map_err(|e| format!("{path}: {e}"))
+            }
+            OutputSink::UnixSocket { path, .. } => write_unix_socket(path, &body),
+            OutputSink::Webhook {
+                url, content_type, ..
+            } => ureq::post(url)
+                .timeout(WEBHOOK_TIMEOUT)
+                .set("Content-Type", content_type)
+                .send_string(&body)
+                .map(|_| ())
+                .map_err(|e| format!("{url}: {e}")),
+        }
+    }
+}
+
+/// Non-blocking open so a FIFO without a reader fails fast instead of hanging the worker.
+#[cfg(unix)]
+fn open_pipe(path: &str) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_pipe(path: &str) -> std::io::Result<fs::File> {
+    OpenOptions::new().write(true).open(path)
+}
+
+#[cfg(unix)]
+fn write_unix_socket(path: &str, body: &str) -> Result<(), String> {
+    let mut sock = std::os::unix::net::UnixStream::connect(path).map_err(|e| format!("{path}: {e}"))?;
+    sock.write_all(body.as_bytes())
+        .map_err(|e| format!("{path}: {e}"))
+}
+
+/// Kept so a config shared with a Unix machine still loads; the worker
+/// reports the failure as `SpeechEvent::Error`.
+#[cfg(not(unix))]
+fn write_unix_socket(path: &str, _body: &str) -> Result<(), String> {
+    Err(format!("{path}: unsupported on this platform"))
+}
+
+/// A final transcript as seen by the sinks.
+#[derive(Clone, Debug)]
+pub struct TranscriptRecord {
+    pub text: String,
+    pub meta: TranscriptMeta,
+    pub timestamp: DateTime<Local>,
+}
+
+impl TranscriptRecord {
+    pub fn new(text: String, meta: TranscriptMeta) -> Self {
+        Self {
+            text,
+            meta,
+            timestamp: Local::now(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "text": self.text,
+            "timestamp": self.timestamp.to_rfc3339(),
+            "language": self.meta.language,
+            "language_prob": self.meta.language_prob,
+            "avg_logprob": self.meta.avg_logprob,
+            "no_speech_prob": self.meta.no_speech_prob,
+            "confidence": self.meta.confidence(),
+            "duration_ms": self.meta.audio_duration.as_millis() as u64,
+            "decode_ms": self.meta.decode_time.as_millis() as u64,
+            "model": self.meta.model,
+            "partial": self.meta.partial.as_ref().map(|p| serde_json::json!({
+                "model": p.model,
+                "passes": p.passes,
+                "mean_ms": p.mean().as_millis() as u64,
+                "max_ms": p.max.as_millis() as u64,
+                "first_text_ms": p.first_text.map(|t| t.as_millis() as u64),
+            })),
+        })
+    }
+
+    /// Expand `{text}`, `{json}`, `{timestamp}`, `{date}`, `{time}`, `{language}`,
+    /// `{confidence}` and `{duration}` (seconds). The template is scanned
+    /// once, so placeholders inside substituted values (dictated "{date}",
+    /// or `{text}` within `{json}`) stay as they are. Unknown names are kept.
+    pub fn render(&self, template: &str) -> String {
+        expand(template, |name| self.placeholder(name))
+    }
+
+    /// Like `render`, for file paths: only `{date}`, `{time}` and
+    /// `{timestamp}`, so dictated text never picks where the file goes.
+    fn render_path(&self, template: &str) -> String {
+        expand(template, |name| match name {
+            "date" | "time" | "timestamp" => self.placeholder(name),
+            _ => None,
+        })
+    }
+
+    fn placeholder(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "text" => self.text.clone(),
+            "json" => self.to_json().to_string(),
+            "timestamp" => self.timestamp.to_rfc3339(),
+            "date" => self.timestamp.format("%Y-%m-%d").to_string(),
+            "time" => self.timestamp.format("%H:%M:%S").to_string(),
+            "language" => self.meta.language.clone().unwrap_or_default(),
+            "confidence" => format!("{:.3}", self.meta.confidence()),
+            "duration" => format!("{:.2}", self.meta.audio_duration.as_secs_f32()),
+            _ => return None,
+        })
+    }
+}
+
+/// Replace each `{name}` in `template` that `lookup` knows, in one scan.
+fn expand(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest
+            .find('}')
+            .and_then(|close| Some((lookup(&rest[1..close])?, close)));
+        match value {
+            Some((value, close)) => {
+                out.push_str(&value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub struct Outputs {
+    sinks: Vec<OutputSink>,
+    evt_tx: Sender<SpeechEvent>,
+    inject: bool,
+    workers: Vec<Sender<Arc<TranscriptRecord>>>,
+}
+
+impl Outputs {
+    pub fn start(sinks: &[OutputSink], evt_tx: Sender<SpeechEvent>) -> Self {
+        let mut inject = false;
+        let mut workers = Vec::new();
+        for sink in sinks {
+            if let OutputSink::Inject = sink {
+                inject = true;
+                continue;
+            }
+            let (tx, rx) = mpsc::channel::<Arc<TranscriptRecord>>();
+            let sink = sink.clone();
+            let err_tx = evt_tx.clone();
+            thread::spawn(move || {
+                while let Ok(record) = rx.recv() {
+                    if let Err(e) = sink.write(&record) {
+                        let _ = err_tx.send(SpeechEvent::Error(format!(
+                            "output {} failed: {e}",
+                            sink.name()
+                        )));
+                    }
+                }
+            });
+            workers.push(tx);
+        }
+        if !inject && workers.is_empty() {
+            warn!("No outputs configured; transcripts will only be shown in the overlay.");
+        }
+        Self {
+            sinks: sinks.to_vec(),
+            evt_tx,
+            inject,
+            workers,
+        }
+    }
+
+    /// Restart the workers if the configured sinks changed. Dropping the old
+    /// senders lets their workers finish queued records and exit.
+    pub fn sync(&mut self, sinks: &[OutputSink]) {
+        if self.sinks != sinks {
+            info!("Outputs changed; restarting sinks");
+            *self = Self::start(sinks, self.evt_tx.clone());
+        }
+    }
+
+    /// Whether the focused-window injection sink is enabled.
+    pub fn inject(&self) -> bool {
+        self.inject
+    }
+
+    /// Hand the record to every non-inject sink.
+    pub fn publish(&self, record: TranscriptRecord) {
+        let record = Arc::new(record);
+        for tx in &self.workers {
+            let _ = tx.send(record.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn record(text: &str) -> TranscriptRecord {
+        TranscriptRecord {
+            text: text.to_string(),
+            meta: TranscriptMeta {
+                language: Some("en".into()),
+                audio_duration: Duration::from_millis(1500),
+                ..TranscriptMeta::default()
+            },
+            timestamp: Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap(),
+        }
+    }
+
+    #[test]
+    fn render_expands_placeholders() {
+        let out = record("hi").render("{date} {time} [{language}] {duration}s: {text}");
+        assert_eq!(out, "2024-05-06 07:08:09 [en] 1.50s: hi");
+    }
+
+    #[test]
+    fn render_leaves_placeholders_in_values_alone() {
+        let rec = record("say {date} and {json}");
+        assert_eq!(rec.render("{text}"), "say {date} and {json}");
+        let json: serde_json::Value = serde_json::from_str(&rec.render("{json}")).unwrap();
+        assert_eq!(json["text"], "say {date} and {json}");
+    }
+
+    #[test]
+    fn render_keeps_unknown_and_unbalanced_braces() {
+        let rec = record("x");
+        assert_eq!(rec.render("{nope} {{text}} {text"), "{nope} {x} {text");
+        assert_eq!(rec.render("plain"), "plain");
+    }
+
+    #[test]
+    fn file_paths_only_take_time_placeholders() {
+        let rec = record("../../etc/passwd");
+        assert_eq!(
+            rec.render_path("notes/{date}/{time}-{text}-{language}.md"),
+            "notes/2024-05-06/07:08:09-{text}-{language}.md"
+        );
+        assert_eq!(rec.render_path("{timestamp}.log"), format!("{}.log", rec.timestamp.to_rfc3339()));
+    }
+
+    #[test]
+    fn unix_socket_sink_parses_on_every_platform() {
+        let sinks: Vec<OutputSink> =
+            serde_json::from_str(r#"[{"type": "unix_socket", "path": "/tmp/whistxt.sock"}]"#).unwrap();
+        assert_eq!(sinks[0].name(), "unix_socket");
+    }
+}
@@ -0,0 +1,151 @@
+// ----- Partial transcripts -----
+//
+// The loop that turns a growing audio buffer into live text: the dictation
+// overlay and WebSocket captions both run it. Engines with a live decoder
+// are fed the new audio every LIVE_TICK; the others re-decode the last
+// WINDOW_SECS of audio with the partial model every REDECODE_TICK.
+
+use crate::engine::{DecodeOptions, LiveDecoder, SpeechEngine};
+use crate::{model_name, resample_to_16k, PartialStats};
+use log::warn;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often new audio is fed to a live (streaming) decoder.
+const LIVE_TICK: Duration = Duration::from_millis(200);
+const REDECODE_TICK: Duration = Duration::from_millis(900);
+/// Only the most recent audio is re-decoded, to keep latency low.
+const WINDOW_SECS: usize = 10;
+/// How quickly a sleeping loop notices it was stopped.
+const STOP_POLL: Duration = Duration::from_millis(20);
+
+pub enum PartialMode {
+    /// Feed a decoder that streams natively.
+    Live(Box<dyn LiveDecoder>),
+    /// Re-decode the recent audio with `model`.
+    Redecode { model: String, opts: DecodeOptions },
+}
+
+/// What the partial thread hands back when it is stopped.
+pub struct PartialRun {
+    pub stats: PartialStats,
+    /// The live decoder and how many buffered samples it was fed.
+    pub live: Option<(Box<dyn LiveDecoder>, usize)>,
+}
+
+/// The partial model to use next to `final_model`: `partial_model` when it
+/// exists and fits in memory beside it, otherwise the final model.
+pub fn choose_model(engine: &dyn SpeechEngine, partial_model: String, final_model: String) -> String {
+    if partial_model == final_model {
+        return final_model;
+    }
+    if !engine.has_model(&partial_model) {
+        warn!("Partial model {partial_model} not found; using the final model");
+        return final_model;
+    }
+    match engine.load_alongside(&partial_model, &final_model) {
+        Ok(true) => partial_model,
+        Ok(false) => {
+            warn!("Not enough memory for {partial_model} next to {final_model}; using the final model for partials");
+            final_model
+        }
+        Err(e) => {
+            warn!("Partial model: {e}; using the final model");
+            final_model
+        }
+    }
+}
+
+/// Produce partials from `audio` (at `sample_rate`) until `running` is
+/// cleared, passing each new text to `on_text`. `model` names the model in
+/// the stats. The buffer may be cleared or trimmed meanwhile.
+pub fn spawn(
+    engine: Arc<dyn SpeechEngine>,
+    model: &str,
+    mode: PartialMode,
+    audio: Arc<Mutex<Vec<f32>>>,
+    sample_rate: u32,
+    running: Arc<AtomicBool>,
+    mut on_text: impl FnMut(String) + Send + 'static,
+) -> JoinHandle<PartialRun> {
+    let mut stats = PartialStats {
+        model: model_name(model),
+        ..PartialStats::default()
+    };
+    running.store(true, Ordering::SeqCst);
+    thread::spawn(move || {
+        let started = Instant::now();
+        match mode {
+            PartialMode::Live(mut decoder) => {
+                let mut fed = 0;
+                while running.load(Ordering::SeqCst) {
+                    let chunk = match audio.try_lock() {
+                        Some(guard) => {
+                            fed = fed.min(guard.len());
+                            let chunk = guard[fed..].to_vec();
+                            fed = guard.len();
+                            chunk
+                        }
+                        None => Vec::new(),
+                    };
+                    if !chunk.is_empty() {
+                        let pass_start = Instant::now();
+                        let text = decoder.accept(&resample_to_16k(&chunk, sample_rate));
+                        stats.add_pass(pass_start.elapsed());
+                        if let Some(txt) = text.filter(|t| !t.is_empty()) {
+                            stats.first_text.get_or_insert(started.elapsed());
+                            on_text(txt);
+                        }
+                    }
+                    pause(&running, LIVE_TICK);
+                }
+                PartialRun {
+                    stats,
+                    live: Some((decoder, fed)),
+                }
+            }
+            PartialMode::Redecode { model, opts } => {
+                let mut last_text = String::new();
+                while running.load(Ordering::SeqCst) {
+                    let samples: Vec<f32> = match audio.try_lock() {
+                        Some(guard) => {
+                            let tail = guard.len().saturating_sub(sample_rate as usize * WINDOW_SECS);
+                            guard[tail..].to_vec()
+                        }
+                        None => Vec::new(),
+                    };
+                    if samples.len() > sample_rate as usize {
+                        let pcm = resample_to_16k(&samples, sample_rate);
+                        let pass_start = Instant::now();
+                        let result = engine.transcribe(&model, &pcm, &opts);
+                        stats.add_pass(pass_start.elapsed());
+                        if let Ok((txt, _)) = result {
+                            if !txt.is_empty() && txt != last_text {
+                                stats.first_text.get_or_insert(started.elapsed());
+                                last_text = txt.clone();
+                                on_text(txt);
+                            }
+                        }
+                    }
+                    pause(&running, REDECODE_TICK);
+                }
+                PartialRun { stats, live: None }
+            }
+        }
+    })
+}
+
+/// Sleep for `duration`, or less once `running` is cleared.
+fn pause(running: &AtomicBool, duration: Duration) {
+    let until = Instant::now() + duration;
+    while running.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= until {
+            return;
+        }
+        thread::sleep(STOP_POLL.min(until - now));
+    }
+}
@@ -0,0 +1,178 @@
+// ----- File locations -----
+//
+// Where config, data (models, recordings, history), cache and logs live.
+// Per platform:
+//   Windows: config %APPDATA%\WhisTray, everything else %LOCALAPPDATA%\WhisTray
+//   Linux:   $XDG_CONFIG_HOME, $XDG_DATA_HOME, $XDG_CACHE_HOME and
+//            $XDG_STATE_HOME (logs), each with a `whistxt` subdirectory
+//   macOS:   ~/Library/Application Support/WhisTray, ~/Library/Caches/WhisTray,
+//            ~/Library/Logs/WhisTray
+// Portable mode keeps everything next to the executable: it is enabled by a
+// file named `portable` beside the binary or by WHISTXT_PORTABLE=1.
+// Individual locations can be overridden with WHISTXT_CONFIG (the file),
+// WHISTXT_CONFIG_DIR, WHISTXT_DATA_DIR, WHISTXT_CACHE_DIR and WHISTXT_LOG_DIR;
+// `--config` beats all of them.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[cfg(target_os = "linux")]
+const APP_DIR: &str = "whistxt";
+#[cfg(not(target_os = "linux"))]
+const APP_DIR: &str = "WhisTray";
+
+const CONFIG_FILE: &str = "config.json";
+const PORTABLE_MARKER: &str = "portable";
+const DEFAULT_MODEL_FILE: &str = "ggml-small-q5_1.bin";
+
+static PATHS: OnceLock<AppPaths> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct AppPaths {
+    pub config_file: PathBuf,
+    pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub log_dir: PathBuf,
+    pub portable: bool,
+}
+
+/// Resolve the locations once, honouring a `--config` override. Must run
+/// before anything calls `get`, or the override is ignored.
+pub fn init(config_override: Option<PathBuf>) -> &'static AppPaths {
+    PATHS.get_or_init(|| AppPaths::discover(config_override))
+}
+
+pub fn get() -> &'static AppPaths {
+    init(None)
+}
+
+impl AppPaths {
+    fn discover(config_override: Option<PathBuf>) -> Self {
+        let exe_dir = env::current_exe()
+            .ok()
+            .and_then(|p| p.parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| PathBuf::from("."));
+        let portable = env_flag("WHISTXT_PORTABLE") || exe_dir.join(PORTABLE_MARKER).exists();
+
+        let (config_dir, data_dir, cache_dir, log_dir) = if portable {
+            (
+                exe_dir.clone(),
+                exe_dir.join("data"),
+                exe_dir.join("cache"),
+                exe_dir.join("logs"),
+            )
+        } else {
+            platform_dirs().unwrap_or_else(|| {
+                // No home directory at all (e.g. a stripped service account).
+                (
+                    exe_dir.clone(),
+                    exe_dir.join("data"),
+                    exe_dir.join("cache"),
+                    exe_dir.join("logs"),
+                )
+            })
+        };
+
+        let config_file = config_override
+            .or_else(|| env_path("WHISTXT_CONFIG"))
+            .unwrap_or_else(|| {
+                env_path("WHISTXT_CONFIG_DIR")
+                    .unwrap_or(config_dir)
+                    .join(CONFIG_FILE)
+            });
+        Self {
+            config_file: absolute(config_file),
+            data_dir: absolute(env_path("WHISTXT_DATA_DIR").unwrap_or(data_dir)),
+            cache_dir: absolute(env_path("WHISTXT_CACHE_DIR").unwrap_or(cache_dir)),
+            log_dir: absolute(env_path("WHISTXT_LOG_DIR").unwrap_or(log_dir)),
+            portable,
+        }
+    }
+
+    /// Directory holding config.json; relative paths in the config resolve against it.
+    pub fn config_dir(&self) -> &Path {
+        self.config_file.parent().unwrap_or(Path::new("."))
+    }
+
+    pub fn models_dir(&self) -> PathBuf {
+        self.data_dir.join("models")
+    }
+
+    pub fn history_file(&self) -> PathBuf {
+        self.data_dir.join("history.jsonl")
+    }
+
+    /// Default `final_model_path` for new configs. Portable installs store it
+    /// relative to the config so the folder can be moved as a whole.
+    pub fn default_model_path(&self) -> String {
+        let path = self.models_dir().join(DEFAULT_MODEL_FILE);
+        let path = match (self.portable, path.strip_prefix(self.config_dir())) {
+            (true, Ok(rel)) => rel.to_path_buf(),
+            _ => path,
+        };
+        path.to_string_lossy().replace('\\', "/")
+    }
+
+    /// Resolve a path from the config: absolute paths are used as-is,
+    /// relative ones are taken from the config directory.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.config_dir().join(path)
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn platform_dirs() -> Option<(PathBuf, PathBuf, PathBuf, PathBuf)> {
+    // Roaming config (FOLDERID_RoamingAppData), machine-local data (FOLDERID_LocalAppData).
+    let config = dirs::config_dir()?.join(APP_DIR);
+    let local = dirs::data_local_dir()?.join(APP_DIR);
+    Some((config, local.clone(), local.join("cache"), local.join("logs")))
+}
+
+#[cfg(target_os = "linux")]
+fn platform_dirs() -> Option<(PathBuf, PathBuf, PathBuf, PathBuf)> {
+    let data = dirs::data_dir()?.join(APP_DIR);
+    let logs = dirs::state_dir()
+        .map(|d| d.join(APP_DIR))
+        .unwrap_or_else(|| data.join("logs"));
+    Some((
+        dirs::config_dir()?.join(APP_DIR),
+        data,
+        dirs::cache_dir()?.join(APP_DIR),
+        logs,
+    ))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_dirs() -> Option<(PathBuf, PathBuf, PathBuf, PathBuf)> {
+    let support = dirs::data_dir()?.join(APP_DIR);
+    Some((
+        support.clone(),
+        support,
+        dirs::cache_dir()?.join(APP_DIR),
+        dirs::home_dir()?.join("Library").join("Logs").join(APP_DIR),
+    ))
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|v| matches!(v.as_str(), "1" | "true" | "yes" | "on"))
+}
+
+/// Anchor relative overrides to the working directory they were given in.
+fn absolute(path: PathBuf) -> PathBuf {
+    if path.is_absolute() {
+        return path;
+    }
+    env::current_dir().map(|cwd| cwd.join(&path)).unwrap_or(path)
+}
@@ -0,0 +1,239 @@
+// ----- Vosk engine -----
+//
+// Kaldi-based recognizer for machines too slow for whisper (cargo feature
+// `vosk`; libvosk is needed to build and run). A model is an unpacked
+// directory such as vosk-model-small-en-us-0.15. While recording, one
+// recognizer is fed the audio as it arrives, so Stop only flushes the last
+// utterance. Word confidences stand in for token probabilities.
+
+use crate::engine::{DecodeOptions, LiveDecoder, SpeechEngine};
+use crate::{model_name, Segment, TranscriptMeta};
+use log::{info, warn};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vosk::{CompleteResult, DecodingState, Model, Recognizer};
+
+const BACKEND: &str = "vosk";
+/// Audio per `accept_waveform` call when decoding a whole clip (0.5 s).
+const CHUNK: usize = 8_000;
+
+#[derive(Default)]
+pub struct VoskEngine {
+    models: Mutex<HashMap<String, Arc<Model>>>,
+}
+
+impl VoskEngine {
+    fn model(&self, path: &str) -> Result<Arc<Model>, String> {
+        let mut models = self.models.lock();
+        if let Some(model) = models.get(path) {
+            return Ok(model.clone());
+        }
+        let started = Instant::now();
+        let model = Model::new(path).ok_or_else(|| format!("failed to load Vosk model {path}"))?;
+        info!("Loaded {path} in {} ms", started.elapsed().as_millis());
+        let model = Arc::new(model);
+        models.insert(path.to_string(), model.clone());
+        Ok(model)
+    }
+
+    fn session(&self, path: &str, opts: &DecodeOptions) -> Result<Session, String> {
+        let model = self.model(path)?;
+        let mut rec = Recognizer::new(&model, 16_000.0)
+            .ok_or_else(|| "failed to create a Vosk recognizer".to_string())?;
+        rec.set_words(true);
+        Ok(Session {
+            rec,
+            _model: model,
+            model: model_name(path),
+            language: opts.language.clone(),
+            text: String::new(),
+            segments: Vec::new(),
+            conf_sum: 0.0,
+            words: 0,
+            samples: 0,
+        })
+    }
+}
+
+impl SpeechEngine for VoskEngine {
+    fn backend(&self) -> String {
+        BACKEND.to_string()
+    }
+
+    fn has_model(&self, model: &str) -> bool {
+        Path::new(model).is_dir()
+    }
+
+    fn load(&self, model: &str) -> Result<(), String> {
+        self.model(model).map(|_| ())
+    }
+
+    fn transcribe_streaming(
+        &self,
+        model: &str,
+        audio: &[f32],
+        opts: &DecodeOptions,
+        on_segment: &mut dyn FnMut(&str),
+    ) -> Result<(String, TranscriptMeta), String> {
+        let decode_start = Instant::now();
+        let mut session = self.session(model, opts)?;
+        for chunk in audio.chunks(CHUNK) {
+            if opts.cancel.is_cancelled() {
+                return Err("transcribe: aborted".into());
+            }
+            if let Some(utterance) = session.feed(chunk)? {
+                on_segment(&utterance);
+            }
+        }
+        Ok(session.finish(decode_start))
+    }
+
+    fn live(&self, model: &str, opts: &DecodeOptions) -> Option<Result<Box<dyn LiveDecoder>, String>> {
+        Some(self.session(model, opts).map(|session| {
+            Box::new(Live {
+                session,
+                shown: String::new(),
+                failed: false,
+            }) as Box<dyn LiveDecoder>
+        }))
+    }
+}
+
+/// One recognizer over one recording.
+struct Session {
+    rec: Recognizer,
+    /// Held for as long as the recognizer uses it.
+    _model: Arc<Model>,
+    model: String,
+    language: Option<String>,
+    /// Utterances Vosk has closed so far.
+    text: String,
+    segments: Vec<Segment>,
+    conf_sum: f32,
+    words: usize,
+    samples: usize,
+}
+
+impl Session {
+    /// Feed 16 kHz audio; the text of an utterance Vosk closed on the way.
+    fn feed(&mut self, audio: &[f32]) -> Result<Option<String>, String> {
+        let pcm: Vec<i16> = audio
+            .iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect();
+        self.samples += audio.len();
+        match self.rec.accept_waveform(&pcm) {
+            Ok(DecodingState::Running) => Ok(None),
+            Ok(DecodingState::Finalized) => {
+                let utterance = utterance(self.rec.result());
+                Ok(self.add(utterance))
+            }
+            Ok(DecodingState::Failed) => Err("transcribe: Vosk failed to decode".into()),
+            Err(e) => Err(format!("transcribe: {e}")),
+        }
+    }
+
+    fn add(&mut self, utterance: Utterance) -> Option<String> {
+        self.conf_sum += utterance.confs.iter().sum::<f32>();
+        self.words += utterance.confs.len();
+        if utterance.text.is_empty() {
+            return None;
+        }
+        if !self.text.is_empty() {
+            self.text.push(' ');
+        }
+        self.text.push_str(&utterance.text);
+        self.segments.push(Segment {
+            start: utterance.start,
+            end: utterance.end,
+            text: utterance.text.clone(),
+        });
+        Some(utterance.text)
+    }
+
+    /// Closed utterances plus the one in progress.
+    fn current(&mut self) -> String {
+        let partial = self.rec.partial_result().partial;
+        match (self.text.is_empty(), partial.is_empty()) {
+            (_, true) => self.text.clone(),
+            (true, false) => partial.to_string(),
+            (false, false) => format!("{} {partial}", self.text),
+        }
+    }
+
+    /// Flush the last utterance. `decode_start` is when decoding began
+    /// (whole clips) or when recording stopped (live).
+    fn finish(mut self, decode_start: Instant) -> (String, TranscriptMeta) {
+        let utterance = utterance(self.rec.final_result());
+        self.add(utterance);
+        let confidence = if self.words > 0 {
+            self.conf_sum / self.words as f32
+        } else {
+            1.0
+        };
+        let meta = TranscriptMeta {
+            language: self.language,
+            avg_logprob: confidence.clamp(f32::MIN_POSITIVE, 1.0).ln(),
+            audio_duration: Duration::from_secs_f32(self.samples as f32 / 16_000.0),
+            decode_time: decode_start.elapsed(),
+            model: self.model,
+            backend: BACKEND.to_string(),
+            segments: self.segments,
+            ..TranscriptMeta::default()
+        };
+        (self.text, meta)
+    }
+}
+
+#[derive(Default)]
+struct Utterance {
+    text: String,
+    /// Per word.
+    confs: Vec<f32>,
+    start: Duration,
+    end: Duration,
+}
+
+/// A result without alternatives; word times are from the recording start.
+fn utterance(result: CompleteResult) -> Utterance {
+    let CompleteResult::Single(single) = result else {
+        return Utterance::default();
+    };
+    let secs = |t: f32| Duration::from_secs_f32(t.max(0.0));
+    Utterance {
+        text: single.text.to_string(),
+        confs: single.result.iter().map(|w| w.conf).collect(),
+        start: single.result.first().map_or(Duration::ZERO, |w| secs(w.start)),
+        end: single.result.last().map_or(Duration::ZERO, |w| secs(w.end)),
+    }
+}
+
+struct Live {
+    session: Session,
+    /// Text last returned by `accept`.
+    shown: String,
+    failed: bool,
+}
+
+impl LiveDecoder for Live {
+    fn accept(&mut self, audio: &[f32]) -> Option<String> {
+        if let Err(e) = self.session.feed(audio) {
+            if !self.failed {
+                warn!("{e}");
+                self.failed = true;
+            }
+        }
+        let text = self.session.current();
+        (text != self.shown).then(|| {
+            self.shown = text.clone();
+            text
+        })
+    }
+
+    fn finish(self: Box<Self>) -> Result<(String, TranscriptMeta), String> {
+        Ok(self.session.finish(Instant::now()))
+    }
+}
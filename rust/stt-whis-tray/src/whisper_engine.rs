@@ -0,0 +1,244 @@
+// ----- whisper.cpp engine -----
+//
+// Models are kept in a `ModelCache` shared by the final pass, the partial
+// thread and re-transcribe. The partial and final models of the current
+// session (from `load_alongside`) are never unloaded to make room for each
+// other. whisper.cpp polls the decode's cancel token between encoder and
+// decoder steps and stops once it is cancelled.
+
+use crate::engine::{CancelToken, DecodeOptions, SpeechEngine};
+use crate::models::ModelCache;
+use crate::{model_name, Segment, TranscriptMeta, CUDA_BUILD_ENABLED};
+use log::warn;
+use parking_lot::Mutex;
+use std::ffi::{c_void, CStr};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext};
+
+const BACKEND: &str = if CUDA_BUILD_ENABLED { "whisper.cpp/cuda" } else { "whisper.cpp/cpu" };
+
+#[derive(Default)]
+pub struct WhisperEngine {
+    models: Mutex<ModelCache>,
+    /// The partial and final model last loaded together.
+    pair: Mutex<Option<(String, String)>>,
+}
+
+impl WhisperEngine {
+    fn context(&self, model: &str) -> Result<Arc<WhisperContext>, String> {
+        let partner = match &*self.pair.lock() {
+            Some((a, b)) if a == model => Some(b.clone()),
+            Some((a, b)) if b == model => Some(a.clone()),
+            _ => None,
+        };
+        let keep: Vec<&str> = partner.as_deref().into_iter().collect();
+        self.models.lock().load(model, &keep)
+    }
+}
+
+impl SpeechEngine for WhisperEngine {
+    fn backend(&self) -> String {
+        BACKEND.to_string()
+    }
+
+    fn has_model(&self, model: &str) -> bool {
+        Path::new(model).is_file()
+    }
+
+    fn load(&self, model: &str) -> Result<(), String> {
+        self.context(model).map(|_| ())
+    }
+
+    fn load_alongside(&self, model: &str, keep: &str) -> Result<bool, String> {
+        *self.pair.lock() = Some((model.to_string(), keep.to_string()));
+        match self.models.lock().try_load(model, &[keep]) {
+            Some(Ok(_)) => Ok(true),
+            Some(Err(e)) => Err(e),
+            None => Ok(false),
+        }
+    }
+
+    fn transcribe_streaming(
+        &self,
+        model: &str,
+        audio: &[f32],
+        opts: &DecodeOptions,
+        on_segment: &mut dyn FnMut(&str),
+    ) -> Result<(String, TranscriptMeta), String> {
+        let ctx = self.context(model)?;
+        let mut state = ctx.create_state().map_err(|e| format!("state: {e}"))?;
+        let strategy = if opts.beam_size > 1 {
+            SamplingStrategy::BeamSearch {
+                beam_size: opts.beam_size as i32,
+                patience: -1.0,
+            }
+        } else {
+            SamplingStrategy::Greedy { best_of: 1 }
+        };
+        let mut params = FullParams::new(strategy);
+        let threads = std::thread::available_parallelism()
+            .unwrap_or_else(|_| NonZeroUsize::new(2).unwrap())
+            .get();
+        params.set_n_threads(threads as i32);
+        params.set_language(opts.language.as_deref());
+        params.set_translate(false);
+        params.set_print_realtime(false);
+        params.set_print_progress(false);
+        params.set_print_timestamps(false);
+        params.set_single_segment(opts.partial);
+        if let Some(prompt) = opts.prompt.as_deref() {
+            params.set_initial_prompt(&prompt.replace('\0', ""));
+        }
+
+        // Stream segments via callback.
+        unsafe extern "C" fn on_new_segment(
+            _: *mut whisper_rs::whisper_rs_sys::whisper_context,
+            state: *mut whisper_rs::whisper_rs_sys::whisper_state,
+            n_new: std::ffi::c_int,
+            user: *mut c_void,
+        ) {
+            if user.is_null() || state.is_null() {
+                return;
+            }
+            let on_segment = &mut *(user as *mut &mut dyn FnMut(&str));
+            let total = whisper_rs::whisper_rs_sys::whisper_full_n_segments_from_state(state);
+            if total <= 0 {
+                return;
+            }
+            let start = (total - n_new).max(0);
+            for i in start..total {
+                let cstr =
+                    whisper_rs::whisper_rs_sys::whisper_full_get_segment_text_from_state(state, i);
+                if !cstr.is_null() {
+                    if let Ok(txt) = CStr::from_ptr(cstr).to_str() {
+                        on_segment(txt);
+                    }
+                }
+            }
+        }
+
+        unsafe extern "C" fn should_abort(user: *mut c_void) -> bool {
+            !user.is_null() && (*(user as *const CancelToken)).is_cancelled()
+        }
+
+        // Both outlive `full`, which is the only caller of the callbacks.
+        let mut on_segment = on_segment;
+        let cancel = &opts.cancel;
+        unsafe {
+            params.set_new_segment_callback(Some(on_new_segment));
+            params.set_new_segment_callback_user_data(
+                &mut on_segment as *mut &mut dyn FnMut(&str) as *mut c_void,
+            );
+            params.set_abort_callback(Some(should_abort));
+            params.set_abort_callback_user_data(cancel as *const CancelToken as *mut c_void);
+        }
+
+        let decode_start = Instant::now();
+        let res = state.full(params, audio).map_err(|e| format!("transcribe: {e}"));
+        if cancel.is_cancelled() {
+            return Err("transcribe: aborted".into());
+        }
+        res?;
+
+        let num_segments = state
+            .full_n_segments()
+            .map_err(|e| format!("segments: {e}"))?;
+        let mut out = String::new();
+        let mut segments = Vec::new();
+        let eot = ctx.token_eot();
+        let mut logprob_sum = 0.0f32;
+        let mut logprob_count = 0usize;
+        for i in 0..num_segments {
+            let text = state.full_get_segment_text(i).unwrap_or_default();
+            out.push_str(&text);
+            // Timestamps are in 10 ms units.
+            let centis = |t: Result<i64, _>| Duration::from_millis(t.unwrap_or(0).max(0) as u64 * 10);
+            segments.push(Segment {
+                start: centis(state.full_get_segment_t0(i)),
+                end: centis(state.full_get_segment_t1(i)),
+                text: text.trim().to_string(),
+            });
+            let n_tokens = state.full_n_tokens(i).unwrap_or(0);
+            for t in 0..n_tokens {
+                if let Ok(data) = state.full_get_token_data(i, t) {
+                    // Special tokens (timestamps, SOT, EOT...) sort after EOT.
+                    if data.id < eot {
+                        logprob_sum += data.plog;
+                        logprob_count += 1;
+                    }
+                }
+            }
+        }
+        let avg_logprob = if logprob_count > 0 {
+            logprob_sum / logprob_count as f32
+        } else {
+            0.0
+        };
+        let mut meta = TranscriptMeta {
+            language: opts.language.clone(),
+            avg_logprob,
+            audio_duration: Duration::from_secs_f32(audio.len() as f32 / 16_000.0),
+            decode_time: decode_start.elapsed(),
+            model: model_name(model),
+            backend: BACKEND.to_string(),
+            segments,
+            ..TranscriptMeta::default()
+        };
+        if opts.partial {
+            return Ok((out.trim().to_string(), meta));
+        }
+
+        // Segments have been read; the state can now be reused for the
+        // language and no-speech probes without disturbing the result.
+        // `full` left the encoder on the last window, so the probes
+        // re-encode the first one (offset 0) before decoding.
+        let mut first_window = false;
+        if opts.language.is_none() {
+            // `full` already detected the language; only its probability
+            // needs another encoder pass.
+            let id = state.full_lang_id_from_state().unwrap_or(-1);
+            meta.language = whisper_rs::get_lang_str(id).map(str::to_string);
+            if opts.language_prob && id >= 0 {
+                match state.lang_detect(0, threads) {
+                    Ok((_, probs)) => {
+                        meta.language_prob = probs.get(id as usize).copied();
+                        first_window = true;
+                    }
+                    Err(e) => warn!("language detection failed: {e}"),
+                }
+            }
+        }
+        let encoded = if first_window { Ok(()) } else { state.encode(0, threads) };
+        meta.no_speech_prob = encoded
+            .and_then(|_| state.decode(&[ctx.token_sot()], 0, threads))
+            .and_then(|_| state.get_logits().map(|l| l.to_vec()))
+            .ok()
+            .and_then(|logits| softmax_at(&logits, ctx.token_nosp() as usize));
+        Ok((out.trim().to_string(), meta))
+    }
+}
+
+/// English-only models (`*.en*`) know one language, the others all of whisper's.
+pub fn languages(model: &str) -> Vec<String> {
+    if model_name(model).contains(".en") {
+        return vec!["en".to_string()];
+    }
+    (0..=whisper_rs::get_lang_max_id())
+        .filter_map(whisper_rs::get_lang_str)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Probability of `index` under a softmax over `logits`.
+fn softmax_at(logits: &[f32], index: usize) -> Option<f32> {
+    let target = *logits.get(index)?;
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if !max.is_finite() {
+        return None;
+    }
+    let sum: f32 = logits.iter().map(|l| (l - max).exp()).sum();
+    Some((target - max).exp() / sum)
+}
@@ -0,0 +1,495 @@
+// ----- WebSocket live transcription (`serve`, /v1/audio/stream) -----
+//
+// Live captions for browser tools, on the HTTP API's address. Binary
+// messages carry audio: 16 kHz mono s16le PCM (`?format=pcm`, default) or
+// one Opus packet each (`?format=opus`). `?language=` and `?model=` work
+// like the HTTP API's fields. `{"type": "flush"}` ends the current
+// utterance; `{"type": "stop"}` also closes the session. The server answers
+// with the control API's event JSON: `partial` while audio arrives (the
+// overlay's partial loop) and `transcript` per utterance, which ends on a
+// flush or after MAX_UTTERANCE_SECS.
+//
+// Sessions decode on their own threads with the runtime's engine, so they
+// share its loaded models but not its queue; whisper gives every pass its
+// own state. Backpressure: at most MAX_SESSIONS at once, messages up to
+// MAX_MESSAGE, and the socket is not read while an utterance is decoded,
+// so a client sending faster than real time is held back by TCP. Reads
+// time out after PARTIAL_WAIT so partials go out while the client is quiet.
+
+use crate::engine::{CancelToken, DecodeOptions, SpeechEngine};
+use crate::ipc::event_to_json;
+use crate::partials::{self, PartialMode, PartialRun};
+use crate::{network_model_path, shared_engine, SharedConfig, SpeechCommand, SpeechEvent, TranscriptMeta};
+use audiopus::coder::Decoder as OpusDecoder;
+use audiopus::packet::Packet;
+use audiopus::{Channels, MutSignals, SampleRate};
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::io::{self, ErrorKind, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::{Role, WebSocketConfig};
+use tungstenite::{Message, WebSocket};
+
+pub const STREAM: &str = "/v1/audio/stream";
+const MAX_SESSIONS: usize = 4;
+/// Largest audio message: 8 s of PCM.
+const MAX_MESSAGE: usize = 256 * 1024;
+const MAX_UTTERANCE_SECS: usize = 25;
+/// Largest Opus frame (120 ms) at 16 kHz.
+const OPUS_FRAME: usize = 1_920;
+/// Longest a partial waits for the client's next message.
+const PARTIAL_WAIT: Duration = Duration::from_millis(100);
+
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A session that may start: the request was a valid upgrade and there
+/// is room for it.
+pub struct Accepted {
+    key: String,
+    slot: SessionSlot,
+    engine: Arc<dyn SpeechEngine>,
+    opus: Option<OpusDecoder>,
+    final_model: String,
+    partial_model: String,
+    language: Option<String>,
+}
+
+/// Check a `GET` of `STREAM`; an HTTP status and message when refused.
+pub fn accept(
+    url: &str,
+    headers: &[(&str, &str)],
+    speech_tx: &Sender<SpeechCommand>,
+    config: &SharedConfig,
+) -> Result<Accepted, (u16, String)> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_string())
+    };
+    let upgrade = header("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let key = header("Sec-WebSocket-Key")
+        .filter(|_| upgrade)
+        .ok_or((400, "expected a WebSocket upgrade".to_string()))?;
+    let query = |name: &str| {
+        url.split_once('?')
+            .and_then(|(_, q)| q.split('&').filter_map(|p| p.split_once('=')).find(|(k, _)| *k == name))
+            .map(|(_, v)| v.to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let opus = match query("format").as_deref() {
+        None | Some("pcm") => None,
+        Some("opus") => Some(
+            OpusDecoder::new(SampleRate::Hz16000, Channels::Mono).map_err(|e| (500, format!("Opus decoder: {e}")))?,
+        ),
+        Some(other) => return Err((400, format!("unknown format `{other}`"))),
+    };
+    let cfg = config.get();
+    let final_model = network_model_path(query("model").as_deref(), &cfg);
+    let language = match query("language").as_deref() {
+        None => cfg.language.clone(),
+        Some("auto") => None,
+        Some(code) => Some(code.to_string()),
+    };
+
+    let slot = SessionSlot::take().ok_or((503, format!("already {MAX_SESSIONS} live sessions")))?;
+    let engine = shared_engine(speech_tx, &cfg.engine).ok_or((503, "speech runtime stopped".to_string()))?;
+    if !engine.has_model(&final_model) {
+        return Err((500, format!("Model not found at {final_model}")));
+    }
+    engine.load(&final_model).map_err(|e| (500, e))?;
+    let partial_model = partials::choose_model(&*engine, cfg.partial_model_file(), final_model.clone());
+    Ok(Accepted {
+        key,
+        slot,
+        engine,
+        opus,
+        final_model,
+        partial_model,
+        language,
+    })
+}
+
+impl Accepted {
+    /// Answer the upgrade on `stream` and run the session on this thread
+    /// until it ends.
+    pub fn run(self, mut stream: TcpStream) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            derive_accept_key(self.key.as_bytes())
+        )?;
+        stream.set_read_timeout(Some(PARTIAL_WAIT))?;
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        let ws_config = WebSocketConfig {
+            max_message_size: Some(MAX_MESSAGE),
+            max_frame_size: Some(MAX_MESSAGE),
+            ..WebSocketConfig::default()
+        };
+        info!("Live session from {peer} started");
+        let (partial_tx, partial_rx) = mpsc::channel();
+        let mut session = Session {
+            socket: WebSocket::from_raw_socket(stream, Role::Server, Some(ws_config)),
+            engine: self.engine,
+            opus: self.opus,
+            final_model: self.final_model,
+            partial_model: self.partial_model,
+            language: self.language,
+            audio: Arc::new(Mutex::new(Vec::new())),
+            running: Arc::new(AtomicBool::new(false)),
+            partial_cancel: CancelToken::default(),
+            partials: None,
+            partial_tx,
+            partial_rx,
+        };
+        match session.run().map_err(|e| *e) {
+            Ok(()) | Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                info!("Live session from {peer} ended")
+            }
+            Err(e) => warn!("Live session from {peer}: {e}"),
+        }
+        session.stop_partials();
+        drop(self.slot);
+        Ok(())
+    }
+}
+
+/// One of the MAX_SESSIONS places, given back on drop.
+struct SessionSlot;
+
+impl SessionSlot {
+    fn take() -> Option<Self> {
+        SESSIONS
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < MAX_SESSIONS).then_some(n + 1))
+            .ok()
+            .map(|_| SessionSlot)
+    }
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        SESSIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Session {
+    socket: WebSocket<TcpStream>,
+    engine: Arc<dyn SpeechEngine>,
+    /// Set for `format=opus`.
+    opus: Option<OpusDecoder>,
+    final_model: String,
+    partial_model: String,
+    language: Option<String>,
+    /// The current utterance, 16 kHz mono.
+    audio: Arc<Mutex<Vec<f32>>>,
+    running: Arc<AtomicBool>,
+    /// Cuts the partial pass in flight short when the utterance ends.
+    partial_cancel: CancelToken,
+    partials: Option<JoinHandle<PartialRun>>,
+    partial_tx: Sender<String>,
+    partial_rx: Receiver<String>,
+}
+
+type WsResult = Result<(), Box<tungstenite::Error>>;
+
+impl Session {
+    fn run(&mut self) -> WsResult {
+        self.start_partials();
+        loop {
+            let message = match self.socket.read() {
+                Ok(message) => message,
+                // A quiet client; Windows reports the timeout as `TimedOut`.
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    self.send_partial()?;
+                    continue;
+                }
+                Err(e) => return Err(Box::new(e)),
+            };
+            match message {
+                Message::Binary(data) => match self.decode(&data) {
+                    Ok(samples) => {
+                        let full = {
+                            let mut audio = self.audio.lock();
+                            audio.extend(samples);
+                            audio.len() >= 16_000 * MAX_UTTERANCE_SECS
+                        };
+                        if full {
+                            self.commit()?;
+                        }
+                    }
+                    Err(e) => self.send(&SpeechEvent::Error(e))?,
+                },
+                Message::Text(text) => {
+                    let kind = serde_json::from_str::<Value>(&text)
+                        .ok()
+                        .and_then(|v| v.get("type")?.as_str().map(str::to_string));
+                    match kind.as_deref() {
+                        Some("flush") => self.commit()?,
+                        Some("stop") => {
+                            self.commit()?;
+                            self.stop_partials();
+                            self.socket.close(None)?;
+                            continue;
+                        }
+                        _ => self.send(&SpeechEvent::Error(format!("unknown message {text}")))?,
+                    }
+                }
+                // The next read reports the end of the close handshake.
+                Message::Close(_) => continue,
+                // Pings are answered by tungstenite.
+                _ => {}
+            }
+            self.send_partial()?;
+        }
+    }
+
+    /// Send the latest partial, if there is a new one; older ones are stale.
+    fn send_partial(&mut self) -> WsResult {
+        match self.partial_rx.try_iter().last() {
+            Some(text) => self.send(&SpeechEvent::Partial(text)),
+            None => Ok(()),
+        }
+    }
+
+    /// 16 kHz samples from one audio message.
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<f32>, String> {
+        let Some(decoder) = self.opus.as_mut() else {
+            let samples = data.chunks_exact(2);
+            if !samples.remainder().is_empty() {
+                return Err("PCM messages must hold whole 16-bit samples".into());
+            }
+            return Ok(samples
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)
+                .collect());
+        };
+        let mut out = vec![0.0; OPUS_FRAME];
+        let packet = Packet::try_from(data).map_err(|e| format!("Opus packet: {e}"))?;
+        let signals = MutSignals::try_from(&mut out[..]).map_err(|e| format!("Opus: {e}"))?;
+        let n = decoder
+            .decode_float(Some(packet), signals, false)
+            .map_err(|e| format!("Opus packet: {e}"))?;
+        out.truncate(n);
+        Ok(out)
+    }
+
+    fn send(&mut self, event: &SpeechEvent) -> WsResult {
+        Ok(self.socket.send(Message::Text(event_to_json(event).to_string()))?)
+    }
+
+    fn start_partials(&mut self) {
+        self.partial_cancel = CancelToken::default();
+        let opts = DecodeOptions {
+            language: self.language.clone(),
+            ..DecodeOptions::default()
+        };
+        let (model, mode) = match self.engine.live(&self.final_model, &opts) {
+            Some(Ok(decoder)) => (self.final_model.clone(), PartialMode::Live(decoder)),
+            other => {
+                if let Some(Err(e)) = other {
+                    warn!("Live decoding unavailable ({e}); re-decoding for partials");
+                }
+                let opts = DecodeOptions {
+                    partial: true,
+                    cancel: self.partial_cancel.clone(),
+                    ..opts
+                };
+                let model = self.partial_model.clone();
+                (model.clone(), PartialMode::Redecode { model, opts })
+            }
+        };
+        let partial_tx = self.partial_tx.clone();
+        self.partials = Some(partials::spawn(
+            self.engine.clone(),
+            &model,
+            mode,
+            self.audio.clone(),
+            16_000,
+            self.running.clone(),
+            move |text| {
+                let _ = partial_tx.send(text);
+            },
+        ));
+    }
+
+    fn stop_partials(&mut self) -> Option<PartialRun> {
+        self.running.store(false, Ordering::SeqCst);
+        self.partial_cancel.cancel();
+        self.partials.take().and_then(|h| h.join().ok())
+    }
+
+    /// End the utterance: send its transcript and start on the next one.
+    fn commit(&mut self) -> WsResult {
+        let run = self.stop_partials();
+        let samples = std::mem::take(&mut *self.audio.lock());
+        // Partials of the finished utterance are stale now.
+        self.partial_rx.try_iter().for_each(drop);
+        if !samples.is_empty() {
+            let (stats, live) = match run {
+                Some(run) => (Some(run.stats), run.live),
+                None => (None, None),
+            };
+            let result = match live {
+                Some((mut decoder, fed)) if fed <= samples.len() => {
+                    decoder.accept(&samples[fed..]);
+                    decoder.finish()
+                }
+                _ => self.transcribe(&samples),
+            };
+            let event = match result {
+                Ok((text, mut meta)) => {
+                    meta.partial = stats;
+                    SpeechEvent::Transcript { text, meta }
+                }
+                Err(e) => SpeechEvent::Error(e),
+            };
+            self.send(&event)?;
+        }
+        self.start_partials();
+        Ok(())
+    }
+
+    fn transcribe(&self, samples: &[f32]) -> Result<(String, TranscriptMeta), String> {
+        let opts = DecodeOptions {
+            language: self.language.clone(),
+            ..DecodeOptions::default()
+        };
+        self.engine.transcribe(&self.final_model, samples, &opts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_api;
+    use crate::tests::mock_service;
+    use serde_json::json;
+    use std::net::SocketAddr;
+
+    /// Sessions count against one process-wide limit, so tests take turns.
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// Wait for this test's turn and for the sessions of the last one to end.
+    fn turn() -> parking_lot::MutexGuard<'static, ()> {
+        let guard = SERIAL.lock();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while SESSIONS.load(Ordering::SeqCst) > 0 {
+            assert!(std::time::Instant::now() < deadline, "sessions of an earlier test still running");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        guard
+    }
+
+    fn server() -> SocketAddr {
+        let (speech_tx, config) = mock_service(&["Hello there, how are you?", "Second."]);
+        http_api::start("127.0.0.1:0".parse().unwrap(), speech_tx, config).unwrap()
+    }
+
+    fn connect(addr: SocketAddr) -> Result<WebSocket<TcpStream>, Box<tungstenite::Error>> {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        match tungstenite::client(format!("ws://{addr}{STREAM}"), stream) {
+            Ok((ws, _)) => Ok(ws),
+            Err(tungstenite::HandshakeError::Failure(e)) => Err(Box::new(e)),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// `seconds` of a 16 kHz s16le tone.
+    fn pcm(seconds: f32) -> Vec<u8> {
+        (0..(16_000.0 * seconds) as usize)
+            .flat_map(|i| (((i as f32 * 0.05).sin() * 8_000.0) as i16).to_le_bytes())
+            .collect()
+    }
+
+    /// The next event of type `kind`, skipping others.
+    fn event(ws: &mut WebSocket<TcpStream>, kind: &str) -> Value {
+        loop {
+            if let Message::Text(text) = ws.read().unwrap() {
+                let event: Value = serde_json::from_str(&text).unwrap();
+                if event["event"] == kind {
+                    return event;
+                }
+            }
+        }
+    }
+
+    fn send_json(ws: &mut WebSocket<TcpStream>, message: Value) {
+        ws.send(Message::Text(message.to_string())).unwrap();
+    }
+
+    #[test]
+    fn partials_arrive_while_the_client_is_quiet_and_flush_ends_the_utterance() {
+        let _turn = turn();
+        let mut ws = connect(server()).unwrap();
+        ws.send(Message::Binary(pcm(2.0))).unwrap();
+        // Nothing more is sent until the partial is in.
+        let partial = event(&mut ws, "partial");
+        let text = partial["text"].as_str().unwrap();
+        assert!(!text.is_empty() && "Hello there, how are you?".starts_with(text), "{partial}");
+
+        send_json(&mut ws, json!({ "type": "flush" }));
+        let transcript = event(&mut ws, "transcript");
+        assert_eq!(transcript["text"], "Hello there, how are you?");
+        assert_eq!(transcript["duration_ms"], 2000);
+
+        ws.send(Message::Binary(pcm(0.5))).unwrap();
+        send_json(&mut ws, json!({ "type": "flush" }));
+        assert_eq!(event(&mut ws, "transcript")["text"], "Second.");
+    }
+
+    #[test]
+    fn stop_sends_the_transcript_and_closes() {
+        let _turn = turn();
+        let mut ws = connect(server()).unwrap();
+        ws.send(Message::Binary(pcm(0.5))).unwrap();
+        send_json(&mut ws, json!({ "type": "stop" }));
+        assert_eq!(event(&mut ws, "transcript")["text"], "Hello there, how are you?");
+        loop {
+            match ws.read() {
+                Ok(Message::Close(_)) => {}
+                Ok(other) => panic!("unexpected {other:?}"),
+                Err(tungstenite::Error::ConnectionClosed) => break,
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_messages_get_error_events() {
+        let _turn = turn();
+        let mut ws = connect(server()).unwrap();
+        ws.send(Message::Binary(vec![1, 2, 3])).unwrap();
+        assert_eq!(event(&mut ws, "error")["message"], "PCM messages must hold whole 16-bit samples");
+        send_json(&mut ws, json!({ "type": "pause" }));
+        assert_eq!(event(&mut ws, "error")["message"], r#"unknown message {"type":"pause"}"#);
+        // The session carries on.
+        ws.send(Message::Binary(pcm(0.5))).unwrap();
+        send_json(&mut ws, json!({ "type": "flush" }));
+        assert_eq!(event(&mut ws, "transcript")["text"], "Hello there, how are you?");
+    }
+
+    #[test]
+    fn sessions_are_limited() {
+        let _turn = turn();
+        let addr = server();
+        let mut sessions: Vec<_> = (0..MAX_SESSIONS).map(|_| connect(addr).unwrap()).collect();
+        match connect(addr).map_err(|e| *e) {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status().as_u16(), 503),
+            other => panic!("expected a 503, got {:?}", other.map(|_| ())),
+        }
+        // A place frees up when a session ends.
+        sessions.pop().unwrap().close(None).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while connect(addr).is_err() {
+            assert!(std::time::Instant::now() < deadline, "no place came free");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
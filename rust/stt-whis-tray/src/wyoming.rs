@@ -0,0 +1,449 @@
+// ----- Wyoming protocol server (`serve --wyoming`) -----
+//
+// Speech-to-text for Home Assistant's Assist pipeline over Wyoming: each
+// event is a JSON header line, then `data_length` bytes of JSON data and
+// `payload_length` bytes of payload. A client may send `describe` (answered
+// with `info`), then `transcribe` (model name and language), `audio-start`,
+// `audio-chunk`s of raw PCM and `audio-stop`, which is answered with
+// `transcript`. Audio is decoded on the connection's thread with the speech
+// runtime's engine, like the HTTP API. No authentication; one thread per
+// connection.
+
+use crate::engine::{DecodeOptions, EngineConfig};
+use crate::{
+    max_samples, model_name, network_model_path, resample_to_16k, shared_engine, SharedConfig, SpeechCommand,
+    MAX_SAMPLES,
+};
+use log::{debug, info, warn};
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+const PROTOCOL_VERSION: &str = "1.5.0";
+/// Longest header line or data block accepted.
+const MAX_JSON: usize = 64 * 1024;
+/// Largest single payload (about 10 s of 48 kHz stereo 32-bit audio).
+const MAX_PAYLOAD: usize = 4 * 1024 * 1024;
+
+/// Serve on `bind`; the address actually bound (for port 0).
+pub fn start(bind: SocketAddr, speech_tx: Sender<SpeechCommand>, config: SharedConfig) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(bind).map_err(|e| format!("{bind}: {e}"))?;
+    let bind = listener.local_addr().unwrap_or(bind);
+    info!("Wyoming server listening on tcp://{bind}");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Wyoming accept failed: {e}");
+                    continue;
+                }
+            };
+            let speech_tx = speech_tx.clone();
+            let config = config.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                debug!("Wyoming client {peer} connected");
+                match serve(stream, &speech_tx, &config) {
+                    Ok(()) => debug!("Wyoming client {peer} disconnected"),
+                    Err(e) => warn!("Wyoming client {peer}: {e}"),
+                }
+            });
+        }
+    });
+    Ok(bind)
+}
+
+struct Event {
+    kind: String,
+    data: Map<String, Value>,
+    payload: Vec<u8>,
+}
+
+impl Event {
+    fn str(&self, key: &str) -> Option<&str> {
+        self.data.get(key).and_then(Value::as_str).filter(|s| !s.is_empty())
+    }
+
+    fn u32(&self, key: &str) -> Option<u32> {
+        self.data.get(key).and_then(Value::as_u64).and_then(|n| u32::try_from(n).ok())
+    }
+}
+
+/// The next event, or `None` when the client closed the connection.
+fn read_event(reader: &mut impl BufRead) -> Result<Option<Event>, String> {
+    let mut line = Vec::new();
+    let n = reader
+        .by_ref()
+        .take(MAX_JSON as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| format!("read: {e}"))?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if line.len() > MAX_JSON {
+        return Err("header line too long".into());
+    }
+    let header: Value = serde_json::from_slice(&line).map_err(|e| format!("bad header: {e}"))?;
+    let kind = header
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or("header without `type`")?
+        .to_string();
+    let length = |key: &str| header.get(key).and_then(Value::as_u64).unwrap_or(0) as usize;
+    let (data_length, payload_length) = (length("data_length"), length("payload_length"));
+    if data_length > MAX_JSON || payload_length > MAX_PAYLOAD {
+        return Err(format!("`{kind}` is too large"));
+    }
+    // Older clients put `data` in the header; newer ones send it after it.
+    let mut data = match header.get("data") {
+        Some(Value::Object(map)) => map.clone(),
+        _ => Map::new(),
+    };
+    if data_length > 0 {
+        let mut buf = vec![0; data_length];
+        reader.read_exact(&mut buf).map_err(|e| format!("read: {e}"))?;
+        match serde_json::from_slice(&buf) {
+            Ok(Value::Object(map)) => data.extend(map),
+            _ => return Err(format!("bad data in `{kind}`")),
+        }
+    }
+    let mut payload = vec![0; payload_length];
+    reader.read_exact(&mut payload).map_err(|e| format!("read: {e}"))?;
+    Ok(Some(Event { kind, data, payload }))
+}
+
+fn write_event(writer: &mut impl Write, kind: &str, data: Value) -> Result<(), String> {
+    let header = json!({ "type": kind, "version": PROTOCOL_VERSION, "data": data });
+    writeln!(writer, "{header}")
+        .and_then(|_| writer.flush())
+        .map_err(|e| format!("write: {e}"))
+}
+
+/// PCM format of the current audio stream.
+#[derive(Clone, Copy)]
+struct AudioFormat {
+    rate: u32,
+    width: u32,
+    channels: u32,
+}
+
+impl Default for AudioFormat {
+    /// What Home Assistant sends.
+    fn default() -> Self {
+        Self {
+            rate: 16_000,
+            width: 2,
+            channels: 1,
+        }
+    }
+}
+
+impl AudioFormat {
+    /// `rate`, `width` and `channels` from an audio event, defaulting to `self`'s.
+    fn update(self, event: &Event) -> Result<Self, String> {
+        let format = Self {
+            rate: event.u32("rate").unwrap_or(self.rate),
+            width: event.u32("width").unwrap_or(self.width),
+            channels: event.u32("channels").unwrap_or(self.channels),
+        };
+        if format.rate == 0 || format.channels == 0 || !matches!(format.width, 1 | 2 | 4) {
+            return Err(format!(
+                "unsupported audio: {} Hz, {} bytes per sample, {} channels",
+                format.rate, format.width, format.channels
+            ));
+        }
+        Ok(format)
+    }
+
+    /// Little-endian PCM (8-bit unsigned, 16- or 32-bit signed) mixed down to mono.
+    fn decode(&self, pcm: &[u8], out: &mut Vec<f32>) {
+        let width = self.width as usize;
+        let frame = width * self.channels as usize;
+        for frame in pcm.chunks_exact(frame) {
+            let sum: f32 = frame
+                .chunks_exact(width)
+                .map(|s| match s {
+                    [b] => (*b as f32 - 128.0) / 128.0,
+                    [a, b] => i16::from_le_bytes([*a, *b]) as f32 / 32_768.0,
+                    [a, b, c, d] => i32::from_le_bytes([*a, *b, *c, *d]) as f32 / 2_147_483_648.0,
+                    _ => 0.0,
+                })
+                .sum();
+            out.push(sum / self.channels as f32);
+        }
+    }
+}
+
+/// Per-connection state.
+#[derive(Default)]
+struct Session {
+    /// From `transcribe`; the config's when unset.
+    model: Option<String>,
+    language: Option<String>,
+    format: AudioFormat,
+    samples: Vec<f32>,
+    /// Audio past the length limit was dropped.
+    truncated: bool,
+}
+
+fn serve(stream: TcpStream, speech_tx: &Sender<SpeechCommand>, config: &SharedConfig) -> Result<(), String> {
+    let mut writer = stream.try_clone().map_err(|e| format!("socket: {e}"))?;
+    let mut reader = BufReader::new(stream);
+    let mut session = Session::default();
+    while let Some(event) = read_event(&mut reader)? {
+        match event.kind.as_str() {
+            "describe" => write_event(&mut writer, "info", describe(config))?,
+            "transcribe" => {
+                session.model = event.str("name").map(str::to_string);
+                session.language = event.str("language").map(str::to_string);
+            }
+            "audio-start" => {
+                session.format = session.format.update(&event)?;
+                session.samples.clear();
+                session.truncated = false;
+            }
+            "audio-chunk" => {
+                session.format = session.format.update(&event)?;
+                let max = max_samples(session.format.rate);
+                if session.samples.len() < max {
+                    session.format.decode(&event.payload, &mut session.samples);
+                    session.samples.truncate(max);
+                } else if !session.truncated {
+                    warn!("Wyoming audio longer than {} s; ignoring the rest", MAX_SAMPLES / 16_000);
+                    session.truncated = true;
+                }
+            }
+            "audio-stop" => {
+                let samples = std::mem::take(&mut session.samples);
+                match transcribe(&session, samples, speech_tx, config) {
+                    Ok(text) => write_event(&mut writer, "transcript", json!({ "text": text }))?,
+                    Err(e) => {
+                        warn!("Wyoming transcription failed: {e}");
+                        let data = json!({ "text": e, "code": "transcribe-failed" });
+                        write_event(&mut writer, "error", data)?;
+                    }
+                }
+            }
+            other => debug!("Wyoming: ignoring `{other}`"),
+        }
+    }
+    Ok(())
+}
+
+fn transcribe(
+    session: &Session,
+    samples: Vec<f32>,
+    speech_tx: &Sender<SpeechCommand>,
+    config: &SharedConfig,
+) -> Result<String, String> {
+    let cfg = config.get();
+    // Unknown names (e.g. one Home Assistant remembered) get the configured model.
+    let model_path = network_model_path(session.model.as_deref(), &cfg);
+    let opts = DecodeOptions {
+        language: session.language.clone().or_else(|| cfg.language.clone()),
+        ..DecodeOptions::default()
+    };
+    let engine = shared_engine(speech_tx, &cfg.engine).ok_or("speech runtime stopped")?;
+    engine
+        .transcribe(&model_path, &resample_to_16k(&samples, session.format.rate), &opts)
+        .map(|(text, _)| text)
+}
+
+/// `info` data: one ASR program offering the configured final model.
+fn describe(config: &SharedConfig) -> Value {
+    let cfg = config.get();
+    let model = cfg.final_model_file();
+    let attribution = attribution(&cfg.engine);
+    let version = env!("CARGO_PKG_VERSION");
+    json!({
+        "asr": [{
+            "name": env!("CARGO_PKG_NAME"),
+            "description": "Local speech-to-text from the WhisTray dictation app",
+            "attribution": attribution,
+            "installed": true,
+            "version": version,
+            "models": [{
+                "name": model_name(&model),
+                "description": model_name(&model),
+                "attribution": attribution,
+                "installed": true,
+                "version": null,
+                "languages": cfg.engine.languages(&model),
+            }],
+            "supports_transcript_streaming": false,
+        }],
+    })
+}
+
+fn attribution(engine: &EngineConfig) -> Value {
+    let (name, url) = match engine {
+        EngineConfig::Whisper => ("whisper.cpp", "https://github.com/ggerganov/whisper.cpp"),
+        EngineConfig::Mock(_) => ("mock", ""),
+        #[cfg(feature = "vosk")]
+        EngineConfig::Vosk => ("Vosk", "https://alphacephei.com/vosk"),
+    };
+    json!({ "name": name, "url": url })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mock_service;
+    use std::time::Duration;
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn connect() -> Self {
+            let (speech_tx, config) = mock_service(&["One. Two.", "Second."]);
+            let addr = start("127.0.0.1:0".parse().unwrap(), speech_tx, config).unwrap();
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            Self {
+                writer: stream.try_clone().unwrap(),
+                reader: BufReader::new(stream),
+            }
+        }
+
+        /// Send with `data` after the header, as current clients do.
+        fn send(&mut self, kind: &str, data: Value, payload: &[u8]) {
+            let data = data.to_string();
+            let header = json!({ "type": kind, "data_length": data.len(), "payload_length": payload.len() });
+            writeln!(self.writer, "{header}").unwrap();
+            self.writer.write_all(data.as_bytes()).unwrap();
+            self.writer.write_all(payload).unwrap();
+        }
+
+        /// Send with `data` inside the header, as older clients do.
+        fn send_inline(&mut self, kind: &str, data: Value, payload: &[u8]) {
+            let header = json!({ "type": kind, "data": data, "payload_length": payload.len() });
+            writeln!(self.writer, "{header}").unwrap();
+            self.writer.write_all(payload).unwrap();
+        }
+
+        fn recv(&mut self) -> Option<Event> {
+            read_event(&mut self.reader).unwrap()
+        }
+
+        /// True once the server closed the connection without replying. A
+        /// reset counts: the server hangs up on input it did not read.
+        fn closed(&mut self) -> bool {
+            let mut buf = Vec::new();
+            match self.reader.read_to_end(&mut buf) {
+                Ok(_) => buf.is_empty(),
+                Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+            }
+        }
+    }
+
+    /// 16-bit mono PCM: `secs` seconds of a tone at `rate`.
+    fn pcm(rate: u32, secs: f32) -> Vec<u8> {
+        (0..(rate as f32 * secs) as usize)
+            .flat_map(|i| (((i as f32 * 0.05).sin() * 8_000.0) as i16).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn describe_gets_info() {
+        let mut client = Client::connect();
+        client.send("describe", json!({}), b"");
+        let info = client.recv().unwrap();
+        assert_eq!(info.kind, "info");
+        let asr = &info.data["asr"][0];
+        assert_eq!(asr["name"], env!("CARGO_PKG_NAME"));
+        assert_eq!(asr["attribution"]["name"], "mock");
+        assert_eq!(asr["installed"], true);
+        assert_eq!(asr["models"][0]["languages"], json!(["en"]));
+    }
+
+    #[test]
+    fn audio_is_transcribed() {
+        let mut client = Client::connect();
+        client.send("transcribe", json!({ "name": "whisper-1", "language": "en" }), b"");
+        let format = json!({ "rate": 16_000, "width": 2, "channels": 1 });
+        client.send("audio-start", format.clone(), b"");
+        client.send("audio-chunk", format.clone(), &pcm(16_000, 0.5));
+        client.send("audio-chunk", format.clone(), &pcm(16_000, 0.5));
+        client.send("audio-stop", json!({}), b"");
+        let transcript = client.recv().unwrap();
+        assert_eq!(transcript.kind, "transcript");
+        assert_eq!(transcript.data["text"], "One. Two.");
+
+        // Same again with `data` in the header, at another rate.
+        let format = json!({ "rate": 48_000, "width": 2, "channels": 1 });
+        client.send_inline("audio-start", format.clone(), b"");
+        client.send_inline("audio-chunk", format, &pcm(48_000, 1.0));
+        client.send_inline("audio-stop", json!({}), b"");
+        let transcript = client.recv().unwrap();
+        assert_eq!(transcript.kind, "transcript");
+        assert_eq!(transcript.data["text"], "Second.");
+    }
+
+    #[test]
+    fn unknown_events_are_ignored() {
+        let mut client = Client::connect();
+        client.send("ping-pong", json!({ "x": 1 }), b"abc");
+        client.send("describe", json!({}), b"");
+        assert_eq!(client.recv().unwrap().kind, "info");
+    }
+
+    #[test]
+    fn oversized_header_line_closes_the_connection() {
+        let mut client = Client::connect();
+        let header = json!({ "type": "describe", "pad": "x".repeat(MAX_JSON) });
+        // The server may hang up before the whole line is sent.
+        let _ = writeln!(client.writer, "{header}");
+        assert!(client.closed());
+    }
+
+    #[test]
+    fn oversized_data_closes_the_connection() {
+        let mut client = Client::connect();
+        let header = json!({ "type": "transcribe", "data_length": MAX_JSON + 1 });
+        writeln!(client.writer, "{header}").unwrap();
+        assert!(client.closed());
+    }
+
+    #[test]
+    fn oversized_payload_closes_the_connection() {
+        let mut client = Client::connect();
+        let header = json!({ "type": "audio-chunk", "payload_length": MAX_PAYLOAD + 1 });
+        writeln!(client.writer, "{header}").unwrap();
+        assert!(client.closed());
+    }
+
+    #[test]
+    fn unsupported_audio_closes_the_connection() {
+        let mut client = Client::connect();
+        client.send("audio-start", json!({ "rate": 16_000, "width": 3, "channels": 1 }), b"");
+        assert!(client.closed());
+    }
+
+    #[test]
+    fn pcm_is_mixed_down_to_mono() {
+        let mut out = Vec::new();
+        let stereo16 = AudioFormat {
+            rate: 16_000,
+            width: 2,
+            channels: 2,
+        };
+        stereo16.decode(&[0x00, 0x40, 0x00, 0xc0, 0x00, 0x40, 0x00, 0x40], &mut out);
+        assert_eq!(out, [0.0, 0.5]);
+
+        out.clear();
+        let mono8 = AudioFormat { width: 1, channels: 1, ..stereo16 };
+        mono8.decode(&[0, 128, 255], &mut out);
+        assert_eq!(out, [-1.0, 0.0, 127.0 / 128.0]);
+
+        out.clear();
+        let mono32 = AudioFormat { width: 4, channels: 1, ..stereo16 };
+        mono32.decode(&i32::MIN.to_le_bytes(), &mut out);
+        assert_eq!(out, [-1.0]);
+    }
+}
@@ -0,0 +1,227 @@
+"""Writes the audio_file test fixtures into this directory.
+
+    cc opusenc.c -lopus -lm -o opusenc && ./opusenc > opus.bin
+    python3 gen.py opus.bin
+
+The PCM formats and FLAC hold the same 160-sample tone (500 Hz at 8 kHz,
+half scale). MP3 and Vorbis have no encoder here; their streams are built
+by hand from a few spectral lines, so they only pin down what the decoder
+makes of them. Opus packets come from libopus (opusenc.c). adpcm.wav is
+a WAV whose codec is not built in.
+"""
+import math, os, struct, sys
+
+OUT = os.path.dirname(os.path.abspath(__file__))
+R = 8000
+N = 160
+def ref(i): return 0.5 * math.sin(2 * math.pi * i / 16)
+
+def wav(name, fmt_tag, channels, bits, frames_bytes, rate=R):
+    block = channels * bits // 8
+    fmt = struct.pack("<HHIIHH", fmt_tag, channels, rate, rate * block, block, bits)
+    if fmt_tag != 1:
+        fmt += struct.pack("<H", 0)
+    body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt
+    if fmt_tag != 1:
+        body += b"fact" + struct.pack("<II", 4, len(frames_bytes) // block)
+    body += b"data" + struct.pack("<I", len(frames_bytes)) + frames_bytes
+    open(os.path.join(OUT, name), "wb").write(b"RIFF" + struct.pack("<I", len(body)) + body)
+
+def q(v, bits): return round(v * (2 ** (bits - 1) - 1))
+
+# u8 mono
+wav("tone-u8.wav", 1, 1, 8, bytes(q(ref(i), 8) + 128 for i in range(N)))
+# i16 stereo, right channel silent: mono is ref/2
+wav("tone-i16-stereo.wav", 1, 2, 16, b"".join(struct.pack("<hh", q(ref(i), 16), 0) for i in range(N)))
+wav("tone-i24.wav", 1, 1, 24, b"".join(q(ref(i), 24).to_bytes(3, "little", signed=True) for i in range(N)))
+wav("tone-i32.wav", 1, 1, 32, b"".join(struct.pack("<i", q(ref(i), 32)) for i in range(N)))
+wav("tone-f32.wav", 3, 1, 32, b"".join(struct.pack("<f", ref(i)) for i in range(N)))
+
+# IMA ADPCM parses as WAV, but no decoder for it is built in: one block of silence.
+fmt = struct.pack("<HHIIHHHH", 0x11, 1, R, 4055, 256, 4, 2, 505)
+body = b"WAVE" + b"fmt " + struct.pack("<I", len(fmt)) + fmt + b"fact" + struct.pack("<II", 4, 505)
+body += b"data" + struct.pack("<I", 256) + b"\0" * 256
+open(os.path.join(OUT, "adpcm.wav"), "wb").write(b"RIFF" + struct.pack("<I", len(body)) + body)
+
+# G.711
+def alaw(x):
+    x = max(-32768, min(32767, x))
+    sign = 0x80 if x >= 0 else 0
+    x = abs(x) >> 3
+    if x < 32:
+        seg, man = 0, x >> 1
+    else:
+        seg = x.bit_length() - 5
+        man = (x >> seg) & 0x0f
+    return (sign | (seg << 4) | man) ^ 0x55
+def ulaw(x):
+    BIAS = 0x84
+    sign = 0x80 if x < 0 else 0
+    x = min(abs(x), 32635) + BIAS
+    seg = x.bit_length() - 8
+    man = (x >> (seg + 3)) & 0x0f
+    return ~(sign | (seg << 4) | man) & 0xff
+wav("tone-alaw.wav", 6, 1, 8, bytes(alaw(q(ref(i), 16)) for i in range(N)))
+wav("tone-mulaw.wav", 7, 1, 8, bytes(ulaw(q(ref(i), 16)) for i in range(N)))
+
+# FLAC, one verbatim frame of 16-bit mono
+def crc8(b):
+    c = 0
+    for x in b:
+        c ^= x
+        for _ in range(8): c = ((c << 1) ^ 0x07) & 0xff if c & 0x80 else (c << 1) & 0xff
+    return c
+def crc16(b):
+    c = 0
+    for x in b:
+        c ^= x << 8
+        for _ in range(8): c = ((c << 1) ^ 0x8005) & 0xffff if c & 0x8000 else (c << 1) & 0xffff
+    return c
+samples = [q(ref(i), 16) for i in range(N)]
+si = struct.pack(">HH", N, N) + (0).to_bytes(3, "big") + (0).to_bytes(3, "big")
+si += ((R << 44) | (0 << 41) | (15 << 36) | N).to_bytes(8, "big") + b"\0" * 16
+flac = b"fLaC" + bytes([0x80]) + len(si).to_bytes(3, "big") + si
+hdr = bytes([0xFF, 0xF8, (7 << 4) | 0, (0 << 4) | (4 << 1), 0]) + (N - 1).to_bytes(2, "big")
+hdr += bytes([crc8(hdr)])
+frame = hdr + bytes([0x02]) + b"".join(struct.pack(">h", s) for s in samples)
+flac += frame + crc16(frame).to_bytes(2, "big")
+open(os.path.join(OUT, "tone.flac"), "wb").write(flac)
+
+# ----- bit writers -----
+class MsbBits:
+    def __init__(self): self.bits = []
+    def put(self, v, n):
+        for k in range(n - 1, -1, -1): self.bits.append((v >> k) & 1)
+    def bytes(self, size=None):
+        b = self.bits + [0] * (-len(self.bits) % 8)
+        out = bytes(int("".join(map(str, b[i:i + 8])), 2) for i in range(0, len(b), 8))
+        return out if size is None else out + b"\0" * (size - len(out))
+class LsbBits:
+    def __init__(self): self.bits = []
+    def put(self, v, n):
+        for k in range(n): self.bits.append((v >> k) & 1)
+    def bytes(self):
+        b = self.bits + [0] * (-len(self.bits) % 8)
+        return bytes(sum(b[i + k] << k for k in range(8)) for i in range(0, len(b), 8))
+
+# MP3: MPEG-1 Layer III, 32 kHz mono, 32 kbit/s (144-byte frames), long
+# blocks, every granule a few +/-1 lines in the count1 region (table B).
+def mp3_granule(side, main, lines, gain):
+    # lines: list of (index, sign) with index < 16
+    quads = []
+    for g in range(0, 16, 4):
+        v = sum(1 << (3 - (idx - g)) for idx, _ in lines if g <= idx < g + 4)
+        signs = [s for idx, s in sorted(lines) if g <= idx < g + 4]
+        quads.append((v, signs))
+    nbits = 0
+    for v, signs in quads:
+        main.put(15 - v, 4)
+        for s in signs: main.put(s, 1)
+        nbits += 4 + len(signs)
+    side.put(nbits, 12)      # part2_3_length
+    side.put(0, 9)           # big_values
+    side.put(gain, 8)        # global_gain
+    side.put(0, 4)           # scalefac_compress
+    side.put(0, 1)           # window_switching_flag
+    side.put(0, 15)          # table_select x3
+    side.put(0, 4)           # region0_count
+    side.put(0, 3)           # region1_count
+    side.put(0, 1)           # preflag
+    side.put(0, 1)           # scalefac_scale
+    side.put(1, 1)           # count1table_select: table B
+mp3 = b""
+patterns = [[(1, 0), (2, 1)], [(1, 0), (5, 0)], [(2, 0), (3, 1)], [(1, 1), (4, 0)]]
+for f in range(4):
+    side, main = MsbBits(), MsbBits()
+    side.put(0, 9); side.put(0, 5); side.put(0, 4)
+    for g in range(2):
+        mp3_granule(side, main, patterns[(2 * f + g) % 4], 200)
+    frame = bytes([0xFF, 0xFB, 0x18, 0xC0]) + side.bytes() + main.bytes(144 - 4 - 17)
+    assert len(frame) == 144
+    mp3 += frame
+open(os.path.join(OUT, "tone.mp3"), "wb").write(mp3)
+
+# ----- Ogg -----
+def ogg_crc(data):
+    c = 0
+    for b in data:
+        c ^= b << 24
+        for _ in range(8): c = ((c << 1) ^ 0x04C11DB7) & 0xffffffff if c & 0x80000000 else (c << 1) & 0xffffffff
+    return c
+def ogg_page(serial, seq, granule, packets, flags):
+    lacing = b""
+    for p in packets:
+        n = len(p)
+        lacing += b"\xff" * (n // 255) + bytes([n % 255])
+    hdr = b"OggS" + bytes([0, flags]) + struct.pack("<qIII", granule, serial, seq, 0) + bytes([len(lacing)]) + lacing
+    page = hdr + b"".join(packets)
+    crc = ogg_crc(page)
+    return page[:22] + struct.pack("<I", crc) + page[26:]
+def ogg(name, headers, audio, serial):
+    # headers: list of lists of packets (one page each); audio: list of (packet, granule)
+    out, seq = b"", 0
+    for i, pkts in enumerate(headers):
+        out += ogg_page(serial, seq, 0, pkts, 2 if i == 0 else 0); seq += 1
+    for i, (p, g) in enumerate(audio):
+        out += ogg_page(serial, seq, g, [p], 4 if i == len(audio) - 1 else 0); seq += 1
+    open(os.path.join(OUT, name), "wb").write(out)
+
+# Vorbis: 8 kHz mono, 256-sample blocks only. Floor 1 is a flat line,
+# residue type 1 sets the first 32 lines to +/-1 from a two-entry book.
+def vfloat(m, e, neg=False): return (0x80000000 if neg else 0) | (e << 21) | m
+ident = b"\x01vorbis" + struct.pack("<IBIiii", 0, 1, R, 0, 0, 0) + bytes([(8 << 4) | 8, 1])
+vendor = b"stt-whis-tray fixture"
+comment = b"\x03vorbis" + struct.pack("<I", len(vendor)) + vendor + struct.pack("<I", 0) + b"\x01"
+s = LsbBits()
+for ch in b"\x05vorbis": s.put(ch, 8)
+s.put(2 - 1, 8)                                   # codebooks
+for lookup in (0, 1):
+    s.put(0x564342, 24); s.put(1, 16); s.put(2, 24)  # sync, dimensions, entries
+    s.put(0, 1); s.put(0, 1)                      # not ordered, not sparse
+    s.put(0, 5); s.put(0, 5)                      # both lengths 1
+    s.put(lookup, 4)
+    if lookup:
+        s.put(vfloat(1, 788, True), 32)           # minimum -1.0
+        s.put(vfloat(1, 789), 32)                 # delta 2.0
+        s.put(1 - 1, 4); s.put(0, 1)              # value bits, sequence_p
+        s.put(0, 1); s.put(1, 1)                  # multiplicands
+s.put(0, 6); s.put(0, 16)                         # time domain transforms
+s.put(0, 6); s.put(1, 16)                         # one floor, type 1
+s.put(0, 5)                                       # no partitions
+s.put(4 - 1, 2); s.put(7, 4)                      # multiplier 4, rangebits 7
+s.put(0, 6); s.put(1, 16)                         # one residue, type 1
+s.put(0, 24); s.put(32, 24); s.put(16 - 1, 24)    # begin, end, partition size
+s.put(1 - 1, 6); s.put(0, 8)                      # classifications, classbook
+s.put(1, 3); s.put(0, 1)                          # cascade: pass 0 only
+s.put(1, 8)                                       # pass 0 book
+s.put(0, 6); s.put(0, 16)                         # one mapping, type 0
+s.put(0, 1); s.put(0, 1); s.put(0, 2)             # submaps, coupling, reserved
+s.put(0, 8); s.put(0, 8); s.put(0, 8)             # submap: unused, floor, residue
+s.put(0, 6)                                       # one mode
+s.put(0, 1); s.put(0, 16); s.put(0, 16); s.put(0, 8)
+s.put(1, 1)                                       # framing
+setup = s.bytes()
+audio = []
+for k in range(6):
+    a = LsbBits()
+    a.put(0, 1)                                   # audio packet
+    a.put(1, 1); a.put(40 + k, 6); a.put(40 + k, 6)   # floor: nonzero, Y0, Y1
+    for part in range(2):
+        a.put(0, 1)                               # classword
+        for line in range(16):
+            a.put((line * 7 + part * 3 + k) % 3 == 0, 1)
+    audio.append((a.bytes(), 0))
+# Granules: blocks of 256 overlap by half, the first yields nothing.
+audio = [(p, max(0, 128 * i)) for i, (p, _) in enumerate(audio)]
+ogg("tone.ogg", [[ident], [comment, setup]], audio, 0x766f7262)
+
+# Ogg Opus from libopus packets (opusenc.c): 500 Hz at 48 kHz, six 20 ms frames.
+raw = open(sys.argv[1], "rb").read()
+pre_skip = struct.unpack_from("<i", raw)[0]
+packets, off = [], 4
+while off < len(raw):
+    n = struct.unpack_from("<i", raw, off)[0]; off += 4
+    packets.append(raw[off:off + n]); off += n
+head = b"OpusHead" + struct.pack("<BBHIhB", 1, 1, pre_skip, 48000, 0, 0)
+tags = b"OpusTags" + struct.pack("<I", len(vendor)) + vendor + struct.pack("<I", 0)
+ogg("tone.opus", [[head], [tags]], [(p, 960 * (i + 1)) for i, p in enumerate(packets)], 0x6f707573)
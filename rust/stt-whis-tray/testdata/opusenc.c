@@ -0,0 +1,28 @@
+/* Opus packets for tone.opus (see gen.py): 500 Hz at 48 kHz, six 20 ms frames.
+   Writes the encoder lookahead (the pre-skip) and then each packet, all
+   prefixed with their length as little-endian 32-bit integers. */
+#include <opus.h>
+#include <math.h>
+#include <stdio.h>
+#include <stdint.h>
+int main(void) {
+    int err;
+    OpusEncoder *enc = opus_encoder_create(48000, 1, OPUS_APPLICATION_AUDIO, &err);
+    opus_encoder_ctl(enc, OPUS_SET_BITRATE(64000));
+    opus_encoder_ctl(enc, OPUS_SET_COMPLEXITY(10));
+    opus_int32 lookahead;
+    opus_encoder_ctl(enc, OPUS_GET_LOOKAHEAD(&lookahead));
+    fwrite(&lookahead, 4, 1, stdout);
+    float pcm[960];
+    unsigned char out[1500];
+    for (int f = 0; f < 6; f++) {
+        for (int i = 0; i < 960; i++) {
+            int n = f * 960 + i;
+            pcm[i] = 0.5f * sinf(2.0f * 3.14159265f * n / 96.0f);
+        }
+        opus_int32 len = opus_encode_float(enc, pcm, 960, out, sizeof out);
+        fwrite(&len, 4, 1, stdout);
+        fwrite(out, 1, len, stdout);
+    }
+    return 0;
+}
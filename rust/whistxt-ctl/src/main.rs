@@ -0,0 +1,160 @@
+//! `whistxt-ctl`: drive a running stt-whis-tray over its control API.
+//!
+//! Every command prints JSON on stdout (one object per line for
+//! `tail-events`) so it can be piped into `jq` or used from WM keybindings:
+//!
+//!     bindsym --no-repeat $mod+d exec whistxt-ctl start
+//!     bindsym --release   $mod+d exec whistxt-ctl stop
+
+use interprocess::local_socket::{prelude::*, GenericFilePath};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::ExitCode;
+use whistxt_ipc::endpoint;
+
+const EXIT_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_NOT_RUNNING: u8 = 3;
+
+const USAGE: &str = "\
+usage: whistxt-ctl <command> [args]
+
+commands:
+  start                    begin recording
+  stop                     stop recording and transcribe
+  toggle                   start if idle, stop if recording
+  cancel                   stop recording and discard the audio
+  reinject                 type the last transcript again
+  retranscribe             redo the last recording with the re-transcribe model
+  status                   runtime state, model and last transcript
+  config                   print the running config
+  reload                   re-read the config file
+  set model <name|path>    switch model (persisted to config)
+  transcribe-file <path>   transcribe an audio file with the loaded model
+  tail-events              stream every speech event as JSON lines
+
+exit codes: 0 ok, 1 request failed, 2 usage error, 3 tray not running";
+
+enum Failure {
+    Usage(String),
+    NotRunning(io::Error),
+    Io(io::Error),
+    Rpc(Value),
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Usage(msg)) => {
+            eprintln!("{msg}\n\n{USAGE}");
+            ExitCode::from(EXIT_USAGE)
+        }
+        Err(Failure::NotRunning(e)) => {
+            eprintln!("whistxt-ctl: cannot reach tray at {}: {e}", endpoint());
+            ExitCode::from(EXIT_NOT_RUNNING)
+        }
+        Err(Failure::Io(e)) => {
+            eprintln!("whistxt-ctl: {e}");
+            ExitCode::from(EXIT_ERROR)
+        }
+        Err(Failure::Rpc(err)) => {
+            println!("{}", json!({ "error": err }));
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Failure> {
+    let words: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (method, params) = match words.as_slice() {
+        ["start"] => ("start", Value::Null),
+        ["stop"] => ("stop", Value::Null),
+        ["toggle"] => ("toggle", Value::Null),
+        ["cancel"] => ("cancel", Value::Null),
+        ["reinject"] => ("reinject", Value::Null),
+        ["retranscribe"] => ("retranscribe", Value::Null),
+        ["status"] => ("status", Value::Null),
+        ["config"] => ("get_config", Value::Null),
+        ["reload"] => ("reload_config", Value::Null),
+        ["set", "model", name] => ("set_model", json!({ "model": name })),
+        ["transcribe-file", path] => {
+            // The tray may run with a different working directory.
+            let abs = std::fs::canonicalize(path).map_err(Failure::Io)?;
+            ("transcribe_file", json!({ "path": abs }))
+        }
+        ["tail-events"] => return tail_events(),
+        ["-h"] | ["--help"] | ["help"] => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        [] => return Err(Failure::Usage("missing command".into())),
+        other => return Err(Failure::Usage(format!("unknown command: {}", other.join(" ")))),
+    };
+    let result = call(method, params)?;
+    println!("{result}");
+    Ok(())
+}
+
+fn connect() -> Result<BufReader<LocalSocketStream>, Failure> {
+    let path = endpoint();
+    let name = path
+        .as_str()
+        .to_fs_name::<GenericFilePath>()
+        .map_err(Failure::NotRunning)?;
+    LocalSocketStream::connect(name)
+        .map(BufReader::new)
+        .map_err(Failure::NotRunning)
+}
+
+fn send(conn: &mut BufReader<LocalSocketStream>, method: &str, params: Value) -> Result<(), Failure> {
+    let mut req = json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+    if !params.is_null() {
+        req["params"] = params;
+    }
+    let stream = conn.get_mut();
+    stream
+        .write_all(format!("{req}\n").as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(Failure::Io)
+}
+
+fn read_message(conn: &mut BufReader<LocalSocketStream>) -> Result<Value, Failure> {
+    let mut line = String::new();
+    if conn.read_line(&mut line).map_err(Failure::Io)? == 0 {
+        return Err(Failure::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "tray closed the connection",
+        )));
+    }
+    serde_json::from_str(&line).map_err(|e| Failure::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+fn call(method: &str, params: Value) -> Result<Value, Failure> {
+    let mut conn = connect()?;
+    send(&mut conn, method, params)?;
+    let mut resp = read_message(&mut conn)?;
+    if let Some(err) = resp.get_mut("error") {
+        return Err(Failure::Rpc(err.take()));
+    }
+    Ok(resp.get_mut("result").map(Value::take).unwrap_or(Value::Null))
+}
+
+fn tail_events() -> Result<(), Failure> {
+    let mut conn = connect()?;
+    send(&mut conn, "subscribe", Value::Null)?;
+    let ack = read_message(&mut conn)?;
+    if let Some(err) = ack.get("error") {
+        return Err(Failure::Rpc(err.clone()));
+    }
+    let stdout = io::stdout();
+    loop {
+        let msg = read_message(&mut conn)?;
+        let Some(params) = msg.get("params") else { continue };
+        let mut out = stdout.lock();
+        // A closed pipe (`| head`) is a normal way to stop tailing.
+        if writeln!(out, "{params}").and_then(|_| out.flush()).is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Shared by stt-whis-tray and whistxt-ctl: where the control API listens.
+
+/// Path of the control endpoint for the current user: a Unix domain socket
+/// in the runtime dir on Unix, a per-user named pipe on Windows.
+pub fn endpoint() -> String {
+    #[cfg(windows)]
+    {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!(r"\\.\pipe\whistxt-{user}")
+    }
+    #[cfg(unix)]
+    {
+        match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => std::path::Path::new(&dir)
+                .join("whistxt.sock")
+                .to_string_lossy()
+                .into_owned(),
+            None => {
+                let user = std::env::var("USER").unwrap_or_default();
+                format!("/tmp/whistxt-{user}.sock")
+            }
+        }
+    }
+}